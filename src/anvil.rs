@@ -0,0 +1,660 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use fastnbt::{LongArray, Value};
+use flate2::{
+    Compression,
+    read::{GzDecoder, ZlibDecoder},
+    write::ZlibEncoder,
+};
+use mc_schem::{Block, Region, region::BlockEntity, region::WorldSlice};
+
+use crate::*;
+
+///一个区块中要写入的方块和它们的世界坐标
+type ChunkBlocks = Vec<([i32; 3], Block)>;
+
+///region文件中一个扇区的大小
+const SECTOR_SIZE: usize = 4096;
+///一个区块在region文件中最多占用的扇区数，更大的区块保存在单独的c.X.Z.mcc文件中
+const MAX_CHUNK_SECTORS: usize = 255;
+///压缩方式的这一位表示区块保存在c.X.Z.mcc文件中
+const EXTERNAL_FLAG: u8 = 128;
+///1.18的数据版本，从这个版本开始区块使用sections/block_states格式
+const MIN_CHUNK_DATA_VERSION: i32 = 2860;
+
+///## 写入存档
+/// 把region放到存档world中anchor的位置，world是存档文件夹(或者DIM-1这样的维度文件夹)。
+///
+/// 只修改region覆盖到的方块，结构空位保留存档原有的方块。
+/// 区块必须已经生成过，缺少的区块段会被创建。修改过的区块会在游戏中重新计算光照和高度图。
+pub fn write_to_world(world: &str, region: &Region, anchor: [i32; 3]) {
+    let region_dir = Path::new(world).join("region");
+    if !region_dir.is_dir() {
+        error_begin();
+        panic!(
+            "no region folder in {}, is it a world save (or a dimension folder like world/DIM-1)?",
+            world
+        );
+    }
+    //按region文件和区块分组
+    let mut files: BTreeMap<(i32, i32), BTreeMap<(i32, i32), ChunkBlocks>> = BTreeMap::new();
+    let shape = region.shape();
+    for y in 0..shape[1] {
+        for z in 0..shape[2] {
+            for x in 0..shape[0] {
+                let block = region.block_at([x, y, z]).unwrap();
+                if block.is_structure_void() {
+                    continue;
+                }
+                let pos = [anchor[0] + x, anchor[1] + y, anchor[2] + z];
+                let (cx, cz) = (pos[0].div_euclid(16), pos[2].div_euclid(16));
+                files
+                    .entry((cx.div_euclid(32), cz.div_euclid(32)))
+                    .or_default()
+                    .entry((cx, cz))
+                    .or_default()
+                    .push((pos, block.clone()));
+            }
+        }
+    }
+    let block_entities: HashMap<[i32; 3], &BlockEntity> = region
+        .block_entities
+        .iter()
+        .map(|(pos, be)| {
+            (
+                [anchor[0] + pos[0], anchor[1] + pos[1], anchor[2] + pos[2]],
+                be,
+            )
+        })
+        .collect();
+    for ((rx, rz), chunks) in files {
+        let mut file = RegionFile::load(&region_dir, (rx, rz));
+        for ((cx, cz), blocks) in chunks {
+            let index = (cx.rem_euclid(32) + cz.rem_euclid(32) * 32) as usize;
+            let mut chunk = file.read_chunk(index).unwrap_or_else(|| {
+                error_begin();
+                panic!(
+                    "chunk ({},{}) is not generated yet, visit it in game once before writing the circuit",
+                    cx, cz
+                );
+            });
+            edit_chunk(&mut chunk, (cx, cz), &blocks, &block_entities);
+            file.write_chunk(index, &chunk);
+        }
+        file.save();
+        println!("updated {}", file.path.display());
+    }
+    println!(
+        "note: close the world in game before writing, otherwise the game overwrites the changes when it saves"
+    );
+}
+
+///## RegionFile
+/// r.X.Z.mca文件：1024个区块，每个区块保存为压缩方式(1字节)加压缩后的nbt。
+/// 压缩方式带EXTERNAL_FLAG时，压缩后的nbt在同目录的c.X.Z.mcc文件中
+struct RegionFile {
+    path: PathBuf,
+    ///region文件的坐标
+    rx: i32,
+    rz: i32,
+    chunks: Vec<Option<Vec<u8>>>,
+    timestamps: Vec<u32>,
+    ///重新写回region文件的区块之前单独保存的文件，保存后删除
+    stale: Vec<PathBuf>,
+}
+impl RegionFile {
+    ///读取region_dir中的region文件，文件不存在时返回空的region
+    fn load(region_dir: &Path, (rx, rz): (i32, i32)) -> RegionFile {
+        let path = region_dir.join(format!("r.{}.{}.mca", rx, rz));
+        let mut file = RegionFile {
+            path,
+            rx,
+            rz,
+            chunks: vec![None; 1024],
+            timestamps: vec![0; 1024],
+            stale: vec![],
+        };
+        let Ok(data) = fs::read(&file.path) else {
+            return file;
+        };
+        if data.len() < SECTOR_SIZE * 2 {
+            return file;
+        }
+        let be_u32 =
+            |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        for i in 0..1024 {
+            let location = be_u32(i * 4);
+            let offset = (location >> 8) as usize * SECTOR_SIZE;
+            file.timestamps[i] = be_u32(SECTOR_SIZE + i * 4);
+            if location == 0 || offset + 5 > data.len() {
+                continue;
+            }
+            let length = be_u32(offset) as usize;
+            if length == 0 || offset + 4 + length > data.len() {
+                continue;
+            }
+            file.chunks[i] = Some(data[offset + 4..offset + 4 + length].to_vec());
+        }
+        file
+    }
+    ///区块index单独保存时的c.X.Z.mcc文件
+    fn external_path(&self, index: usize) -> PathBuf {
+        let (cx, cz) = (
+            self.rx * 32 + (index % 32) as i32,
+            self.rz * 32 + (index / 32) as i32,
+        );
+        self.path.with_file_name(format!("c.{}.{}.mcc", cx, cz))
+    }
+    ///解压并解析一个区块
+    fn read_chunk(&self, index: usize) -> Option<HashMap<String, Value>> {
+        let payload = self.chunks[index].as_ref()?;
+        let compression = payload[0] & !EXTERNAL_FLAG;
+        let external;
+        let data = if payload[0] & EXTERNAL_FLAG != 0 {
+            let path = self.external_path(index);
+            external = fs::read(&path).unwrap_or_else(|e| {
+                error_begin();
+                panic!("failed to read oversized chunk {}: {}", path.display(), e)
+            });
+            &external[..]
+        } else {
+            &payload[1..]
+        };
+        let mut raw = vec![];
+        let result = match compression {
+            1 => GzDecoder::new(data)
+                .read_to_end(&mut raw)
+                .map_err(|e| e.to_string()),
+            2 => ZlibDecoder::new(data)
+                .read_to_end(&mut raw)
+                .map_err(|e| e.to_string()),
+            3 => {
+                raw = data.to_vec();
+                Ok(raw.len())
+            }
+            4 => lz4_block_stream(data).map(|data| {
+                raw = data;
+                raw.len()
+            }),
+            _ => {
+                error_begin();
+                panic!(
+                    "chunk {} uses compression type {}, only gzip, zlib, uncompressed and LZ4 chunks are supported",
+                    index, compression
+                );
+            }
+        };
+        result.unwrap_or_else(|e| {
+            error_begin();
+            panic!("failed to decompress chunk {}: {}", index, e)
+        });
+        Some(fastnbt::from_bytes(&raw).unwrap_or_else(|x| {
+            error_begin();
+            panic!("failed to parse chunk {}: {}", index, x)
+        }))
+    }
+    ///以zlib压缩写回区块
+    fn write_chunk(&mut self, index: usize, chunk: &HashMap<String, Value>) {
+        let raw = fastnbt::to_bytes(chunk).expect("failed to serialize chunk");
+        let mut encoder = ZlibEncoder::new(vec![2u8], Compression::default());
+        encoder.write_all(&raw).unwrap();
+        let mut payload = encoder.finish().unwrap();
+        //放不进region文件的区块单独保存，否则删掉之前单独保存的文件
+        let external = self.external_path(index);
+        if (payload.len() + 4).div_ceil(SECTOR_SIZE) > MAX_CHUNK_SECTORS {
+            fs::write(&external, &payload[1..]).unwrap_or_else(|e| {
+                error_begin();
+                panic!("failed to write oversized chunk {}: {}", external.display(), e)
+            });
+            payload = vec![2 | EXTERNAL_FLAG];
+        } else if external.exists() {
+            self.stale.push(external);
+        }
+        self.chunks[index] = Some(payload);
+        self.timestamps[index] = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+    }
+    ///重新排列所有区块并保存
+    fn save(&self) {
+        let mut header = vec![0u8; SECTOR_SIZE * 2];
+        let mut body = vec![];
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            header[SECTOR_SIZE + i * 4..SECTOR_SIZE + i * 4 + 4]
+                .copy_from_slice(&self.timestamps[i].to_be_bytes());
+            let Some(chunk) = chunk else {
+                continue;
+            };
+            let sector = 2 + body.len() / SECTOR_SIZE;
+            let sectors = (chunk.len() + 4).div_ceil(SECTOR_SIZE);
+            let location = ((sector as u32) << 8) | sectors as u32;
+            header[i * 4..i * 4 + 4].copy_from_slice(&location.to_be_bytes());
+            body.extend((chunk.len() as u32).to_be_bytes());
+            body.extend(chunk);
+            body.resize(sectors * SECTOR_SIZE + (sector - 2) * SECTOR_SIZE, 0);
+        }
+        header.extend(body);
+        fs::write(&self.path, header).unwrap_or_else(|_| {
+            error_begin();
+            panic!("failed to write region file {}", self.path.display())
+        });
+        for path in self.stale.iter() {
+            fs::remove_file(path).unwrap_or_else(|e| {
+                error_begin();
+                panic!("failed to remove {}: {}", path.display(), e)
+            });
+        }
+    }
+}
+
+///## LZ4区块
+/// 解压1.20.5起可选的LZ4压缩，格式是Java的LZ4BlockOutputStream：
+/// 若干个块，每块是"LZ4Block"、方式(1字节)、压缩后长度、原长度、校验和(都是4字节小端)和数据，
+/// 原长度为0的块表示结束。校验和不检查
+fn lz4_block_stream(mut data: &[u8]) -> Result<Vec<u8>, String> {
+    const HEADER: usize = 21;
+    let mut out = vec![];
+    while !data.is_empty() {
+        if data.len() < HEADER || &data[..8] != b"LZ4Block" {
+            return Err("bad LZ4 block header".to_string());
+        }
+        let le_u32 = |at: usize| {
+            u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize
+        };
+        let (method, compressed, length) = (data[8] & 0xf0, le_u32(9), le_u32(13));
+        let Some(block) = data.get(HEADER..HEADER + compressed) else {
+            return Err("LZ4 block is truncated".to_string());
+        };
+        if length == 0 {
+            break;
+        }
+        match method {
+            0x10 => out.extend_from_slice(block),
+            0x20 => lz4_block(block, length, &mut out)?,
+            _ => return Err(format!("unknown LZ4 block method {:#x}", method)),
+        }
+        data = &data[HEADER + compressed..];
+    }
+    Ok(out)
+}
+
+///解压一个LZ4块，接在out后面。每个序列是字面量加上对已解压数据的复制
+fn lz4_block(src: &[u8], length: usize, out: &mut Vec<u8>) -> Result<(), String> {
+    let start = out.len();
+    let truncated = || "LZ4 data is truncated".to_string();
+    let mut i = 0;
+    //长度字段为15时后面的字节继续累加，直到遇到不是255的字节
+    let read_length = |i: &mut usize, mut len: usize| -> Result<usize, String> {
+        if len == 15 {
+            loop {
+                let byte = *src.get(*i).ok_or_else(truncated)?;
+                *i += 1;
+                len += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(len)
+    };
+    while i < src.len() {
+        let token = src[i];
+        i += 1;
+        let literals = read_length(&mut i, (token >> 4) as usize)?;
+        out.extend_from_slice(src.get(i..i + literals).ok_or_else(truncated)?);
+        i += literals;
+        //最后一个序列只有字面量
+        if i == src.len() {
+            break;
+        }
+        let offset = u16::from_le_bytes([src[i], *src.get(i + 1).ok_or_else(truncated)?]) as usize;
+        i += 2;
+        let matched = read_length(&mut i, (token & 15) as usize)? + 4;
+        if offset == 0 || offset > out.len() - start {
+            return Err(format!("LZ4 match offset {} is out of range", offset));
+        }
+        for _ in 0..matched {
+            out.push(out[out.len() - offset]);
+        }
+    }
+    if out.len() - start != length {
+        return Err(format!(
+            "LZ4 block decompressed to {} bytes instead of {}",
+            out.len() - start,
+            length
+        ));
+    }
+    Ok(())
+}
+
+///## 修改区块
+/// 把blocks写入区块的各个区块段，替换掉这些位置原有的方块实体
+fn edit_chunk(
+    chunk: &mut HashMap<String, Value>,
+    (cx, cz): (i32, i32),
+    blocks: &[([i32; 3], Block)],
+    block_entities: &HashMap<[i32; 3], &BlockEntity>,
+) {
+    let data_version = match chunk.get("DataVersion") {
+        Some(Value::Int(v)) => *v,
+        _ => 0,
+    };
+    if data_version < MIN_CHUNK_DATA_VERSION || !chunk.contains_key("sections") {
+        error_begin();
+        panic!(
+            "chunk ({},{}) has data version {}, only worlds saved by 1.18 or later are supported",
+            cx, cz, data_version
+        );
+    }
+    let min_section = match chunk.get("yPos") {
+        Some(Value::Int(y)) => *y,
+        _ => -4,
+    };
+    let Some(Value::List(sections)) = chunk.get_mut("sections") else {
+        unreachable!()
+    };
+    //按区块段分组
+    let mut by_section: BTreeMap<i32, Vec<&([i32; 3], Block)>> = BTreeMap::new();
+    for entry in blocks {
+        by_section
+            .entry(entry.0[1].div_euclid(16))
+            .or_default()
+            .push(entry);
+    }
+    for (section_y, section_blocks) in by_section {
+        if section_y < min_section {
+            error_begin();
+            panic!(
+                "y={} is below the bottom of the world",
+                section_blocks[0].0[1]
+            );
+        }
+        let index = match sections
+            .iter()
+            .position(|s| section_y_of(s) == Some(section_y))
+        {
+            Some(index) => index,
+            None => {
+                sections.push(new_section(section_y, sections));
+                sections.len() - 1
+            }
+        };
+        let Value::Compound(section) = &mut sections[index] else {
+            unreachable!()
+        };
+        let mut states = decode_block_states(section);
+        for (pos, block) in section_blocks {
+            let (x, y, z) = (
+                pos[0].rem_euclid(16),
+                pos[1].rem_euclid(16),
+                pos[2].rem_euclid(16),
+            );
+            states[((y * 16 + z) * 16 + x) as usize] = block.clone();
+        }
+        section.insert("block_states".to_string(), encode_block_states(&states));
+        //光照由游戏重新计算
+        section.remove("BlockLight");
+        section.remove("SkyLight");
+    }
+    sections.sort_by_key(|s| section_y_of(s).unwrap_or(i32::MIN));
+    //被覆盖位置原有的方块实体删除，再加入电路中的方块实体
+    if let Some(Value::List(entities)) = chunk.get_mut("block_entities") {
+        entities.retain(|e| {
+            let Value::Compound(e) = e else {
+                return true;
+            };
+            let coordinate = |key: &str| match e.get(key) {
+                Some(Value::Int(v)) => *v,
+                _ => i32::MIN,
+            };
+            let pos = [coordinate("x"), coordinate("y"), coordinate("z")];
+            !blocks.iter().any(|(p, _)| *p == pos)
+        });
+    } else {
+        chunk.insert("block_entities".to_string(), Value::List(vec![]));
+    }
+    let Some(Value::List(entities)) = chunk.get_mut("block_entities") else {
+        unreachable!()
+    };
+    for (pos, _) in blocks {
+        if let Some(be) = block_entities.get(pos) {
+            let mut tags = be.tags.clone();
+            tags.insert("x".to_string(), Value::Int(pos[0]));
+            tags.insert("y".to_string(), Value::Int(pos[1]));
+            tags.insert("z".to_string(), Value::Int(pos[2]));
+            tags.insert("keepPacked".to_string(), Value::Byte(0));
+            entities.push(Value::Compound(tags));
+        }
+    }
+    chunk.remove("Heightmaps");
+    chunk.insert("isLightOn".to_string(), Value::Byte(0));
+}
+
+///区块段的Y坐标
+fn section_y_of(section: &Value) -> Option<i32> {
+    let Value::Compound(section) = section else {
+        return None;
+    };
+    match section.get("Y") {
+        Some(Value::Byte(y)) => Some(*y as i32),
+        Some(Value::Int(y)) => Some(*y),
+        _ => None,
+    }
+}
+
+///全是空气的区块段，生物群系沿用最近的区块段
+fn new_section(section_y: i32, sections: &[Value]) -> Value {
+    let biomes = sections
+        .iter()
+        .filter_map(|s| Some((section_y_of(s)?, s)))
+        .min_by_key(|(y, _)| (y - section_y).abs())
+        .and_then(|(_, s)| match s {
+            Value::Compound(s) => s.get("biomes").cloned(),
+            _ => None,
+        })
+        .unwrap_or_else(|| {
+            let mut biomes = HashMap::new();
+            biomes.insert(
+                "palette".to_string(),
+                Value::List(vec![Value::String("minecraft:plains".to_string())]),
+            );
+            Value::Compound(biomes)
+        });
+    let mut section = HashMap::new();
+    section.insert("Y".to_string(), Value::Byte(section_y as i8));
+    section.insert("biomes".to_string(), biomes);
+    section.insert(
+        "block_states".to_string(),
+        encode_block_states(&vec![Block::air(); 4096]),
+    );
+    Value::Compound(section)
+}
+
+///每个索引占用的位数，至少4位
+fn bits_per_entry(palette_len: usize) -> usize {
+    let bits = usize::BITS - (palette_len.max(2) - 1).leading_zeros();
+    (bits as usize).max(4)
+}
+
+///解码区块段的4096个方块，按y、z、x排列
+fn decode_block_states(section: &HashMap<String, Value>) -> Vec<Block> {
+    let Some(Value::Compound(states)) = section.get("block_states") else {
+        return vec![Block::air(); 4096];
+    };
+    let palette: Vec<Block> = match states.get("palette") {
+        Some(Value::List(palette)) => palette.iter().map(block_from_nbt).collect(),
+        _ => vec![Block::air()],
+    };
+    let Some(Value::LongArray(data)) = states.get("data") else {
+        return vec![palette[0].clone(); 4096];
+    };
+    let bits = bits_per_entry(palette.len());
+    let per_long = 64 / bits;
+    let mask = (1u64 << bits) - 1;
+    (0..4096)
+        .map(|i| {
+            let long = data[i / per_long] as u64;
+            let index = ((long >> ((i % per_long) * bits)) & mask) as usize;
+            palette.get(index).cloned().unwrap_or_else(Block::air)
+        })
+        .collect()
+}
+
+///编码区块段，调色板只保留用到的方块
+fn encode_block_states(states: &[Block]) -> Value {
+    let mut palette: Vec<&Block> = vec![];
+    let indices: Vec<usize> = states
+        .iter()
+        .map(|b| match palette.iter().position(|p| *p == b) {
+            Some(i) => i,
+            None => {
+                palette.push(b);
+                palette.len() - 1
+            }
+        })
+        .collect();
+    let mut result = HashMap::new();
+    result.insert(
+        "palette".to_string(),
+        Value::List(palette.iter().map(|b| block_to_nbt(b)).collect()),
+    );
+    if palette.len() > 1 {
+        let bits = bits_per_entry(palette.len());
+        let per_long = 64 / bits;
+        let mut data = vec![0i64; 4096usize.div_ceil(per_long)];
+        for (i, index) in indices.iter().enumerate() {
+            data[i / per_long] |= (*index as i64) << ((i % per_long) * bits);
+        }
+        result.insert("data".to_string(), Value::LongArray(LongArray::new(data)));
+    }
+    Value::Compound(result)
+}
+
+///调色板中的一项转成方块
+fn block_from_nbt(value: &Value) -> Block {
+    let Value::Compound(value) = value else {
+        return Block::air();
+    };
+    let name = match value.get("Name") {
+        Some(Value::String(name)) => name.as_str(),
+        _ => "minecraft:air",
+    };
+    let (namespace, id) = name.split_once(':').unwrap_or(("minecraft", name));
+    let mut attributes = BTreeMap::new();
+    if let Some(Value::Compound(properties)) = value.get("Properties") {
+        for (k, v) in properties {
+            if let Value::String(v) = v {
+                attributes.insert(k.clone(), v.clone());
+            }
+        }
+    }
+    Block {
+        namespace: namespace.to_string(),
+        id: id.to_string(),
+        attributes,
+    }
+}
+
+///方块转成调色板中的一项
+fn block_to_nbt(block: &Block) -> Value {
+    let namespace = if block.namespace.is_empty() {
+        "minecraft"
+    } else {
+        block.namespace.as_str()
+    };
+    let mut value = HashMap::new();
+    value.insert(
+        "Name".to_string(),
+        Value::String(format!("{}:{}", namespace, block.id)),
+    );
+    if !block.attributes.is_empty() {
+        value.insert(
+            "Properties".to_string(),
+            Value::Compound(
+                block
+                    .attributes
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                    .collect(),
+            ),
+        );
+    }
+    Value::Compound(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Java的LZ4BlockOutputStream的一个块，校验和填0
+    fn lz4_block_header(method: u8, compressed: &[u8], length: u32) -> Vec<u8> {
+        let mut data = b"LZ4Block".to_vec();
+        data.push(method);
+        data.extend((compressed.len() as u32).to_le_bytes());
+        data.extend(length.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(compressed);
+        data
+    }
+
+    #[test]
+    fn lz4_sequences() {
+        //字面量abc，复制偏移3长度9；字面量长度15+2的x，复制偏移1长度15+1+4
+        let mut compressed = vec![0x35, b'a', b'b', b'c', 3, 0, 0xff, 2];
+        compressed.extend([b'x'; 17]);
+        compressed.extend([1, 0, 1]);
+        compressed.extend([0x20, b'y', b'z']);
+        let mut data = lz4_block_header(0x20, &compressed, 12 + 17 + 20 + 2);
+        data.extend(lz4_block_header(0x10, b"raw", 3));
+        data.extend(lz4_block_header(0x10, b"", 0));
+        let expected = "abc".repeat(4) + &"x".repeat(37) + "yzraw";
+        assert_eq!(lz4_block_stream(&data).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn lz4_rejects_bad_offsets() {
+        let data = lz4_block_header(0x20, &[0x10, b'a', 5, 0], 6);
+        assert!(lz4_block_stream(&data).is_err());
+        assert!(lz4_block_stream(b"LZ4Blo").is_err());
+    }
+
+    #[test]
+    fn oversized_chunks_use_mcc_files() {
+        let dir = std::env::temp_dir().join("mc_circuit_script_anvil_mcc");
+        fs::create_dir_all(&dir).unwrap();
+        let mut chunk = HashMap::new();
+        chunk.insert("DataVersion".to_string(), Value::Int(3700));
+        //随机的字节压缩不了，超过255个扇区
+        let mut seed = 1u32;
+        let noise: Vec<i8> = (0..SECTOR_SIZE * 300)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as i8
+            })
+            .collect();
+        chunk.insert("noise".to_string(), Value::ByteArray(fastnbt::ByteArray::new(noise)));
+        let mut file = RegionFile::load(&dir, (-1, 0));
+        //区块(-31,1)在region(-1,0)中的下标是1+1*32
+        file.write_chunk(33, &chunk);
+        file.save();
+        let mcc = dir.join("c.-31.1.mcc");
+        assert!(mcc.exists());
+        let file = RegionFile::load(&dir, (-1, 0));
+        assert_eq!(file.chunks[33].as_deref(), Some(&[2 | EXTERNAL_FLAG][..]));
+        assert_eq!(file.read_chunk(33).unwrap()["noise"], chunk["noise"]);
+        //变小之后写回region文件，删掉mcc文件
+        let mut file = RegionFile::load(&dir, (-1, 0));
+        chunk.remove("noise");
+        file.write_chunk(33, &chunk);
+        file.save();
+        assert!(!mcc.exists());
+        let file = RegionFile::load(&dir, (-1, 0));
+        assert_eq!(file.read_chunk(33).unwrap()["DataVersion"], Value::Int(3700));
+    }
+}
@@ -1,3 +1,5 @@
+use mc_schem::region::WorldSlice;
+
 use crate::*;
 
 // 定义数据结构
@@ -105,6 +107,45 @@ impl GlobalDirection {
             Self::Down => Self::Up,
         }
     }
+    ///所有六个方向
+    pub const ALL: [GlobalDirection; 6] = [
+        Self::North,
+        Self::South,
+        Self::West,
+        Self::East,
+        Self::Up,
+        Self::Down,
+    ];
+    ///水平的四个方向
+    pub const HORIZONTAL: [GlobalDirection; 4] = [Self::North, Self::South, Self::West, Self::East];
+    ///向这个方向移动一格的偏移，北为-z，东为+x
+    pub fn offset(self) -> Position {
+        let (x, y, z) = match self {
+            Self::South => (0, 0, 1),
+            Self::North => (0, 0, -1),
+            Self::West => (-1, 0, 0),
+            Self::East => (1, 0, 0),
+            Self::Up => (0, 1, 0),
+            Self::Down => (0, -1, 0),
+        };
+        Position { x, y, z }
+    }
+}
+impl Display for GlobalDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                GlobalDirection::South => "south",
+                GlobalDirection::North => "north",
+                GlobalDirection::West => "west",
+                GlobalDirection::East => "east",
+                GlobalDirection::Up => "up",
+                GlobalDirection::Down => "down",
+            }
+        )
+    }
 }
 impl From<&str> for GlobalDirection {
     fn from(value: &str) -> Self {
@@ -113,6 +154,8 @@ impl From<&str> for GlobalDirection {
             "North" | "north" => GlobalDirection::North,
             "West" | "west" => GlobalDirection::West,
             "East" | "east" => GlobalDirection::East,
+            "Up" | "up" => GlobalDirection::Up,
+            "Down" | "down" => GlobalDirection::Down,
             &_ => panic!("Invalid direction"),
         }
     }
@@ -231,9 +274,141 @@ pub fn check_circuit(obj: &Circuit, model_objects: &Vec<Box<dyn ModelObject>>) -
         }
     }
 
+    if !check_crosstalk(obj) {
+        isok = false;
+    }
+
     isok
 }
 
+///## 串扰检查
+/// 编译导线和方块，找出属于不同导线的红石线相连(包括上下坡)，
+/// 以及导线上的中继器从别的导线取得输入或者输出到别的导线。
+///
+/// 端点重合、或者端点在另一根导线上的导线本来就是连通的，不算串扰。元件不参与编译。
+pub fn check_crosstalk(obj: &Circuit) -> bool {
+    let reports = crosstalk_reports(obj);
+    for report in reports.iter() {
+        error_begin();
+        println!("{}", report);
+    }
+    reports.is_empty()
+}
+
+///每一对串扰的导线一条说明
+fn crosstalk_reports(obj: &Circuit) -> Vec<String> {
+    let mut region = Region::with_shape(obj.size.to_slice());
+    compile_wires(obj, &mut region, Position { x: 0, y: 0, z: 0 });
+    let up = GlobalDirection::Up.offset();
+    //连通的导线分成一组
+    let mut group: Vec<usize> = (0..obj.wires.len()).collect();
+    fn find(group: &mut [usize], mut i: usize) -> usize {
+        while group[i] != i {
+            group[i] = group[group[i]];
+            i = group[i];
+        }
+        i
+    }
+    let paths: Vec<Vec<Position>> = obj.wires.iter().map(|w| w.path()).collect();
+    for (i, wire) in obj.wires.iter().enumerate() {
+        for (j, path) in paths.iter().enumerate() {
+            if i != j && (path.contains(&wire.start) || path.contains(&wire.end)) {
+                let (a, b) = (find(&mut group, i), find(&mut group, j));
+                group[a] = b;
+            }
+        }
+    }
+    let group: Vec<usize> = (0..obj.wires.len()).map(|i| find(&mut group, i)).collect();
+    //每一格的底座和红石线属于哪根导线，编译时后面的导线覆盖前面的
+    let mut contacts: BTreeMap<(usize, usize), Vec<(Position, Position)>> = BTreeMap::new();
+    let mut add_contact = |a: usize, b: usize, from: Position, to: Position| {
+        if group[a] != group[b] {
+            contacts
+                .entry((a.min(b), a.max(b)))
+                .or_default()
+                .push((from, to));
+        }
+    };
+    let mut bases: HashMap<Position, usize> = HashMap::new();
+    let mut dusts: HashMap<Position, usize> = HashMap::new();
+    for (i, path) in paths.iter().enumerate() {
+        for cell in path.iter() {
+            for (owners, pos) in [(&mut bases, *cell), (&mut dusts, *cell + up)] {
+                if let Some(j) = owners.insert(pos, i) {
+                    add_contact(j, i, pos, pos);
+                }
+            }
+        }
+    }
+    //相连的红石线
+    let sim = BlockSimulator::new(&region);
+    let mut dust_positions: Vec<(&Position, &usize)> = dusts.iter().collect();
+    dust_positions.sort_by_key(|(p, _)| (p.x, p.y, p.z));
+    for (pos, a) in dust_positions {
+        if region
+            .block_at(pos.to_slice())
+            .is_none_or(|b| b.id != "redstone_wire")
+        {
+            continue;
+        }
+        for n in sim.dust_neighbors(*pos) {
+            if let Some(b) = dusts.get(&n)
+                && a < b
+            {
+                add_contact(*a, *b, *pos, n);
+            }
+        }
+    }
+    //中继器的输入和输出
+    for block in obj.blocks.iter().filter(|b| b.id.contains("repeater")) {
+        let Some(a) = paths
+            .iter()
+            .position(|path| path.iter().any(|cell| on_wire_cell(block.position, *cell)))
+        else {
+            continue;
+        };
+        let facing = region
+            .block_at(block.position.to_slice())
+            .and_then(|b| b.attributes.get("facing").cloned())
+            .unwrap_or("north".to_string());
+        let facing = GlobalDirection::from(facing.as_str());
+        let input = block.position + facing.offset();
+        let output = block.position + facing.opposite().offset();
+        if let Some(b) = dusts.get(&input) {
+            add_contact(*b, a, input, block.position);
+        }
+        //输出到别的导线的红石线，或者充能别的导线的底座
+        if let Some(b) = dusts.get(&output).or(bases.get(&output)) {
+            add_contact(a, *b, block.position, output);
+        }
+    }
+    let mut reports = vec![];
+    for ((a, b), positions) in contacts.iter() {
+        //并排的导线每一格都相连，只列出前几处
+        let mut listed: Vec<String> = positions
+            .iter()
+            .take(8)
+            .map(|(from, to)| {
+                if from == to {
+                    from.to_string()
+                } else {
+                    format!("{}-{}", from, to)
+                }
+            })
+            .collect();
+        if positions.len() > listed.len() {
+            listed.push(format!("... ({} in total)", positions.len()));
+        }
+        reports.push(format!(
+            "wires {} and {} connect at {}",
+            obj.wires[*a].name,
+            obj.wires[*b].name,
+            listed.join(", ")
+        ));
+    }
+    reports
+}
+
 impl Graph {
     fn new() -> Self {
         Graph {
@@ -368,28 +543,6 @@ impl Graph {
     }
 }
 
-fn wire_positions(start: &Position, end: &Position) -> Vec<Position> {
-    // 假设导线是直线连接的，这里简单处理为从start到end的所有位置
-    let mut positions = vec![];
-    let (dx, dy, dz) = (end.x - start.x, end.y - start.y, end.z - start.z);
-    let steps = dx.abs().max(dy.abs()).max(dz.abs());
-
-    for i in 0..=steps {
-        positions.push(Position {
-            x: start.x + dx * i / steps,
-            y: start.y + dy * i / steps,
-            z: start.z + dz * i / steps,
-        });
-    }
-
-    positions
-}
-
-fn wire_length(start: &Position, end: &Position) -> i32 {
-    // 计算曼哈顿距离
-    (start.x - end.x).abs() + (start.y - end.y).abs() + (start.z - end.z).abs()
-}
-
 fn repeater_direction(
     pos: &Position,
     blocks: &Vec<BlockInfo>,
@@ -397,16 +550,18 @@ fn repeater_direction(
 ) -> Option<RepeaterDirection> {
     //中继器的方向存储在项目json中
     for block in blocks {
-        if block.position == *pos && block.id.contains("repeater") {
+        if on_wire_cell(block.position, *pos) && block.id.contains("repeater") {
             let direct = GlobalDirection::from(
                 block
                     .properties
                     .as_ref()
                     .expect("repeater block has no properties")
-                    .facing
+                    .get("facing")
+                    .expect("repeater block has no facing property")
                     .as_str(),
             );
-            if direct == wire_direction {
+            //facing是中继器输入端的方向，信号向相反方向输出
+            if direct.opposite() == wire_direction {
                 return Some(RepeaterDirection::Forward);
             } else {
                 return Some(RepeaterDirection::Backward);
@@ -417,7 +572,7 @@ fn repeater_direction(
     None
 }
 
-const MAX_REDSTONE_DISTANCE: i32 = 15; // 红石的最大传播距离
+pub const MAX_REDSTONE_DISTANCE: i32 = 15; // 红石的最大传播距离
 
 ///
 /// ## 构建连接图，表示元件端口之间的连接关系。
@@ -451,14 +606,14 @@ pub fn create_graph(circuit: &Circuit, model_objects: &Vec<Box<dyn ModelObject>>
 
         model.get_inputs().iter().for_each(|port| {
             graph.add_dot(Dot {
-                pos: port.position + comp.position,
+                pos: comp.port_position(port, model.as_ref()),
                 distance: i32::MAX,
                 type_: NodeType::Input,
             });
         });
         model.get_outputs().iter().for_each(|port| {
             graph.add_dot(Dot {
-                pos: port.position + comp.position,
+                pos: comp.port_position(port, model.as_ref()),
                 distance: i32::MAX,
                 type_: NodeType::Output,
             });
@@ -472,21 +627,11 @@ pub fn create_graph(circuit: &Circuit, model_objects: &Vec<Box<dyn ModelObject>>
         let mut direct = EdgeDirect::Bidirectional;
         let mut conflict = false;
         // 检查导线上的中继器
-        let positions = wire_positions(&wire.start, &wire.end);
-        for pos in positions {
-            if let Some(block) = circuit.blocks.iter().find(|b| b.position == pos) {
+        let positions = wire.headings();
+        for (pos, wire_direction) in positions {
+            if let Some(block) = circuit.blocks.iter().find(|b| on_wire_cell(b.position, pos)) {
                 if block.id.contains("repeater") {
-                    let new_direct = match repeater_direction(&pos, &circuit.blocks, {
-                        if wire.start.x < wire.end.x {
-                            GlobalDirection::East
-                        } else if wire.start.x > wire.end.x {
-                            GlobalDirection::West
-                        } else if wire.start.z < wire.end.z {
-                            GlobalDirection::South
-                        } else {
-                            GlobalDirection::North
-                        }
-                    }) {
+                    let new_direct = match repeater_direction(&pos, &circuit.blocks, wire_direction) {
                         Some(RepeaterDirection::Forward) => EdgeDirect::Nonreversed,
                         Some(RepeaterDirection::Backward) => EdgeDirect::Reversed,
                         None => EdgeDirect::Bidirectional, // 默认双向
@@ -511,7 +656,7 @@ pub fn create_graph(circuit: &Circuit, model_objects: &Vec<Box<dyn ModelObject>>
             graph.add_edge(Edge {
                 start: start_idx,
                 end: end_idx,
-                length: wire_length(&wire.start, &wire.end),
+                length: wire.effective_length(&circuit.blocks),
                 direct,
             });
         }
@@ -533,3 +678,52 @@ pub fn create_graph(circuit: &Circuit, model_objects: &Vec<Box<dyn ModelObject>>
     }
     Some(graph)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wire(name: &str, start: [i32; 3], end: [i32; 3]) -> String {
+        format!(
+            r#"{{"name":"{}","start":{{"x":{},"y":{},"z":{}}},"end":{{"x":{},"y":{},"z":{}}},
+            "baseMaterial":"stone"}}"#,
+            name, start[0], start[1], start[2], end[0], end[1], end[2]
+        )
+    }
+    fn circuit(wires: &[String]) -> Circuit {
+        serde_json::from_str(&format!(
+            r#"{{"name":"crosstalk","size":{{"x":4,"y":3,"z":6}},"imports":[],"components":[],
+            "wires":[{}],"blocks":[],"inputs":[],"outputs":[]}}"#,
+            wires.join(",")
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn side_by_side_and_stacked_wires() {
+        let obj = circuit(&[
+            wire("a", [0, 0, 0], [3, 0, 0]),
+            wire("b", [0, 0, 1], [3, 0, 1]),
+            //c的红石线能爬上d的底座
+            wire("c", [0, 0, 4], [3, 0, 4]),
+            wire("d", [0, 1, 5], [3, 1, 5]),
+        ]);
+        assert_eq!(
+            crosstalk_reports(&obj),
+            [
+                "wires a and b connect at (0,1,0)-(0,1,1), (1,1,0)-(1,1,1), (2,1,0)-(2,1,1), (3,1,0)-(3,1,1)",
+                "wires c and d connect at (0,1,4)-(0,2,5), (1,1,4)-(1,2,5), (2,1,4)-(2,2,5), (3,1,4)-(3,2,5)",
+            ]
+        );
+        assert!(!check_crosstalk(&obj));
+    }
+
+    #[test]
+    fn wires_joined_at_an_end_are_not_crosstalk() {
+        let obj = circuit(&[
+            wire("a", [0, 0, 0], [3, 0, 0]),
+            wire("b", [3, 0, 0], [3, 0, 3]),
+        ]);
+        assert!(crosstalk_reports(&obj).is_empty());
+    }
+}
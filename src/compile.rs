@@ -0,0 +1,420 @@
+use std::fs::OpenOptions;
+
+use mc_schem::{Block, Region, Schematic};
+
+use crate::*;
+
+///## 库路径
+/// 如果指定了元件库，相对路径从元件库中寻找
+pub fn library_path(library: Option<&str>, relative: &str) -> String {
+    if let Some(library) = library {
+        library.to_owned() + "/" + relative
+    } else {
+        relative.to_string()
+    }
+}
+
+///## 库中的元件
+/// library目录下所有元件json的路径，按文件名排序。其他json(比如电路和配置)跳过
+pub fn library_component_files(library: Option<&str>) -> Vec<String> {
+    let dir = library.unwrap_or(".");
+    let mut entries: Vec<_> = std::fs::read_dir(dir)
+        .unwrap_or_else(|_| {
+            error_begin();
+            panic!("failed to read library directory {}", dir)
+        })
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    entries.sort();
+    entries
+        .into_iter()
+        .filter(|path| {
+            std::fs::read_to_string(path)
+                .ok()
+                .and_then(|content| serde_json::from_str::<ComponentModelObject>(&content).ok())
+                .is_some_and(|model| model.modelType == "component")
+        })
+        .map(|path| path.to_string_lossy().to_string())
+        .collect()
+}
+
+///## 加载导入
+/// 读取imports中的每一项，返回模型对象列表。
+///
+/// 子电路只读取它自身的json，它的导入在编译时再加载。
+pub fn load_imports(imports: &[ImportItem], library: Option<&str>) -> Vec<Box<dyn ModelObject>> {
+    let mut model_objects: Vec<Box<dyn ModelObject>> = vec![];
+    for import_item in imports.iter() {
+        let path = library_path(library, &import_item.path);
+        let model_type = import_item.modelType.as_str();
+        let rd = OpenOptions::new()
+            .read(true)
+            .open(&path)
+            .unwrap_or_else(|_| panic!("failed to open import file {}", &path));
+        match model_type {
+            "component" => {
+                //元件类型
+                let model_obj = Box::<ComponentModelObject>::new(
+                    serde_json::from_reader(rd).unwrap_or_else(|x| {
+                        error_begin();
+                        panic!("failed to parse component json file {}:\n{}", &path, x)
+                    }),
+                );
+                model_objects.push(model_obj);
+            }
+            "circuit" => {
+                let model_obj = Box::<Circuit>::new(serde_json::from_reader(rd).unwrap_or_else(|x| {
+                    error_begin();
+                    panic!("failed to parse circuit json file {}:\n{}", &path, x)
+                }));
+                model_objects.push(model_obj);
+            }
+            _ => {
+                error_begin();
+                panic!("Unsupported model type: {}", model_type);
+            }
+        }
+    }
+    model_objects
+}
+
+///## 编译电路
+/// 把circuit的元件、导线、方块放置到region中，offset为circuit在region中的位置。
+///
+/// 子电路会被递归编译。chain记录了当前正在编译的电路文件路径，用于检测循环导入。
+pub fn compile_circuit(
+    circuit: &Circuit,
+    model_objects: &[Box<dyn ModelObject>],
+    library: Option<&str>,
+    region: &mut Region,
+    offset: Position,
+    chain: &mut Vec<String>,
+) {
+    //解析元件
+    for component in circuit.components.iter() {
+        let origin = component.origin();
+        println!(
+            "Component:{},Model:{},Position:({},{},{})",
+            component.name, component.model, origin.x, origin.y, origin.z
+        );
+        let model_name = component.model.as_str();
+        //找到对应导入
+        let model_import_item = model_objects
+            .iter()
+            .find(|x| x.get_name() == model_name)
+            .unwrap_or_else(|| {
+                error_begin();
+                println!("Error: Model {} not found in imports", model_name);
+                panic!("Model {} not found in imports", model_name);
+            });
+        let position = offset + origin;
+        //根据不同的model_type进行处理，然后放置到schematic的region中
+        match model_import_item.get_type() {
+            "component" => {
+                // 元件，寻找它的nbt
+                let nbt_path = library_path(library, model_import_item.get_nbt_path().unwrap());
+                let nbt_region = load_nbt_region(&nbt_path);
+                stamp_region(
+                    &nbt_region,
+                    model_import_item.get_size(),
+                    &component.orientation,
+                    position,
+                    region,
+                );
+            }
+            "circuit" => {
+                let sub_circuit = model_import_item
+                    .as_any()
+                    .downcast_ref::<Circuit>()
+                    .unwrap();
+                let import_item = circuit
+                    .imports
+                    .iter()
+                    .find(|x| x.modelName == model_name)
+                    .unwrap();
+                let path = library_path(library, &import_item.path);
+                let path = std::fs::canonicalize(&path)
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or(path);
+                if chain.contains(&path) {
+                    error_begin();
+                    panic!(
+                        "circular import: circuit {} imports itself through {}",
+                        sub_circuit.name,
+                        chain.join(" -> ") + " -> " + &path
+                    );
+                }
+                //子电路不能超出父电路的范围
+                let sub_size = component
+                    .orientation
+                    .transform_size(sub_circuit.size.to_slice());
+                let end = origin
+                    + Position {
+                        x: sub_size[0],
+                        y: sub_size[1],
+                        z: sub_size[2],
+                    };
+                if origin.x < 0
+                    || origin.y < 0
+                    || origin.z < 0
+                    || end.x > circuit.size.x
+                    || end.y > circuit.size.y
+                    || end.z > circuit.size.z
+                {
+                    error_begin();
+                    panic!(
+                        "sub-circuit {} (model {}) of size {} at {} overflows circuit {} of size {}",
+                        component.name,
+                        model_name,
+                        Position {
+                            x: sub_size[0],
+                            y: sub_size[1],
+                            z: sub_size[2],
+                        },
+                        origin,
+                        circuit.name,
+                        circuit.size
+                    );
+                }
+                //子电路先编译到单独的region，再按朝向放置
+                let sub_models = load_imports(&sub_circuit.imports, library);
+                let mut sub_region = Region::with_shape(sub_circuit.size.to_slice());
+                chain.push(path);
+                compile_circuit(
+                    sub_circuit,
+                    &sub_models,
+                    library,
+                    &mut sub_region,
+                    Position { x: 0, y: 0, z: 0 },
+                    chain,
+                );
+                chain.pop();
+                stamp_region(
+                    &sub_region,
+                    sub_circuit.size.to_slice(),
+                    &component.orientation,
+                    position,
+                    region,
+                );
+            }
+            _ => {
+                error_begin();
+                panic!("Unsupported model type: {}", model_import_item.get_type());
+            }
+        }
+    }
+    compile_wires(circuit, region, offset);
+}
+
+///## 编译导线和方块
+/// 把circuit的导线(底座和红石线)和方块放置到region中，offset为circuit在region中的位置
+pub fn compile_wires(circuit: &Circuit, region: &mut Region, offset: Position) {
+    //解析导线
+    for wire in circuit.wires.iter() {
+        let dust = Block::from_id("redstone_wire").unwrap();
+        for cell in wire.cells() {
+            let base_block = Block::from_id(&cell.base).expect("err: invalid base material");
+            let pos = offset + cell.position;
+            region.set_block(pos.to_slice(), &base_block).unwrap_or_else(|_| {
+                error_begin();
+                panic!("wire {} goes out of range at {}", wire.name, pos)
+            });
+            //放置导线
+            let dust_pos = pos + Position { x: 0, y: 1, z: 0 };
+            region.set_block(dust_pos.to_slice(), &dust).unwrap_or_else(|_| {
+                error_begin();
+                panic!("wire {} goes out of range at {}", wire.name, dust_pos)
+            });
+        }
+    }
+    //解析方块
+    for block in circuit.blocks.iter() {
+        let block_id = block.id.as_str();
+        let block_pos = (offset + block.position).to_slice();
+        let mut block_block = Block::from_id(block_id)
+            .unwrap_or_else(|_| panic!("err: invalid block id {}", block_id));
+        if let Some(properties) = block.properties.as_ref() {
+            properties.apply_to(&mut block_block);
+        }
+        region.set_block(block_pos, &block_block).unwrap();
+    }
+}
+
+///原版结构文件(.nbt)中的一种方块状态
+#[derive(Deserialize)]
+struct StructurePaletteEntry {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Properties", default)]
+    properties: BTreeMap<String, String>,
+}
+///原版结构文件中的一个方块，state是palette中的下标
+#[derive(Deserialize)]
+struct StructureBlock {
+    state: usize,
+    pos: Vec<i32>,
+    #[serde(default)]
+    nbt: Option<HashMap<String, fastnbt::Value>>,
+}
+#[derive(Deserialize)]
+struct StructureFile {
+    size: Vec<i32>,
+    #[serde(default)]
+    palette: Vec<StructurePaletteEntry>,
+    ///有多个palette时(例如沉船)取第一个
+    #[serde(default)]
+    palettes: Vec<Vec<StructurePaletteEntry>>,
+    blocks: Vec<StructureBlock>,
+}
+
+///## 读取元件nbt
+/// 读取原版结构文件，每个方块按它自己的pos放置，没有列出的位置是空气。
+///
+/// 结构文件中的blocks不一定按坐标顺序排列，所以不能按下标推算位置。
+pub fn load_nbt_region(nbt_path: &str) -> Region {
+    let raw = std::fs::read(nbt_path).unwrap_or_else(|e| {
+        error_begin();
+        panic!("failed to load nbt file {}: {}", nbt_path, e)
+    });
+    //结构文件一般是gzip压缩的
+    let mut data = Vec::new();
+    let data = if raw.starts_with(&[0x1f, 0x8b]) {
+        flate2::read::GzDecoder::new(&raw[..])
+            .read_to_end(&mut data)
+            .unwrap_or_else(|e| {
+                error_begin();
+                panic!("failed to decompress nbt file {}: {}", nbt_path, e)
+            });
+        data
+    } else {
+        raw
+    };
+    let structure: StructureFile = fastnbt::from_bytes(&data).unwrap_or_else(|e| {
+        error_begin();
+        panic!("failed to parse nbt file {}: {}", nbt_path, e)
+    });
+    let palette = if structure.palette.is_empty() {
+        structure.palettes.into_iter().next().unwrap_or_default()
+    } else {
+        structure.palette
+    };
+    let palette: Vec<Block> = palette
+        .into_iter()
+        .map(|entry| {
+            let mut block = Block::from_id(&entry.name).unwrap_or_else(|_| {
+                error_begin();
+                panic!("invalid block {} in nbt file {}", entry.name, nbt_path)
+            });
+            block.attributes = entry.properties;
+            block
+        })
+        .collect();
+    if structure.size.len() != 3 {
+        error_begin();
+        panic!("nbt file {} has an invalid size {:?}", nbt_path, structure.size);
+    }
+    let mut region = Region::with_shape([structure.size[0], structure.size[1], structure.size[2]]);
+    for block in structure.blocks {
+        let (Some(state), &[x, y, z]) = (palette.get(block.state), &block.pos[..]) else {
+            error_begin();
+            panic!(
+                "invalid block state {} at {:?} in nbt file {}",
+                block.state, block.pos, nbt_path
+            );
+        };
+        region.set_block([x, y, z], state).unwrap_or_else(|_| {
+            error_begin();
+            panic!("block at {:?} is outside nbt file {}", block.pos, nbt_path)
+        });
+        if let Some(tags) = block.nbt {
+            region
+                .block_entities
+                .insert([x, y, z], mc_schem::region::BlockEntity { tags });
+        }
+    }
+    region
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{LIBRARY, load_circuit, nested_library, test_library};
+    use mc_schem::region::WorldSlice;
+
+    ///和main一样，chain从电路自身的文件开始
+    fn compile(path: &str, circuit: &Circuit, library: &str) -> Region {
+        let models = load_imports(&circuit.imports, Some(library));
+        let mut region = Region::with_shape(circuit.size.to_slice());
+        let mut chain = vec![
+            std::fs::canonicalize(path)
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+        ];
+        compile_circuit(
+            circuit,
+            &models,
+            Some(library),
+            &mut region,
+            Position { x: 0, y: 0, z: 0 },
+            &mut chain,
+        );
+        region
+    }
+
+    #[test]
+    fn nested_circuits_place_the_innermost_component() {
+        let library = nested_library("compile", "nested");
+        let path = format!("{}/top.json", library);
+        let region = compile(&path, &load_circuit(&path), &library);
+        let and = load_nbt_region(&format!("{}/nbt/and.nbt", LIBRARY));
+        let shape = and.shape();
+        for x in 0..shape[0] {
+            for y in 0..shape[1] {
+                for z in 0..shape[2] {
+                    assert_eq!(
+                        region.block_at([x, y, z]).unwrap().full_id(),
+                        and.block_at([x, y, z]).unwrap().full_id(),
+                        "block at [{}, {}, {}]",
+                        x,
+                        y,
+                        z
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "circular import: circuit a imports itself through")]
+    fn circular_import_rejected() {
+        let library = test_library("compile", "circular", &[]);
+        //a导入b，b又导入a
+        for (name, other) in [("a", "b"), ("b", "a")] {
+            let circuit = format!(
+                r#"{{"name":"{0}","size":{{"x":1,"y":1,"z":1}},
+                "imports":[{{"modelName":"{1}","modelType":"circuit","path":"{1}.json"}}],
+                "components":[{{"name":"{1}1","model":"{1}","position":{{"x":0,"y":0,"z":0}}}}],
+                "wires":[],"blocks":[],"inputs":[],"outputs":[]}}"#,
+                name, other
+            );
+            std::fs::write(format!("{}/{}.json", library, name), circuit).unwrap();
+        }
+        let path = format!("{}/a.json", library);
+        compile(&path, &load_circuit(&path), &library);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "sub-circuit outer (model middle) of size (4,4,5) at (0,0,0) overflows circuit top of size (3,4,5)"
+    )]
+    fn overflowing_sub_circuit_rejected() {
+        let library = nested_library("compile", "overflow");
+        let path = format!("{}/top.json", library);
+        let mut top = load_circuit(&path);
+        top.size.x = 3;
+        compile(&path, &top, &library);
+    }
+}
@@ -0,0 +1,559 @@
+use std::fs::OpenOptions;
+
+use mc_schem::{Block, Region, Schematic, region::WorldSlice};
+
+use crate::{sim::load_component_model, *};
+
+///## 反编译
+/// 读取schematic，识别其中的元件、导线和方块，生成可以重新编译成相同schematic的项目。
+///
+/// 元件从library目录下的所有component json中识别，导线由底座上连续的红石线组成，
+/// 其余方块都放入blocks。电路的输入输出无法从方块中得知，需要手动补充。
+pub fn decompile(schematic_path: &str, library: Option<&str>) -> Circuit {
+    //原版结构文件按每个方块自己的pos读取
+    let region = if schematic_path.ends_with(".nbt") {
+        load_nbt_region(schematic_path)
+    } else {
+        let (mut schem, _raw_meta) = Schematic::from_file(schematic_path).unwrap_or_else(|x| {
+            error_begin();
+            panic!("failed to load schematic file {}: {}", schematic_path, x)
+        });
+        schem.merge_regions(&Block::air());
+        schem.regions.remove(0)
+    };
+    let shape = region.shape();
+    let name = std::path::Path::new(schematic_path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut circuit = Circuit {
+        name,
+        size: Position {
+            x: shape[0],
+            y: shape[1],
+            z: shape[2],
+        },
+        imports: vec![],
+        components: vec![],
+        wires: vec![],
+        nets: vec![],
+        blocks: vec![],
+        inputs: vec![],
+        outputs: vec![],
+    };
+    //已经被元件或导线使用的位置
+    let mut claimed: HashSet<Position> = HashSet::new();
+    //元件的包围盒，编译时整个包围盒都会被元件覆盖
+    let mut boxes: HashSet<Position> = HashSet::new();
+    for (import, pattern) in library_patterns(library) {
+        let mut count = 0;
+        for (origin, orientation) in find_pattern(&region, &pattern, &boxes) {
+            count += 1;
+            for dx in 0..pattern.size[0] {
+                for dy in 0..pattern.size[1] {
+                    for dz in 0..pattern.size[2] {
+                        boxes.insert(
+                            origin
+                                + Position {
+                                    x: dx,
+                                    y: dy,
+                                    z: dz,
+                                },
+                        );
+                    }
+                }
+            }
+            claimed.extend(
+                pattern.variants[&orientation]
+                    .iter()
+                    .map(|(p, _)| origin + *p),
+            );
+            circuit.components.push(Component {
+                name: format!("{}{:03}", import.modelName, count),
+                model: import.modelName.clone(),
+                position: Some(origin),
+                orientation,
+                movable: false,
+            });
+        }
+        if count > 0 {
+            circuit.imports.push(import);
+        }
+    }
+    let (wires, repeaters) = find_wires(&region, &boxes);
+    for wire in wires.iter() {
+        for pos in wire.path() {
+            claimed.insert(pos);
+            claimed.insert(pos + Position { x: 0, y: 1, z: 0 });
+        }
+    }
+    //导线上的中继器作为方块放在红石线的位置
+    for pos in repeaters {
+        claimed.remove(&pos);
+    }
+    circuit.wires = wires;
+    for y in 0..shape[1] {
+        for z in 0..shape[2] {
+            for x in 0..shape[0] {
+                let position = Position { x, y, z };
+                let block = region.block_at([x, y, z]).unwrap();
+                if block.is_air() || claimed.contains(&position) {
+                    continue;
+                }
+                let properties = block
+                    .attributes
+                    .iter()
+                    .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+                    .collect::<BTreeMap<_, _>>();
+                circuit.blocks.push(BlockInfo {
+                    position,
+                    id: block_id_string(&Block {
+                        namespace: block.namespace.clone(),
+                        id: block.id.clone(),
+                        attributes: BTreeMap::new(),
+                    }),
+                    properties: if properties.is_empty() {
+                        None
+                    } else {
+                        Some(Properties(properties))
+                    },
+                });
+            }
+        }
+    }
+    circuit
+}
+
+///## 反编译并保存
+pub fn decompile_to_file(schematic_path: &str, output_path: &str, library: Option<&str>) {
+    let circuit = decompile(schematic_path, library);
+    let json = serde_json::to_string_pretty(&circuit).unwrap();
+    let mut output_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output_path)
+        .unwrap_or_else(|_| {
+            error_begin();
+            panic!("failed to open output json file {}", output_path);
+        });
+    output_file
+        .write_all(json.as_bytes())
+        .expect("failed to write output json file");
+    println!(
+        "decompiled {} component(s), {} wire(s) and {} block(s) into {}",
+        circuit.components.len(),
+        circuit.wires.len(),
+        circuit.blocks.len(),
+        output_path
+    );
+}
+
+///## Pattern
+/// 一个元件在各个朝向下的非空气方块，位置相对于元件的包围盒
+struct Pattern {
+    size: [i32; 3],
+    variants: HashMap<Orientation, Vec<(Position, Block)>>,
+    ///按搜索顺序排列的朝向
+    orientations: Vec<Orientation>,
+}
+
+///读取library目录下所有的元件，按方块数从多到少排列，优先识别大的元件
+fn library_patterns(library: Option<&str>) -> Vec<(ImportItem, Pattern)> {
+    let mut patterns = vec![];
+    for path in library_component_files(library) {
+        let model = load_component_model(&path);
+        let nbt_path = library_path(library, &model.nbt);
+        if !std::path::Path::new(&nbt_path).exists() {
+            println!(
+                "warning: nbt file {} of component {} not found, skipped",
+                nbt_path, model.name
+            );
+            continue;
+        }
+        let source = load_nbt_region(&nbt_path);
+        let mut pattern = Pattern {
+            size: model.size,
+            variants: HashMap::new(),
+            orientations: vec![],
+        };
+        for mirror_x in [false, true] {
+            for mirror_z in [false, true] {
+                for rotation in [0, 90, 180, 270] {
+                    let orientation = Orientation {
+                        rotation,
+                        mirror_x,
+                        mirror_z,
+                    };
+                    let blocks = oriented_blocks(&source, model.size, &orientation);
+                    //对称的元件在不同朝向下完全相同，只保留第一个
+                    if blocks.is_empty() || pattern.variants.values().any(|v| *v == blocks) {
+                        continue;
+                    }
+                    pattern.orientations.push(orientation);
+                    pattern.variants.insert(orientation, blocks);
+                }
+            }
+        }
+        if pattern.orientations.is_empty() {
+            continue;
+        }
+        let file_name = std::path::Path::new(&path)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        patterns.push((
+            ImportItem {
+                modelName: model.name.clone(),
+                modelType: "component".to_string(),
+                path: file_name,
+            },
+            pattern,
+        ));
+    }
+    patterns.sort_by_key(|(_, p)| std::cmp::Reverse(p.variants[&p.orientations[0]].len()));
+    patterns
+}
+
+///元件按朝向变换后的非空气方块，和编译时stamp_region放置的结果一致
+fn oriented_blocks(
+    source: &Region,
+    size: [i32; 3],
+    orientation: &Orientation,
+) -> Vec<(Position, Block)> {
+    let shape = source.shape();
+    let mut blocks = vec![];
+    for x in 0..shape[0] {
+        for y in 0..shape[1] {
+            for z in 0..shape[2] {
+                let block = source.block_at([x, y, z]).unwrap();
+                if block.is_air() || block.is_structure_void() {
+                    continue;
+                }
+                let pos = orientation.transform_position(Position { x, y, z }, size);
+                blocks.push((pos, orientation.transform_block(block)));
+            }
+        }
+    }
+    blocks.sort_by_key(|(p, _)| (p.y, p.z, p.x));
+    blocks
+}
+
+///在region中寻找元件，返回每个实例的位置和朝向。
+///
+/// 包围盒不能和已经找到的元件重叠，否则重新编译时会互相覆盖。
+fn find_pattern(
+    region: &Region,
+    pattern: &Pattern,
+    boxes: &HashSet<Position>,
+) -> Vec<(Position, Orientation)> {
+    let shape = region.shape();
+    let mut found = vec![];
+    let mut used: HashSet<Position> = HashSet::new();
+    for orientation in pattern.orientations.iter() {
+        let blocks = &pattern.variants[orientation];
+        let size = orientation.transform_size(pattern.size);
+        for y in 0..=shape[1] - size[1] {
+            for z in 0..=shape[2] - size[2] {
+                for x in 0..=shape[0] - size[0] {
+                    let origin = Position { x, y, z };
+                    let matched = blocks.iter().all(|(p, b)| {
+                        let pos = origin + *p;
+                        region
+                            .block_at(pos.to_slice())
+                            .is_some_and(|r| same_block(r, b))
+                    });
+                    if !matched {
+                        continue;
+                    }
+                    let cells: Vec<Position> = (0..size[0])
+                        .flat_map(|dx| {
+                            (0..size[1]).flat_map(move |dy| {
+                                (0..size[2]).map(move |dz| {
+                                    origin
+                                        + Position {
+                                            x: dx,
+                                            y: dy,
+                                            z: dz,
+                                        }
+                                })
+                            })
+                        })
+                        .collect();
+                    if cells.iter().any(|c| boxes.contains(c) || used.contains(c)) {
+                        continue;
+                    }
+                    used.extend(cells);
+                    found.push((origin, *orientation));
+                }
+            }
+        }
+    }
+    found
+}
+
+///两个方块是否相同，没有命名空间的方块视为minecraft
+fn same_block(a: &Block, b: &Block) -> bool {
+    let namespace = |b: &Block| {
+        if b.namespace.is_empty() {
+            "minecraft".to_string()
+        } else {
+            b.namespace.clone()
+        }
+    };
+    a.id == b.id && a.attributes == b.attributes && namespace(a) == namespace(b)
+}
+
+///项目json中方块的写法，minecraft命名空间省略
+fn block_id_string(block: &Block) -> String {
+    let id = block.full_id();
+    id.strip_prefix("minecraft:")
+        .map(|s| s.to_string())
+        .unwrap_or(id)
+}
+
+///## 识别导线
+/// 红石线(或中继器)和它下方的底座组成导线的一格，相邻两格水平相邻且高度差不超过1，
+/// 并且底座材质相同。连通的格子被拆分成若干条不分叉的导线，只有一格的不算导线。
+///
+/// 返回导线和导线上中继器的位置。
+fn find_wires(region: &Region, boxes: &HashSet<Position>) -> (Vec<Wire>, Vec<Position>) {
+    let shape = region.shape();
+    let up = Position { x: 0, y: 1, z: 0 };
+    //底座位置 -> (底座材质, 中继器的facing)
+    let mut cells: HashMap<Position, (String, Option<String>)> = HashMap::new();
+    for y in 0..shape[1] - 1 {
+        for z in 0..shape[2] {
+            for x in 0..shape[0] {
+                let base_pos = Position { x, y, z };
+                if boxes.contains(&base_pos) || boxes.contains(&(base_pos + up)) {
+                    continue;
+                }
+                let base = region.block_at([x, y, z]).unwrap();
+                let top = region.block_at([x, y + 1, z]).unwrap();
+                if base.is_air() {
+                    continue;
+                }
+                let repeater_facing = match top.id.as_str() {
+                    "redstone_wire" => None,
+                    "repeater" => top.attributes.get("facing").cloned(),
+                    _ => continue,
+                };
+                cells.insert(base_pos, (block_id_string(base), repeater_facing));
+            }
+        }
+    }
+    //中继器只和它朝向的轴上同一高度的格子相连
+    let reachable = |from: Position, to: Position| {
+        let (base, facing) = &cells[&from];
+        if cells[&to].0 != *base {
+            return false;
+        }
+        let (dx, dy, dz) = (to.x - from.x, to.y - from.y, to.z - from.z);
+        match facing.as_deref() {
+            Some("east") | Some("west") => dy == 0 && dz == 0,
+            Some(_) => dy == 0 && dx == 0,
+            None => true,
+        }
+    };
+    let neighbors = |pos: Position| -> Vec<Position> {
+        let mut result = vec![];
+        for (dx, dz) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            for dy in [0, 1, -1] {
+                let other = pos
+                    + Position {
+                        x: dx,
+                        y: dy,
+                        z: dz,
+                    };
+                if cells.contains_key(&other) && reachable(pos, other) && reachable(other, pos) {
+                    result.push(other);
+                }
+            }
+        }
+        result
+    };
+    let mut order: Vec<Position> = cells.keys().copied().collect();
+    order.sort_by_key(|p| (p.y, p.z, p.x));
+    let mut visited: HashSet<Position> = HashSet::new();
+    let mut wires = vec![];
+    let mut repeaters = vec![];
+    loop {
+        //从剩余邻居最少的格子(导线的端点)开始
+        let unvisited_degree = |p: &Position| {
+            neighbors(*p)
+                .iter()
+                .filter(|n| !visited.contains(n))
+                .count()
+        };
+        let Some(start) = order
+            .iter()
+            .filter(|p| !visited.contains(p))
+            .min_by_key(|p| unvisited_degree(p))
+            .copied()
+        else {
+            break;
+        };
+        let mut path = vec![start];
+        visited.insert(start);
+        loop {
+            let current = *path.last().unwrap();
+            let next = neighbors(current)
+                .into_iter()
+                .filter(|n| !visited.contains(n))
+                //优先直行
+                .min_by_key(|n| {
+                    path.len() >= 2 && {
+                        let prev = path[path.len() - 2];
+                        (n.x - current.x, n.z - current.z)
+                            != (current.x - prev.x, current.z - prev.z)
+                    }
+                });
+            let Some(next) = next else {
+                break;
+            };
+            visited.insert(next);
+            path.push(next);
+        }
+        if path.len() < 2 {
+            continue;
+        }
+        repeaters.extend(
+            path.iter()
+                .filter(|p| cells[p].1.is_some())
+                .map(|p| *p + up),
+        );
+        wires.push(Wire {
+            name: format!("wire{:03}", wires.len() + 1),
+            start: path[0],
+            end: *path.last().unwrap(),
+            waypoints: waypoints_of(&path),
+            baseMaterial: cells[&path[0]].0.clone(),
+            verticalStyle: VerticalStyle::Staircase,
+        });
+    }
+    (wires, repeaters)
+}
+
+///把导线的每一格压缩成拐点，展开后和原来的格子完全相同
+fn waypoints_of(path: &[Position]) -> Vec<Position> {
+    let mut waypoints = vec![];
+    let mut from = 0;
+    while from < path.len() - 1 {
+        let mut to = from + 1;
+        while to + 1 < path.len()
+            && segment_cells(path[from], path[to + 1]).as_deref() == Some(&path[from + 1..=to + 1])
+        {
+            to += 1;
+        }
+        if to < path.len() - 1 {
+            waypoints.push(path[to]);
+        }
+        from = to;
+    }
+    waypoints
+}
+
+///按Wire::cells的规则展开一段，不是合法的水平段或阶梯时返回None
+fn segment_cells(from: Position, to: Position) -> Option<Vec<Position>> {
+    let (dx, dy, dz) = (to.x - from.x, to.y - from.y, to.z - from.z);
+    let steps = dx.abs().max(dz.abs());
+    if (dx != 0 && dz != 0) || steps == 0 || dy.abs() > steps {
+        return None;
+    }
+    let mut pos = from;
+    let mut cells = vec![];
+    for _ in 0..steps {
+        pos = pos
+            + Position {
+                x: dx.signum(),
+                y: if pos.y != to.y { dy.signum() } else { 0 },
+                z: dz.signum(),
+            };
+        cells.push(pos);
+    }
+    Some(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/lib");
+
+    fn compile(circuit: &Circuit) -> Region {
+        let models = load_imports(&circuit.imports, Some(LIBRARY));
+        let mut region = Region::with_shape(circuit.size.to_slice());
+        compile_circuit(
+            circuit,
+            &models,
+            Some(LIBRARY),
+            &mut region,
+            Position { x: 0, y: 0, z: 0 },
+            &mut vec![],
+        );
+        region
+    }
+
+    ///编译示例电路，反编译后重新编译，两次的方块完全相同
+    #[test]
+    fn recompile_matches_original() {
+        let circuit: Circuit = serde_json::from_str(
+            r#"{
+            "name": "sample",
+            "size": {"x": 16, "y": 4, "z": 12},
+            "imports": [{"modelName": "and", "modelType": "component", "path": "and.json"}],
+            "components": [
+                {"name": "a", "model": "and", "position": {"x": 0, "y": 0, "z": 0}},
+                {"name": "b", "model": "and", "position": {"x": 5, "y": 0, "z": 0},
+                    "orientation": {"rotation": 90}},
+                {"name": "c", "model": "and", "position": {"x": 0, "y": 0, "z": 6},
+                    "orientation": {"mirror_z": true}},
+                {"name": "d", "model": "and", "position": {"x": 5, "y": 0, "z": 6},
+                    "orientation": {"rotation": 270, "mirror_x": true}}
+            ],
+            "wires": [{"name": "w", "start": {"x": 11, "y": 1, "z": 1},
+                "end": {"x": 11, "y": 1, "z": 10}, "baseMaterial": "minecraft:stone"}],
+            "blocks": [{"position": {"x": 14, "y": 0, "z": 5}, "id": "minecraft:redstone_lamp"}],
+            "inputs": [],
+            "outputs": []
+        }"#,
+        )
+        .unwrap();
+        let original = compile(&circuit);
+        let dir = std::env::temp_dir().join("mc_circuit_script_decompile_round_trip");
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["sample.litematic", "sample.nbt"] {
+            let path = dir.join(file).to_string_lossy().to_string();
+            let mut schem = Schematic::new();
+            schem.regions.push(original.clone());
+            save_schematic(&mut schem, &path, None, None, None);
+            assert_round_trip(&circuit, &original, &path);
+        }
+    }
+
+    ///反编译path后重新编译，和original比较
+    fn assert_round_trip(circuit: &Circuit, original: &Region, path: &str) {
+        let decompiled = decompile(path, Some(LIBRARY));
+        assert_eq!(decompiled.components.len(), 4);
+        assert_eq!(decompiled.wires.len(), 1);
+        let recompiled = compile(&decompiled);
+        for x in 0..circuit.size.x {
+            for y in 0..circuit.size.y {
+                for z in 0..circuit.size.z {
+                    //项目json中的方块省略了minecraft命名空间，按json中的写法比较
+                    assert_eq!(
+                        block_id_string(original.block_at([x, y, z]).unwrap()),
+                        block_id_string(recompiled.block_at([x, y, z]).unwrap()),
+                        "block at ({}, {}, {}) of {}",
+                        x,
+                        y,
+                        z,
+                        path
+                    );
+                }
+            }
+        }
+    }
+}
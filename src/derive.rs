@@ -0,0 +1,394 @@
+use crate::{
+    sim::{
+        ComponentSimulator, TruthTable, evaluate_component_logic, input_combinations,
+        load_component_model,
+    },
+    *,
+};
+
+/*
+从元件的nbt推导它的"sim"表达式。
+
+每个输入取低(0)或高(15)，枚举所有组合，用方块级仿真得到每个输出的能量。
+每个输出按能量分组，每组的输入组合用Quine-McCluskey化简成与或式:
+    只有0和15:      "input0 & !input1 | input2"
+    高电平不是15:   "input0 & input1 ? 13 : 0"
+    多种能量:       "input0 ? 15 : input1 ? 12 : 0"
+*/
+
+///最多枚举的输入个数，每个组合都要做一次方块级仿真
+pub const MAX_DERIVE_INPUTS: usize = 12;
+
+///## Implicant
+/// 与项，mask中为1的位是不关心的变量
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Implicant {
+    value: u32,
+    mask: u32,
+}
+impl Implicant {
+    fn covers(&self, minterm: u32) -> bool {
+        minterm & !self.mask == self.value
+    }
+    ///只差一个变量的两项合并
+    fn merge(&self, other: &Implicant) -> Option<Implicant> {
+        let diff = self.value ^ other.value;
+        if self.mask == other.mask && diff.count_ones() == 1 {
+            Some(Implicant {
+                value: self.value & !diff,
+                mask: self.mask | diff,
+            })
+        } else {
+            None
+        }
+    }
+    ///写成表达式，第一个输入是最高位
+    fn to_expr(self, names: &[String]) -> String {
+        let n = names.len();
+        let literals: Vec<String> = names
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.mask >> (n - 1 - i) & 1 == 0)
+            .map(|(i, name)| {
+                if self.value >> (n - 1 - i) & 1 == 1 {
+                    name.clone()
+                } else {
+                    format!("!{}", name)
+                }
+            })
+            .collect();
+        if literals.is_empty() {
+            "15".to_string()
+        } else {
+            literals.join(" & ")
+        }
+    }
+}
+
+///求所有质蕴涵项
+fn prime_implicants(minterms: &[u32]) -> Vec<Implicant> {
+    let mut current: Vec<Implicant> = minterms
+        .iter()
+        .map(|m| Implicant { value: *m, mask: 0 })
+        .collect();
+    let mut primes = Vec::new();
+    while !current.is_empty() {
+        let mut merged = vec![false; current.len()];
+        let mut next: Vec<Implicant> = Vec::new();
+        for i in 0..current.len() {
+            for j in i + 1..current.len() {
+                if let Some(m) = current[i].merge(&current[j]) {
+                    merged[i] = true;
+                    merged[j] = true;
+                    if !next.contains(&m) {
+                        next.push(m);
+                    }
+                }
+            }
+        }
+        primes.extend(
+            current
+                .iter()
+                .zip(merged)
+                .filter(|(_, m)| !m)
+                .map(|(imp, _)| *imp),
+        );
+        current = next;
+    }
+    primes
+}
+
+///化简成与或式。先选必要质蕴涵项，剩下的最小项贪心地选覆盖最多的项
+pub fn minimize(minterms: &[u32], names: &[String]) -> String {
+    if minterms.is_empty() {
+        return "0".to_string();
+    }
+    let primes = prime_implicants(minterms);
+    let mut uncovered: Vec<u32> = minterms.to_vec();
+    let mut chosen: Vec<Implicant> = Vec::new();
+    for m in minterms {
+        let covering: Vec<&Implicant> = primes.iter().filter(|p| p.covers(*m)).collect();
+        if covering.len() == 1 && !chosen.contains(covering[0]) {
+            chosen.push(*covering[0]);
+        }
+    }
+    uncovered.retain(|m| !chosen.iter().any(|p| p.covers(*m)));
+    while !uncovered.is_empty() {
+        let best = *primes
+            .iter()
+            .max_by_key(|p| uncovered.iter().filter(|m| p.covers(**m)).count())
+            .unwrap();
+        chosen.push(best);
+        uncovered.retain(|m| !best.covers(*m));
+    }
+    //固定顺序，让结果可重复
+    chosen.sort_by_key(|p| (p.mask, std::cmp::Reverse(p.value)));
+    chosen
+        .iter()
+        .map(|p| p.to_expr(names))
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+///由一个输出在每个输入组合下的能量生成表达式，powers的下标是输入组合
+fn synthesize_output(powers: &[i32], names: &[String]) -> String {
+    let mut levels: Vec<i32> = powers.iter().copied().filter(|p| *p > 0).collect();
+    levels.sort_unstable_by(|a, b| b.cmp(a));
+    levels.dedup();
+    let minterms_of = |level: i32| -> Vec<u32> {
+        (0..powers.len() as u32)
+            .filter(|i| powers[*i as usize] == level)
+            .collect()
+    };
+    match levels.as_slice() {
+        [] => "0".to_string(),
+        [15] => minimize(&minterms_of(15), names),
+        _ => {
+            //从高到低逐个判断，最后是0
+            let mut expr = "0".to_string();
+            for level in levels.iter().rev() {
+                let cond = minimize(&minterms_of(*level), names);
+                expr = if cond == "15" {
+                    level.to_string()
+                } else {
+                    format!("{} ? {} : {}", cond, level, expr)
+                };
+            }
+            expr
+        }
+    }
+}
+
+///## 推导元件的sim
+/// 用方块级仿真枚举元件的所有输入组合，返回真值表和每个输出的表达式
+pub fn derive_component_sim(
+    model: &ComponentModelObject,
+    library: Option<&str>,
+) -> (TruthTable, BTreeMap<String, String>) {
+    let names: Vec<String> = model.inputs.iter().map(|p| p.name.clone()).collect();
+    let n = names.len();
+    if n > MAX_DERIVE_INPUTS {
+        error_begin();
+        panic!(
+            "component {} has {} inputs, at most {} can be enumerated",
+            model.name, n, MAX_DERIVE_INPUTS
+        );
+    }
+    if let Some(name) = names
+        .iter()
+        .find(|name| !matches!(Expr::parse(name), Ok(Expr::Variable(_))))
+    {
+        error_begin();
+        panic!("input {} of component {} can't be used as a variable in sim expressions", name, model.name);
+    }
+    let mut table = TruthTable::new(
+        names.clone(),
+        model.outputs.iter().map(|p| p.name.clone()).collect(),
+    );
+    //每个输出在每个输入组合下的能量，第一个输入是最高位
+    let mut powers: Vec<Vec<i32>> = vec![Vec::new(); model.outputs.len()];
+    let simulator = ComponentSimulator::new(model, library);
+    for values in input_combinations(n) {
+        let inputs: HashMap<String, i32> = names.iter().cloned().zip(values.iter().copied()).collect();
+        let result = simulator.simulate(&inputs);
+        let outputs: Vec<i32> = model.outputs.iter().map(|p| result[&p.name]).collect();
+        for (i, power) in outputs.iter().enumerate() {
+            powers[i].push(*power);
+        }
+        table.push(values, outputs);
+    }
+    let mut sim = BTreeMap::new();
+    for (port, powers) in model.outputs.iter().zip(powers.iter()) {
+        let source = synthesize_output(powers, &names);
+        //推导出的表达式必须和仿真结果一致
+        let expr = Expr::parse(&source).expect("fatal: derived sim expression failed to parse");
+        for (values, power) in input_combinations(n).zip(powers.iter()) {
+            let lookup = |name: &str| values[names.iter().position(|n| n == name).unwrap()];
+            assert_eq!(
+                expr.eval(&lookup).clamp(0, 15),
+                *power,
+                "fatal: derived sim expression {} of {}.{} disagrees with the simulation",
+                source,
+                model.name,
+                port.name
+            );
+        }
+        sim.insert(port.name.clone(), source);
+    }
+    (table, sim)
+}
+
+///声明的sim和推导的sim计算结果不同的输入组合，每条是"输入: 输出 声明的能量 -> 推导的能量"
+fn sim_disagreements(model: &ComponentModelObject, derived: &BTreeMap<String, String>) -> Vec<String> {
+    if model.sim.is_none() {
+        return vec![];
+    }
+    let mut derived_model = model.clone();
+    derived_model.sim = Some(derived.clone());
+    let mut disagreements = vec![];
+    for values in input_combinations(model.inputs.len()) {
+        let declared = evaluate_component_logic(model, &values);
+        let derived = evaluate_component_logic(&derived_model, &values);
+        for (port, (a, b)) in model.outputs.iter().zip(declared.iter().zip(derived.iter())) {
+            if a != b {
+                disagreements.push(format!("{:?}: {} {} -> {}", values, port.name, a, b));
+            }
+        }
+    }
+    disagreements
+}
+
+///推导元件json中的sim并写到output_path。
+/// 声明的sim和推导的结果不一致时，只有force为true才覆盖
+pub fn derive_sim_to_file(model_path: &str, output_path: &str, library: Option<&str>, force: bool) {
+    let mut model = load_component_model(model_path);
+    let (table, sim) = derive_component_sim(&model, library);
+    print!("{}", table.to_pretty());
+    for (name, expr) in sim.iter() {
+        println!("  {}: {}", name, expr);
+    }
+    let disagreements = sim_disagreements(&model, &sim);
+    if !disagreements.is_empty() {
+        let declared = model.sim.as_ref().unwrap();
+        println!("the declared sim of {} disagrees with its blocks:", model.name);
+        for (name, expr) in declared.iter() {
+            println!("  - {}: {}", name, expr);
+        }
+        for line in disagreements.iter() {
+            println!("  {}", line);
+        }
+        if !force {
+            error_begin();
+            panic!(
+                "refusing to replace the declared sim of {}, check the nbt or use --force",
+                model.name
+            );
+        }
+        println!("replacing the declared sim of {}", model.name);
+    }
+    model.sim = Some(sim);
+    let json = serde_json::to_string_pretty(&model).expect("fatal: ComponentModelObject failed to_string");
+    std::fs::write(output_path, json).unwrap_or_else(|e| {
+        error_begin();
+        panic!("failed to write component json file {}: {}", output_path, e);
+    });
+    println!("Component json file saved to {}", output_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{LIBRARY, library_and_with_sim};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn implicants_merge_only_with_the_same_dont_cares() {
+        let a = Implicant {
+            value: 0b000,
+            mask: 0b001,
+        };
+        let b = Implicant {
+            value: 0b010,
+            mask: 0b001,
+        };
+        assert_eq!(
+            a.merge(&b),
+            Some(Implicant {
+                value: 0b000,
+                mask: 0b011
+            })
+        );
+        let c = Implicant {
+            value: 0b010,
+            mask: 0b100,
+        };
+        assert_eq!(a.merge(&c), None);
+        assert!(a.covers(0b001) && !a.covers(0b010));
+    }
+
+    #[test]
+    fn prime_implicants_of_a_cover() {
+        //a & !c | b：0b100, 0b110, 0b010, 0b011, 0b111
+        let mut primes = prime_implicants(&[2, 3, 4, 6, 7]);
+        primes.sort_by_key(|p| (p.mask, p.value));
+        assert_eq!(
+            primes,
+            [
+                Implicant {
+                    value: 0b100,
+                    mask: 0b010
+                },
+                Implicant {
+                    value: 0b010,
+                    mask: 0b101
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn minimize_covers() {
+        let abc = names(&["a", "b", "c"]);
+        assert_eq!(minimize(&[], &abc), "0");
+        assert_eq!(minimize(&[2, 3, 4, 6, 7], &abc), "a & !c | b");
+        assert_eq!(minimize(&[4, 5, 6, 7], &abc), "a");
+        assert_eq!(minimize(&[0, 1, 2, 3, 4, 5, 6], &abc), "!a | !b | !c");
+        //所有组合都为高
+        assert_eq!(minimize(&(0..8).collect::<Vec<u32>>(), &abc), "15");
+    }
+
+    #[test]
+    fn levels_become_a_conditional_chain() {
+        let ab = names(&["a", "b"]);
+        assert_eq!(synthesize_output(&[0, 0, 0, 0], &ab), "0");
+        assert_eq!(synthesize_output(&[0, 15, 15, 15], &ab), "a | b");
+        assert_eq!(synthesize_output(&[0, 0, 0, 13], &ab), "a & b ? 13 : 0");
+        assert_eq!(
+            synthesize_output(&[0, 12, 15, 15], &ab),
+            "a ? 15 : !a & b ? 12 : 0"
+        );
+        assert_eq!(
+            synthesize_output(&[7, 12, 15, 15], &ab),
+            "a ? 15 : !a & b ? 12 : !a & !b ? 7 : 0"
+        );
+    }
+
+    #[test]
+    fn derive_library_and_gate() {
+        let model = load_component_model(&format!("{}/and.json", LIBRARY));
+        let (table, sim) = derive_component_sim(&model, Some(LIBRARY));
+        assert_eq!(
+            table.to_csv(),
+            "input0,input1,output\n0,0,0\n0,15,0\n15,0,0\n15,15,15\n"
+        );
+        assert_eq!(sim["output"], "input0 & input1");
+        assert!(sim_disagreements(&model, &sim).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "refusing to replace the declared sim of and")]
+    fn disagreeing_sim_is_not_replaced() {
+        let path = library_and_with_sim("derive", "disagreeing", "input0 | input1");
+        derive_sim_to_file(&path, &path, Some(LIBRARY), false);
+    }
+
+    #[test]
+    fn disagreeing_sim_is_replaced_with_force() {
+        let path = library_and_with_sim("derive", "force", "input0 | input1");
+        let model = load_component_model(&path);
+        let derived = BTreeMap::from([("output".to_string(), "input0 & input1".to_string())]);
+        assert_eq!(
+            sim_disagreements(&model, &derived),
+            ["[0, 15]: output 15 -> 0", "[15, 0]: output 15 -> 0"]
+        );
+        derive_sim_to_file(&path, &path, Some(LIBRARY), true);
+        assert_eq!(
+            load_component_model(&path).sim.unwrap()["output"],
+            "input0 & input1"
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use ansi_term::Color::{Green, Red, Yellow};
+use serde::Serialize;
+
+use crate::{
+    sim::{ComponentSimulator, evaluate_component_logic, input_combinations, load_component_model},
+    *,
+};
+
+/*
+比较元件声明的逻辑(sim表达式或内置的and/or/not)和它的nbt的方块级仿真。
+
+每个输入取低(0)或高(15)，枚举所有组合，两边的每个输出都要相同。
+高低电平不同是逻辑错误；都是高电平但能量不同只是信号强度不同，比如输出端的红石线衰减了。
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+///不一致的种类
+enum MismatchKind {
+    ///高低电平不同
+    Level,
+    ///都是高电平，能量不同
+    Strength,
+}
+
+#[derive(Debug, Clone, Serialize)]
+///一个输入组合下不一致的输出
+struct Mismatch {
+    inputs: BTreeMap<String, i32>,
+    output: String,
+    kind: MismatchKind,
+    ///声明的逻辑计算出的能量
+    logic: i32,
+    ///方块级仿真得到的能量
+    block: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+///一个元件的比较结果
+struct ComponentEquivalence {
+    name: String,
+    path: String,
+    vectors: usize,
+    mismatches: Vec<Mismatch>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+///## EquivalenceReport
+/// 所有元件的比较结果
+pub struct EquivalenceReport {
+    ///有高低电平不一致的元件数
+    pub failed: usize,
+    components: Vec<ComponentEquivalence>,
+}
+
+///比较一个元件，nbt不存在或输入太多时返回None
+fn check_component(path: &str, library: Option<&str>) -> Option<ComponentEquivalence> {
+    let model = load_component_model(path);
+    let nbt_path = library_path(library, &model.nbt);
+    if !std::path::Path::new(&nbt_path).exists() {
+        println!(
+            "warning: nbt file {} of component {} not found, skipped",
+            nbt_path, model.name
+        );
+        return None;
+    }
+    let n = model.inputs.len();
+    if n > MAX_DERIVE_INPUTS {
+        println!(
+            "warning: component {} has {} inputs, at most {} can be enumerated, skipped",
+            model.name, n, MAX_DERIVE_INPUTS
+        );
+        return None;
+    }
+    let mut result = ComponentEquivalence {
+        name: model.name.clone(),
+        path: path.to_string(),
+        vectors: 1 << n,
+        mismatches: Vec::new(),
+    };
+    let simulator = ComponentSimulator::new(&model, library);
+    for values in input_combinations(n) {
+        let inputs: BTreeMap<String, i32> = model
+            .inputs
+            .iter()
+            .map(|p| p.name.clone())
+            .zip(values.iter().copied())
+            .collect();
+        let logic = evaluate_component_logic(&model, &values);
+        let block = simulator.simulate(&inputs.clone().into_iter().collect());
+        for (port, logic) in model.outputs.iter().zip(logic) {
+            let block = block[&port.name];
+            if logic == block {
+                continue;
+            }
+            let kind = if (logic > 0) == (block > 0) {
+                MismatchKind::Strength
+            } else {
+                MismatchKind::Level
+            };
+            result.mismatches.push(Mismatch {
+                inputs: inputs.clone(),
+                output: port.name.clone(),
+                kind,
+                logic,
+                block,
+            });
+        }
+    }
+    Some(result)
+}
+
+///打印一个元件的比较结果，类似diff，-为声明的逻辑，+为方块级仿真
+fn print_component(result: &ComponentEquivalence) {
+    if result.mismatches.is_empty() {
+        println!(
+            "{} {}: {} vectors agree",
+            Green.paint("OK"),
+            result.name,
+            result.vectors
+        );
+        return;
+    }
+    let level = result
+        .mismatches
+        .iter()
+        .any(|m| m.kind == MismatchKind::Level);
+    println!(
+        "{} {}: {} mismatching output(s) in {} vectors",
+        if level {
+            Red.paint("FAIL")
+        } else {
+            Yellow.paint("WARN")
+        },
+        result.name,
+        result.mismatches.len(),
+        result.vectors
+    );
+    for m in result.mismatches.iter() {
+        println!(
+            "  {} ({}):",
+            m.inputs
+                .iter()
+                .map(|(name, power)| format!("{}={}", name, power))
+                .collect::<Vec<String>>()
+                .join(" "),
+            if m.kind == MismatchKind::Level {
+                "level"
+            } else {
+                "strength"
+            }
+        );
+        println!("{}", Red.paint(format!("  - {}: {}", m.output, m.logic)));
+        println!("{}", Green.paint(format!("  + {}: {}", m.output, m.block)));
+    }
+}
+
+///## 检查元件逻辑和方块是否一致
+/// component为None时检查library下的所有元件
+pub fn check_equivalence(component: Option<&str>, library: Option<&str>) -> EquivalenceReport {
+    let paths = match component {
+        Some(path) => vec![path.to_string()],
+        None => library_component_files(library),
+    };
+    let mut report = EquivalenceReport {
+        failed: 0,
+        components: Vec::new(),
+    };
+    for path in paths {
+        let Some(result) = check_component(&path, library) else {
+            continue;
+        };
+        print_component(&result);
+        if result
+            .mismatches
+            .iter()
+            .any(|m| m.kind == MismatchKind::Level)
+        {
+            report.failed += 1;
+        }
+        report.components.push(result);
+    }
+    println!(
+        "{} of {} components agree with their blocks",
+        report.components.len() - report.failed,
+        report.components.len()
+    );
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{LIBRARY, library_and_with_sim};
+
+    fn kinds(report: &EquivalenceReport) -> Vec<(Vec<i32>, MismatchKind, i32, i32)> {
+        report.components[0]
+            .mismatches
+            .iter()
+            .map(|m| {
+                (
+                    m.inputs.values().copied().collect(),
+                    m.kind,
+                    m.logic,
+                    m.block,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn library_and_gate_agrees() {
+        let report = check_equivalence(Some(&format!("{}/and.json", LIBRARY)), Some(LIBRARY));
+        assert_eq!(report.failed, 0);
+        assert_eq!(report.components.len(), 1);
+        assert_eq!(report.components[0].vectors, 4);
+        assert!(report.components[0].mismatches.is_empty());
+    }
+
+    #[test]
+    fn wrong_logic_is_a_level_mismatch() {
+        let path = library_and_with_sim("equivalence", "level", "input0 | input1");
+        let report = check_equivalence(Some(&path), Some(LIBRARY));
+        assert_eq!(report.failed, 1);
+        assert_eq!(
+            kinds(&report),
+            [
+                (vec![0, 15], MismatchKind::Level, 15, 0),
+                (vec![15, 0], MismatchKind::Level, 15, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn weaker_output_is_a_strength_mismatch() {
+        let path = library_and_with_sim("equivalence", "strength", "input0 & input1 ? 13 : 0");
+        let report = check_equivalence(Some(&path), Some(LIBRARY));
+        assert_eq!(report.failed, 0);
+        assert_eq!(
+            kinds(&report),
+            [(vec![15, 15], MismatchKind::Strength, 13, 15)]
+        );
+    }
+}
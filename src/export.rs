@@ -0,0 +1,759 @@
+use std::fs::File;
+
+use clap::ValueEnum;
+use fastnbt::{ByteArray, SerOpts, Value};
+use flate2::{Compression, write::GzEncoder};
+use mc_schem::{
+    Block, Region, Schematic,
+    region::{BlockEntity, WorldSlice},
+    schem::{DataVersion, LitematicaSaveOption, VanillaStructureSaveOption, WorldEdit13SaveOption},
+};
+
+use crate::*;
+
+///## OutputFormat
+/// 编译结果的保存格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    ///Litematica .litematic
+    Litematic,
+    ///原版结构方块 .nbt
+    Nbt,
+    ///Sponge .schem，版本由数据版本决定(1.20之前为v2，之后为v3)
+    Schem,
+    ///Sponge .schem v2
+    SchemV2,
+    ///Sponge .schem v3
+    SchemV3,
+    ///MCEdit .schematic，使用1.12.2的数字方块id
+    Schematic,
+    ///.mcfunction，由setblock和fill命令组成
+    Mcfunction,
+    ///包含mcfunction的数据包文件夹
+    Datapack,
+    ///直接写入存档的region文件，output_path为存档文件夹
+    World,
+    ///结构化Verilog网表 .v
+    Verilog,
+    ///BLIF网表 .blif
+    Blif,
+}
+impl OutputFormat {
+    ///根据输出文件的扩展名选择格式
+    pub fn from_path(path: &str) -> Option<OutputFormat> {
+        let extension = path.rsplit('.').next()?;
+        match extension {
+            "litematic" | "lithematic" => Some(OutputFormat::Litematic),
+            "nbt" => Some(OutputFormat::Nbt),
+            "schem" => Some(OutputFormat::Schem),
+            "schematic" => Some(OutputFormat::Schematic),
+            "mcfunction" => Some(OutputFormat::Mcfunction),
+            "v" => Some(OutputFormat::Verilog),
+            "blif" => Some(OutputFormat::Blif),
+            _ => None,
+        }
+    }
+    ///是否是网表格式，网表不编译成方块
+    pub fn is_netlist(&self) -> bool {
+        matches!(self, OutputFormat::Verilog | OutputFormat::Blif)
+    }
+}
+
+///## 保存编译结果
+/// format为None时按扩展名选择格式，不认识的扩展名保存为litematic。data_version为None时使用mc_schem支持的最新版本。
+///
+/// anchor是电路原点在世界中的坐标，写入存档时必须指定；
+/// 对mcfunction和数据包为None时命令使用以执行位置为原点的相对坐标
+pub fn save_schematic(
+    schem: &mut Schematic,
+    output_path: &str,
+    format: Option<OutputFormat>,
+    data_version: Option<i32>,
+    anchor: Option<[i32; 3]>,
+) {
+    //扩展名不认识或者没有扩展名时保存为litematic
+    let format = format
+        .or_else(|| OutputFormat::from_path(output_path))
+        .unwrap_or(OutputFormat::Litematic);
+    if let Some(data_version) = data_version {
+        schem.metadata.mc_data_version = data_version;
+    }
+    let result = match format {
+        OutputFormat::Litematic => {
+            let save_option = LitematicaSaveOption {
+                compress_level: Compression::default(),
+                rename_duplicated_regions: true,
+            };
+            schem.save_litematica_file(output_path, &save_option)
+        }
+        OutputFormat::Nbt => {
+            schem.save_vanilla_structure_file(output_path, &VanillaStructureSaveOption::default())
+        }
+        OutputFormat::Schem => {
+            schem.save_world_edit_13_file(output_path, &WorldEdit13SaveOption::default())
+        }
+        OutputFormat::SchemV2 | OutputFormat::SchemV3 => {
+            let option = WorldEdit13SaveOption::default();
+            match schem.metadata_world_edit_13() {
+                Ok(mut md) => {
+                    let nbt = if format == OutputFormat::SchemV2 {
+                        md.version = 2;
+                        schem.to_nbt_world_edit_13_v2(md, &option)
+                    } else {
+                        md.version = 3;
+                        schem.to_nbt_world_edit_13_v3(md, &option)
+                    };
+                    nbt.map(|nbt| write_gzip_nbt(output_path, &nbt, "Schematic"))
+                }
+                Err(x) => Err(x),
+            }
+        }
+        OutputFormat::Schematic => {
+            let region = schem.to_single_region(&Block::air());
+            write_gzip_nbt(output_path, &mcedit_schematic_nbt(&region), "Schematic");
+            Ok(())
+        }
+        OutputFormat::Mcfunction => {
+            let region = schem.to_single_region(&Block::air());
+            write_text(
+                output_path,
+                &mcfunction_commands(&region, anchor).join("\n"),
+            );
+            Ok(())
+        }
+        OutputFormat::Datapack => {
+            let region = schem.to_single_region(&Block::air());
+            write_datapack(output_path, &region, anchor, schem.metadata.mc_data_version);
+            Ok(())
+        }
+        OutputFormat::World => {
+            let anchor = anchor.unwrap_or_else(|| {
+                error_begin();
+                panic!("--anchor is required to write into a world");
+            });
+            let region = schem.to_single_region(&Block::air());
+            write_to_world(output_path, &region, anchor);
+            Ok(())
+        }
+        OutputFormat::Verilog | OutputFormat::Blif => {
+            error_begin();
+            panic!("{:?} is a netlist format, use export_netlist", format);
+        }
+    };
+    result.unwrap_or_else(|x| {
+        error_begin();
+        panic!("failed to save schematic file {}: {}", output_path, x);
+    });
+}
+
+///把nbt以gzip压缩写入文件
+fn write_gzip_nbt(path: &str, nbt: &HashMap<String, Value>, root_name: &str) {
+    let file = File::create(path).unwrap_or_else(|_| {
+        error_begin();
+        panic!("failed to create output file {}", path);
+    });
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    fastnbt::to_writer_with_opts(&mut encoder, nbt, SerOpts::new().root_name(root_name))
+        .expect("failed to write nbt");
+    encoder.finish().expect("failed to write nbt");
+}
+
+///写入文本文件，必要时创建上级目录
+fn write_text(path: &str, content: &str) {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent).ok();
+    }
+    std::fs::write(path, content.to_owned() + "\n").unwrap_or_else(|_| {
+        error_begin();
+        panic!("failed to write output file {}", path);
+    });
+}
+
+///fill命令一次最多填充的方块数
+const MAX_FILL_VOLUME: i32 = 32768;
+
+///## mcfunction
+/// 把region转成setblock/fill命令，相同方块组成的长方体合并成一条fill。
+///
+/// 空气也会被写入，效果和粘贴schematic一样会清空原有的方块。
+/// 有方块实体的方块(比较器、容器等)单独用带NBT的setblock放置。
+/// 命令按y从低到高排列，保证红石线、火把等方块放置时下方已经有支撑。
+fn mcfunction_commands(region: &Region, anchor: Option<[i32; 3]>) -> Vec<String> {
+    let shape = region.shape();
+    let coordinate = |pos: [i32; 3]| match anchor {
+        Some(anchor) => format!(
+            "{} {} {}",
+            anchor[0] + pos[0],
+            anchor[1] + pos[1],
+            anchor[2] + pos[2]
+        ),
+        None => format!("~{} ~{} ~{}", pos[0], pos[1], pos[2]),
+    };
+    let block = |pos: [i32; 3]| region.block_at(pos).unwrap();
+    let mut done = vec![false; (shape[0] * shape[1] * shape[2]) as usize];
+    let index = |pos: [i32; 3]| ((pos[1] * shape[2] + pos[2]) * shape[0] + pos[0]) as usize;
+    let mut commands = vec![];
+    for y in 0..shape[1] {
+        for z in 0..shape[2] {
+            for x in 0..shape[0] {
+                if done[index([x, y, z])] {
+                    continue;
+                }
+                let current = block([x, y, z]);
+                if let Some(entity) = region.block_entities.get(&[x, y, z]) {
+                    done[index([x, y, z])] = true;
+                    commands.push(format!(
+                        "setblock {} {}{}",
+                        coordinate([x, y, z]),
+                        current.full_id(),
+                        block_entity_snbt(entity)
+                    ));
+                    continue;
+                }
+                //长方体扩展到end时新增的一层(axis方向上坐标为end[axis])都和current相同，
+                //没有被放置，也没有方块实体
+                let layer_fits = |end: [i32; 3], axis: usize, done: &Vec<bool>| {
+                    let mut start = [x, y, z];
+                    start[axis] = end[axis];
+                    (start[1]..=end[1]).all(|by| {
+                        (start[2]..=end[2]).all(|bz| {
+                            (start[0]..=end[0]).all(|bx| {
+                                !done[index([bx, by, bz])]
+                                    && block([bx, by, bz]) == current
+                                    && !region.block_entities.contains_key(&[bx, by, bz])
+                            })
+                        })
+                    })
+                };
+                //依次沿x、z、y扩展
+                let mut end = [x, y, z];
+                for axis in [0, 2, 1] {
+                    loop {
+                        let mut next = end;
+                        next[axis] += 1;
+                        if next[axis] >= shape[axis]
+                            || (next[0] - x + 1) * (next[1] - y + 1) * (next[2] - z + 1)
+                                > MAX_FILL_VOLUME
+                            || !layer_fits(next, axis, &done)
+                        {
+                            break;
+                        }
+                        end = next;
+                    }
+                }
+                for by in y..=end[1] {
+                    for bz in z..=end[2] {
+                        for bx in x..=end[0] {
+                            done[index([bx, by, bz])] = true;
+                        }
+                    }
+                }
+                if end == [x, y, z] {
+                    commands.push(format!(
+                        "setblock {} {}",
+                        coordinate([x, y, z]),
+                        current.full_id()
+                    ));
+                } else {
+                    commands.push(format!(
+                        "fill {} {} {}",
+                        coordinate([x, y, z]),
+                        coordinate(end),
+                        current.full_id()
+                    ));
+                }
+            }
+        }
+    }
+    commands
+}
+
+///方块实体的标签写成setblock后面的SNBT，坐标和id由命令决定，不写入
+fn block_entity_snbt(entity: &BlockEntity) -> String {
+    let tags = entity
+        .tags
+        .iter()
+        .filter(|(key, _)| !["x", "y", "z", "id", "keepPacked"].contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    snbt(&Value::Compound(tags))
+}
+
+///## SNBT
+/// NBT的文本形式，用于命令。复合标签的键按字母顺序排列
+fn snbt(value: &Value) -> String {
+    let join = |items: Vec<String>| items.join(",");
+    match value {
+        Value::Byte(v) => format!("{}b", v),
+        Value::Short(v) => format!("{}s", v),
+        Value::Int(v) => v.to_string(),
+        Value::Long(v) => format!("{}L", v),
+        Value::Float(v) => format!("{}f", v),
+        Value::Double(v) => format!("{}d", v),
+        Value::String(v) => snbt_string(v),
+        Value::ByteArray(v) => format!(
+            "[B;{}]",
+            join(v.iter().map(|b| format!("{}b", b)).collect())
+        ),
+        Value::IntArray(v) => format!("[I;{}]", join(v.iter().map(|i| i.to_string()).collect())),
+        Value::LongArray(v) => format!(
+            "[L;{}]",
+            join(v.iter().map(|l| format!("{}L", l)).collect())
+        ),
+        Value::List(v) => format!("[{}]", join(v.iter().map(snbt).collect())),
+        Value::Compound(v) => {
+            let mut keys: Vec<&String> = v.keys().collect();
+            keys.sort();
+            let entries = keys
+                .into_iter()
+                .map(|key| {
+                    let plain = !key.is_empty()
+                        && key
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "_-.+".contains(c));
+                    let name = if plain { key.clone() } else { snbt_string(key) };
+                    format!("{}:{}", name, snbt(&v[key]))
+                })
+                .collect();
+            format!("{{{}}}", join(entries))
+        }
+    }
+}
+
+///SNBT中带引号的字符串
+fn snbt_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+///## 数据包
+/// output_path为数据包文件夹，包含pack.mcmeta和data/circuits/function(s)/<名字>.mcfunction，
+/// 名字取文件夹名，游戏中用 /function circuits:<名字> 放置
+fn write_datapack(output_path: &str, region: &Region, anchor: Option<[i32; 3]>, data_version: i32) {
+    let name: String = std::path::Path::new(output_path)
+        .file_name()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = if name.is_empty() {
+        "circuit".to_string()
+    } else {
+        name
+    };
+    let (pack_format, function_dir) = pack_format_of(data_version);
+    let mcmeta = serde_json::json!({
+        "pack": {
+            "pack_format": pack_format,
+            "description": format!("circuit {}", name),
+        }
+    });
+    write_text(
+        &format!("{}/pack.mcmeta", output_path),
+        &serde_json::to_string_pretty(&mcmeta).unwrap(),
+    );
+    let function_path = format!(
+        "{}/data/circuits/{}/{}.mcfunction",
+        output_path, function_dir, name
+    );
+    write_text(
+        &function_path,
+        &mcfunction_commands(region, anchor).join("\n"),
+    );
+    println!("run /function circuits:{} to place the circuit", name);
+}
+
+///数据版本对应的数据包格式，以及函数文件夹的名字(1.21起改为function)
+fn pack_format_of(data_version: i32) -> (i32, &'static str) {
+    //(最低数据版本, pack_format)
+    const PACK_FORMATS: [(i32, i32); 12] = [
+        (3953, 48), //1.21
+        (3837, 41), //1.20.5
+        (3698, 26), //1.20.3
+        (3578, 18), //1.20.2
+        (3463, 15), //1.20
+        (3337, 12), //1.19.4
+        (3105, 10), //1.19
+        (2975, 9),  //1.18.2
+        (2860, 8),  //1.18
+        (2724, 7),  //1.17
+        (2578, 6),  //1.16.2
+        (2225, 5),  //1.15
+    ];
+    let pack_format = PACK_FORMATS
+        .iter()
+        .find(|(version, _)| data_version >= *version)
+        .map(|(_, format)| *format)
+        .unwrap_or(4);
+    let function_dir = if data_version >= 3953 {
+        "function"
+    } else {
+        "functions"
+    };
+    (pack_format, function_dir)
+}
+
+///## MCEdit .schematic
+/// 方块按y、z、x的顺序存储为1.12.2的数字id和数据值。
+///
+/// 新版方块通过mc_schem的旧方块转换表反查，找不到对应的方块时以空气代替。
+fn mcedit_schematic_nbt(region: &Region) -> HashMap<String, Value> {
+    let legacy_table = legacy_block_table();
+    let shape = region.shape();
+    let mut blocks = vec![];
+    let mut data = vec![];
+    let mut unsupported: BTreeMap<String, u64> = BTreeMap::new();
+    for y in 0..shape[1] {
+        for z in 0..shape[2] {
+            for x in 0..shape[0] {
+                let block = region.block_at([x, y, z]).unwrap();
+                let (id, damage) = if block.is_air() || block.is_structure_void() {
+                    (0, 0)
+                } else {
+                    legacy_id_of(&to_legacy_name(block), &legacy_table).unwrap_or_else(|| {
+                        *unsupported.entry(block.full_id()).or_insert(0) += 1;
+                        (0, 0)
+                    })
+                };
+                blocks.push(id as i8);
+                data.push(damage as i8);
+            }
+        }
+    }
+    for (block, count) in unsupported.iter() {
+        println!(
+            "warning: {} has no 1.12.2 block id, {} block(s) replaced with air",
+            block, count
+        );
+    }
+    let mut nbt = HashMap::new();
+    nbt.insert("Width".to_string(), Value::Short(shape[0] as i16));
+    nbt.insert("Height".to_string(), Value::Short(shape[1] as i16));
+    nbt.insert("Length".to_string(), Value::Short(shape[2] as i16));
+    nbt.insert("Materials".to_string(), Value::String("Alpha".to_string()));
+    nbt.insert(
+        "Blocks".to_string(),
+        Value::ByteArray(ByteArray::new(blocks)),
+    );
+    nbt.insert("Data".to_string(), Value::ByteArray(ByteArray::new(data)));
+    for letter in ["X", "Y", "Z"] {
+        nbt.insert(format!("WEOffset{}", letter), Value::Int(0));
+        nbt.insert(format!("WEOrigin{}", letter), Value::Int(0));
+    }
+    nbt.insert("Entities".to_string(), Value::List(vec![]));
+    nbt.insert("TileEntities".to_string(), Value::List(vec![]));
+    nbt
+}
+
+///所有1.12.2数字id和数据值对应的新版方块
+fn legacy_block_table() -> Vec<(Block, u8, u8)> {
+    let mut table = vec![];
+    for id in 0..=255u8 {
+        for damage in 0..16u8 {
+            if let Ok(block) = Block::from_old(id, damage, DataVersion::Java_1_12_2) {
+                table.push((block, id, damage));
+            }
+        }
+    }
+    table
+}
+
+///16种颜色在1.12.2中的名字
+const LEGACY_COLORS: [&str; 16] = [
+    "white",
+    "orange",
+    "magenta",
+    "light_blue",
+    "yellow",
+    "lime",
+    "pink",
+    "gray",
+    "silver",
+    "cyan",
+    "purple",
+    "blue",
+    "brown",
+    "green",
+    "red",
+    "black",
+];
+
+///把1.13之后的方块改写成1.12.2的名字和属性，常用的红石元件和建筑方块在这里对应，
+/// 其余方块保持原样，如果两个版本的名字相同也能找到
+fn to_legacy_name(block: &Block) -> Block {
+    let mut legacy = block.clone();
+    let attr = |key: &str| block.attributes.get(key).map(|v| v.as_str());
+    let on = |key: &str| attr(key) == Some("true");
+    let rename = |legacy: &mut Block, id: &str, keep: &[&str]| {
+        legacy.id = id.to_string();
+        legacy.attributes.retain(|k, _| keep.contains(&k.as_str()));
+    };
+    match block.id.as_str() {
+        "repeater" => {
+            let id = if on("powered") {
+                "powered_repeater"
+            } else {
+                "unpowered_repeater"
+            };
+            rename(&mut legacy, id, &["facing", "delay"]);
+        }
+        "comparator" => {
+            let id = if on("powered") {
+                "powered_comparator"
+            } else {
+                "unpowered_comparator"
+            };
+            rename(&mut legacy, id, &["facing", "mode", "powered"]);
+        }
+        "redstone_torch" | "redstone_wall_torch" => {
+            let id = if attr("lit") == Some("false") {
+                "unlit_redstone_torch"
+            } else {
+                "redstone_torch"
+            };
+            rename(&mut legacy, id, &["facing"]);
+        }
+        "wall_torch" => rename(&mut legacy, "torch", &["facing"]),
+        "redstone_lamp" if on("lit") => rename(&mut legacy, "lit_redstone_lamp", &[]),
+        "note_block" => rename(&mut legacy, "noteblock", &[]),
+        "slime_block" => rename(&mut legacy, "slime", &[]),
+        "terracotta" => rename(&mut legacy, "hardened_clay", &[]),
+        "stone" => legacy.set_property("variant", "stone"),
+        "smooth_stone_slab" | "stone_slab" => {
+            if attr("type") == Some("double") {
+                rename(&mut legacy, "double_stone_slab", &[]);
+            } else {
+                let half = attr("type").unwrap_or("bottom").to_string();
+                rename(&mut legacy, "stone_slab", &[]);
+                legacy.set_property("half", &half);
+            }
+            legacy.set_property("variant", "stone");
+        }
+        id => {
+            //带颜色或木材种类的方块，比如white_wool、oak_planks
+            let color_blocks = [
+                ("_wool", "wool"),
+                ("_concrete", "concrete"),
+                ("_stained_glass", "stained_glass"),
+                ("_terracotta", "stained_hardened_clay"),
+            ];
+            for (suffix, legacy_id) in color_blocks {
+                if let Some(color) = id.strip_suffix(suffix) {
+                    let color = if color == "light_gray" {
+                        "silver"
+                    } else {
+                        color
+                    };
+                    if LEGACY_COLORS.contains(&color) {
+                        rename(&mut legacy, legacy_id, &[]);
+                        legacy.set_property("color", color);
+                    }
+                }
+            }
+            if let Some(wood) = id.strip_suffix("_planks") {
+                rename(&mut legacy, "planks", &[]);
+                legacy.set_property("variant", wood);
+            }
+        }
+    }
+    legacy
+}
+
+///查找方块对应的数字id和数据值。
+///
+/// 完全相同的方块优先，否则取同id且属性最吻合的一个，
+/// 旧版不区分的属性(比如红石线的连接方向)不影响结果。
+fn legacy_id_of(block: &Block, table: &[(Block, u8, u8)]) -> Option<(u8, u8)> {
+    table
+        .iter()
+        .filter(|(old, _, _)| old.id == block.id)
+        //分数相同时取数据值最小的
+        .rev()
+        .max_by_key(|(old, _, _)| {
+            old.attributes
+                .iter()
+                .filter(|(k, v)| block.attributes.get(*k) == Some(*v))
+                .count()
+                * 2
+                + (old.attributes.len() == block.attributes.len()) as usize
+        })
+        .map(|(_, id, damage)| (*id, *damage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_schem::schem::LitematicaLoadOption;
+
+    fn region(shape: [i32; 3], blocks: &[([i32; 3], &str)]) -> Region {
+        let mut region = Region::with_shape(shape);
+        for (pos, id) in blocks {
+            region
+                .set_block(*pos, &Block::from_id(id).unwrap())
+                .unwrap();
+        }
+        region
+    }
+
+    #[test]
+    fn identical_blocks_fill_one_cuboid() {
+        let mut stone = vec![];
+        for x in 0..3 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    stone.push(([x, y, z], "minecraft:stone"));
+                }
+            }
+        }
+        let commands = mcfunction_commands(&region([3, 2, 2], &stone), Some([100, -60, 20]));
+        assert_eq!(commands, ["fill 100 -60 20 102 -59 21 minecraft:stone"]);
+    }
+
+    #[test]
+    fn block_entities_are_set_one_by_one() {
+        let chest = "minecraft:chest[facing=north]";
+        let mut region = region(
+            [2, 1, 2],
+            &[
+                ([0, 0, 0], "minecraft:stone"),
+                ([1, 0, 0], "minecraft:stone"),
+                ([0, 0, 1], chest),
+                ([1, 0, 1], chest),
+            ],
+        );
+        let item = HashMap::from([
+            ("Slot".to_string(), Value::Byte(0)),
+            (
+                "id".to_string(),
+                Value::String("minecraft:redstone".to_string()),
+            ),
+            ("count".to_string(), Value::Int(5)),
+        ]);
+        let tags = HashMap::from([
+            (
+                "id".to_string(),
+                Value::String("minecraft:chest".to_string()),
+            ),
+            ("x".to_string(), Value::Int(0)),
+            (
+                "CustomName".to_string(),
+                Value::String("\"a b\"".to_string()),
+            ),
+            (
+                "Items".to_string(),
+                Value::List(vec![Value::Compound(item)]),
+            ),
+        ]);
+        region
+            .block_entities
+            .insert([0, 0, 1], BlockEntity { tags });
+        region.block_entities.insert(
+            [1, 0, 1],
+            BlockEntity {
+                tags: HashMap::new(),
+            },
+        );
+        assert_eq!(
+            mcfunction_commands(&region, None),
+            [
+                "fill ~0 ~0 ~0 ~1 ~0 ~0 minecraft:stone".to_string(),
+                format!(
+                    r#"setblock ~0 ~0 ~1 {}{{CustomName:"\"a b\"",Items:[{{Slot:0b,count:5,id:"minecraft:redstone"}}]}}"#,
+                    chest
+                ),
+                format!("setblock ~1 ~0 ~1 {}{{}}", chest),
+            ]
+        );
+    }
+
+    ///石头、中继器和橡木木板，保存后读回来检查
+    fn sample_schematic() -> Schematic {
+        let mut schem = Schematic::new();
+        schem.regions.push(region(
+            [3, 2, 2],
+            &[
+                ([0, 0, 0], "minecraft:stone"),
+                (
+                    [1, 1, 0],
+                    "minecraft:repeater[delay=3,facing=east,locked=false,powered=false]",
+                ),
+                ([2, 0, 1], "minecraft:oak_planks"),
+            ],
+        ));
+        schem
+    }
+    fn save(file: &str) -> String {
+        let dir = std::env::temp_dir().join("mc_circuit_script_export_writers");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(file).to_string_lossy().to_string();
+        save_schematic(&mut sample_schematic(), &path, None, None, None);
+        path
+    }
+    fn assert_sample_blocks(region: &Region) {
+        assert_eq!(region.shape(), [3, 2, 2]);
+        let id = |pos: [i32; 3]| region.block_at(pos).unwrap().full_id();
+        assert_eq!(id([0, 0, 0]), "minecraft:stone");
+        assert_eq!(id([2, 0, 1]), "minecraft:oak_planks");
+        assert!(region.block_at([1, 0, 1]).unwrap().is_air());
+        let repeater = region.block_at([1, 1, 0]).unwrap();
+        assert_eq!(repeater.id, "repeater");
+        assert_eq!(repeater.attributes["facing"], "east");
+        assert_eq!(repeater.attributes["delay"], "3");
+    }
+
+    #[test]
+    fn schem_keeps_blocks() {
+        let path = save("sample.schem");
+        let (schem, _) = Schematic::from_file(&path).unwrap();
+        assert_sample_blocks(&schem.regions[0]);
+    }
+
+    #[test]
+    fn nbt_keeps_blocks() {
+        let path = save("sample.nbt");
+        assert_sample_blocks(&load_nbt_region(&path));
+    }
+
+    #[test]
+    fn schematic_uses_legacy_ids() {
+        let path = save("sample.schematic");
+        let file = std::fs::read(&path).unwrap();
+        let mut nbt = vec![];
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&file[..]), &mut nbt).unwrap();
+        let nbt: HashMap<String, Value> = fastnbt::from_bytes(&nbt).unwrap();
+        let bytes = |key: &str| match &nbt[key] {
+            Value::ByteArray(array) => array.iter().map(|b| *b as u8).collect::<Vec<u8>>(),
+            other => panic!("{} is {:?}", key, other),
+        };
+        let (blocks, data) = (bytes("Blocks"), bytes("Data"));
+        assert_eq!(nbt["Width"], Value::Short(3));
+        assert_eq!(nbt["Height"], Value::Short(2));
+        assert_eq!(nbt["Length"], Value::Short(2));
+        //按y、z、x的顺序排列
+        let index = |[x, y, z]: [usize; 3]| (y * 2 + z) * 3 + x;
+        assert_eq!((blocks[index([0, 0, 0])], data[index([0, 0, 0])]), (1, 0));
+        assert_eq!((blocks[index([2, 0, 1])], data[index([2, 0, 1])]), (5, 0));
+        assert_eq!(blocks[index([1, 0, 1])], 0);
+        let repeater = index([1, 1, 0]);
+        let block =
+            Block::from_old(blocks[repeater], data[repeater], DataVersion::Java_1_12_2).unwrap();
+        assert_eq!(block.id, "unpowered_repeater");
+        assert_eq!(block.attributes["delay"], "3");
+    }
+
+    #[test]
+    fn unknown_extension_is_saved_as_litematic() {
+        for file in ["sample.litematic", "sample.out", "sample"] {
+            let path = save(file);
+            let (schem, _) =
+                Schematic::from_litematica_file(&path, &LitematicaLoadOption::default()).unwrap();
+            assert_sample_blocks(&schem.regions[0]);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use crate::*;
+
+/*
+元件json中"sim"部分的表达式，每个输出端口一个，例如:
+"sim":{
+    "output":"input0 & input1",
+    "carry":"max(a,b)-1",
+    "inverted":"15 - in"
+}
+变量是元件的输入端口名，值是端口的红石能量(0-15)。
+
+运算符(优先级从低到高):
+    c ? a : b               条件
+    |   ^   &               逻辑或、异或、与，结果为15或0
+    == != < <= > >=         比较，结果为15或0
+    + -                     加减
+    * / %                   乘除取余
+    ! - (一元)              逻辑非(结果为15或0)、取负
+函数: max(a,b,...) min(a,b,...) clamp(x,lo,hi)
+能量大于0视为高电平。输出最终会被限制在0-15之间。
+加减乘和取负溢出时取i32的最大或最小值，除以0得0。
+*/
+
+///## Expr
+/// 解析后的仿真表达式
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Number(i32),
+    Variable(String),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    ///比较运算，运算符可能是两个字符
+    Compare(String, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Condition(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i32),
+    Ident(String),
+    Op(String),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(
+                text.parse().map_err(|_| format!("number {} is too large", text))?,
+            ));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            //先匹配两个字符的运算符
+            let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let double = match pair.as_str() {
+                //&&和||与&和|相同
+                "&&" => Some("&"),
+                "||" => Some("|"),
+                "==" | "!=" | "<=" | ">=" => Some(pair.as_str()),
+                _ => None,
+            };
+            if let Some(op) = double {
+                tokens.push(Token::Op(op.to_string()));
+                i += 2;
+            } else if "+-*/%&|^!~<>?:(),".contains(c) {
+                //~和!相同
+                tokens.push(Token::Op(if c == '~' { "!".to_string() } else { c.to_string() }));
+                i += 1;
+            } else {
+                return Err(format!("unexpected character '{}'", c));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+///递归下降解析器
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+impl ExprParser {
+    fn peek_op(&self) -> Option<&str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op.as_str()),
+            _ => None,
+        }
+    }
+    ///下一个是给定运算符时取出它
+    fn eat(&mut self, op: &str) -> bool {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.eat(op) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", op))
+        }
+    }
+    fn condition(&mut self) -> Result<Expr, String> {
+        let cond = self.binary(0)?;
+        if self.eat("?") {
+            let a = self.condition()?;
+            self.expect(":")?;
+            let b = self.condition()?;
+            return Ok(Expr::Condition(Box::new(cond), Box::new(a), Box::new(b)));
+        }
+        Ok(cond)
+    }
+    ///按优先级解析二元运算，level越大优先级越高
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        const LEVELS: [&[&str]; 6] = [
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!=", "<", "<=", ">", ">="],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().map(|op| op.to_string()) {
+            if !LEVELS[level].contains(&op.as_str()) {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = if level == 3 {
+                Expr::Compare(op, Box::new(left), Box::new(right))
+            } else {
+                Expr::Binary(op.chars().next().unwrap(), Box::new(left), Box::new(right))
+            };
+        }
+        Ok(left)
+    }
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(Expr::Number(n))
+            }
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                if !self.eat("(") {
+                    return Ok(Expr::Variable(name));
+                }
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.condition()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                let arity_ok = match name.as_str() {
+                    "max" | "min" => !args.is_empty(),
+                    "clamp" => args.len() == 3,
+                    _ => return Err(format!("unknown function {}", name)),
+                };
+                if !arity_ok {
+                    return Err(format!("wrong number of arguments for {}", name));
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Some(Token::Op(op)) if op == "(" => {
+                self.pos += 1;
+                let inner = self.condition()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Some(Token::Op(op)) => Err(format!("unexpected '{}'", op)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn bool_power(value: bool) -> i32 {
+    if value { 15 } else { 0 }
+}
+
+impl Expr {
+    ///解析表达式，失败时返回错误说明
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let mut parser = ExprParser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let expr = parser.condition()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(format!("unexpected token after position {}", parser.pos));
+        }
+        Ok(expr)
+    }
+    ///表达式中用到的所有变量
+    pub fn variables(&self) -> Vec<&str> {
+        let mut vars = Vec::new();
+        self.collect_variables(&mut vars);
+        vars
+    }
+    fn collect_variables<'a>(&'a self, vars: &mut Vec<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Variable(name) => vars.push(name),
+            Expr::Not(e) | Expr::Neg(e) => e.collect_variables(vars),
+            Expr::Binary(_, a, b) | Expr::Compare(_, a, b) => {
+                a.collect_variables(vars);
+                b.collect_variables(vars);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|a| a.collect_variables(vars)),
+            Expr::Condition(c, a, b) => {
+                c.collect_variables(vars);
+                a.collect_variables(vars);
+                b.collect_variables(vars);
+            }
+        }
+    }
+    ///计算表达式，变量的值由lookup给出，未知变量视为0
+    pub fn eval(&self, lookup: &dyn Fn(&str) -> i32) -> i32 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Variable(name) => lookup(name),
+            Expr::Not(e) => bool_power(e.eval(lookup) <= 0),
+            Expr::Neg(e) => e.eval(lookup).saturating_neg(),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(lookup), b.eval(lookup));
+                match op {
+                    '+' => a.saturating_add(b),
+                    '-' => a.saturating_sub(b),
+                    '*' => a.saturating_mul(b),
+                    //除以0得0
+                    '/' => a.checked_div(b).unwrap_or(0),
+                    '%' => a.checked_rem(b).unwrap_or(0),
+                    '&' => bool_power(a > 0 && b > 0),
+                    '|' => bool_power(a > 0 || b > 0),
+                    '^' => bool_power((a > 0) != (b > 0)),
+                    _ => unreachable!("operator {} is not produced by the parser", op),
+                }
+            }
+            Expr::Compare(op, a, b) => {
+                let (a, b) = (a.eval(lookup), b.eval(lookup));
+                bool_power(match op.as_str() {
+                    "==" => a == b,
+                    "!=" => a != b,
+                    "<" => a < b,
+                    "<=" => a <= b,
+                    ">" => a > b,
+                    _ => a >= b,
+                })
+            }
+            Expr::Call(name, args) => {
+                let values = args.iter().map(|a| a.eval(lookup)).collect::<Vec<i32>>();
+                match name.as_str() {
+                    "max" => *values.iter().max().unwrap(),
+                    "min" => *values.iter().min().unwrap(),
+                    _ => values[0].clamp(values[1], values[2].max(values[1])),
+                }
+            }
+            Expr::Condition(c, a, b) => {
+                if c.eval(lookup) > 0 {
+                    a.eval(lookup)
+                } else {
+                    b.eval(lookup)
+                }
+            }
+        }
+    }
+}
+
+///解析一个元件模型的"sim"部分，按输出端口的顺序返回表达式
+///
+/// 表达式只能使用模型的输入端口名作为变量，每个输出端口都必须有表达式
+pub fn parse_model_sim(model: &dyn ModelObject, sim: &BTreeMap<String, String>) -> Vec<Expr> {
+    for name in sim.keys() {
+        if !model.get_outputs().iter().any(|p| &p.name == name) {
+            error_begin();
+            panic!("sim of model {} has an expression for {}, which is not an output", model.get_name(), name);
+        }
+    }
+    model
+        .get_outputs()
+        .iter()
+        .map(|port| {
+            let source = sim.get(&port.name).unwrap_or_else(|| {
+                error_begin();
+                panic!("sim of model {} has no expression for output {}", model.get_name(), port.name);
+            });
+            let expr = Expr::parse(source).unwrap_or_else(|e| {
+                error_begin();
+                panic!("failed to parse sim expression \"{}\" of {}.{}: {}", source, model.get_name(), port.name, e);
+            });
+            if let Some(unknown) = expr
+                .variables()
+                .into_iter()
+                .find(|v| !model.get_inputs().iter().any(|p| p.name == *v))
+            {
+                error_begin();
+                panic!("sim expression of {}.{} uses {}, which is not an input", model.get_name(), port.name, unknown);
+            }
+            expr
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///用a=3、b=12计算表达式
+    fn eval(source: &str) -> i32 {
+        Expr::parse(source).unwrap().eval(&|name| match name {
+            "a" => 3,
+            "b" => 12,
+            _ => 0,
+        })
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("b - a - 1"), 8);
+        assert_eq!(eval("b / a % 3"), 1);
+        //比较比加减低，与比比较低
+        assert_eq!(eval("a + 1 == 4 & b > 10"), 15);
+        assert_eq!(eval("a > 5 | b > 10 ^ 0"), 15);
+        assert_eq!(eval("a > 5 | b > 10 ^ 1"), 0);
+        assert_eq!(eval("a > 5 ? 1 : b > 5 ? 2 : 3"), 2);
+        assert_eq!(
+            Expr::parse("a + b * 2").unwrap(),
+            Expr::Binary(
+                '+',
+                Box::new(Expr::Variable("a".to_string())),
+                Box::new(Expr::Binary(
+                    '*',
+                    Box::new(Expr::Variable("b".to_string())),
+                    Box::new(Expr::Number(2))
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("max(a, b, 7)"), 12);
+        assert_eq!(eval("min(a, b, 7)"), 3);
+        assert_eq!(eval("max(a)"), 3);
+        assert_eq!(eval("clamp(b, 0, 10)"), 10);
+        assert_eq!(eval("max(a, b) - min(a, b)"), 9);
+        assert!(Expr::parse("max()").is_err());
+        assert!(Expr::parse("clamp(a, b)").is_err());
+    }
+
+    #[test]
+    fn unary() {
+        assert_eq!(eval("-a + b"), 9);
+        assert_eq!(eval("--a"), 3);
+        assert_eq!(eval("-(a + b)"), -15);
+        assert_eq!(eval("!a"), 0);
+        assert_eq!(eval("!(a - 3)"), 15);
+        assert_eq!(eval("~b"), 0);
+        assert_eq!(eval("15 - !a * 2"), 15);
+    }
+
+    #[test]
+    fn unknown_identifiers() {
+        assert!(Expr::parse("foo(a)").is_err());
+        assert!(Expr::parse("a $ b").is_err());
+        assert!(Expr::parse("a b").is_err());
+        assert!(Expr::parse("(a").is_err());
+        //未知变量视为0
+        assert_eq!(eval("c + a"), 3);
+        assert_eq!(
+            Expr::parse("max(a, c) ? d : e").unwrap().variables(),
+            vec!["a", "c", "d", "e"]
+        );
+    }
+
+    #[test]
+    fn overflow_saturates() {
+        assert_eq!(eval("99999 * 99999 * a"), i32::MAX);
+        assert_eq!(eval("-99999 * 99999"), i32::MIN);
+        assert_eq!(eval("-(0 - 99999 * 99999)"), i32::MAX);
+        assert_eq!(eval("2147483647 + b"), i32::MAX);
+        assert!(Expr::parse("99999999999").is_err());
+    }
+}
@@ -2,16 +2,48 @@ mod check;
 mod sim;
 mod wiring;
 mod config;
+mod compile;
+mod orientation;
+mod repeater;
+mod export;
+mod decompile;
+mod anvil;
+mod expr;
+mod vcd;
+mod redstone;
+mod derive;
+mod equivalence;
+mod netlist;
+mod synth;
+mod place;
+mod route;
+#[cfg(test)]
+mod test_util;
 use ansi_term::Color::{*};
 use clap::Parser;
-use flate2::Compression;
 use std::{any::Any, fmt::Display, fs::{File, OpenOptions}, io::{BufReader, Read, Write}, ops::Add};
 use serde_derive::{Deserialize, Serialize};
-use mc_schem::{region::WorldSlice, schem::{LitematicaSaveOption, Schematic}, Block, Region};
-use std::collections::{HashMap, HashSet};
+use mc_schem::{schem::Schematic, Block, Region};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use check::*;
+use compile::*;
+use orientation::*;
+use wiring::*;
+use repeater::*;
+use export::*;
+use decompile::*;
+use anvil::*;
+use expr::*;
+use vcd::*;
+use redstone::*;
+use derive::*;
+use equivalence::*;
+use netlist::*;
+use synth::*;
+use place::*;
+use route::*;
 
-use crate::sim::{do_simulation, simulate_component};
+use crate::sim::{do_component_simulation, do_simulation, generate_truth_table, run_testbench};
 #[derive(Debug, Clone, Serialize, Deserialize,PartialEq, Eq,Hash,Copy)]
 struct Position{
     x: i32,
@@ -60,7 +92,28 @@ struct ImportItem{
 struct Component{
     name: String,
     model: String,
-    position: Position,
+    ///缺省时由布局器放置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    position: Option<Position>,
+    ///旋转和镜像，缺省时按nbt保存时的朝向放置
+    #[serde(default)]
+    orientation: Orientation,
+    ///为true时布局器可以移动已经有位置的元件
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    movable: bool,
+}
+impl Component {
+    ///元件的位置，还没有放置时报错
+    pub fn origin(&self) -> Position {
+        self.position.unwrap_or_else(|| {
+            error_begin();
+            panic!("component {} has no position, place it with --place", self.name);
+        })
+    }
+    ///端口在电路中的位置，考虑了元件的朝向
+    pub fn port_position(&self, port: &Port, model: &dyn ModelObject) -> Position {
+        self.origin() + self.orientation.transform_position(port.position, model.get_size())
+    }
 }
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize)]
@@ -68,25 +121,42 @@ struct Wire{
     name: String,
     start: Position,
     end: Position,
+    ///start和end之间依次经过的拐点
+    #[serde(default)]
+    waypoints: Vec<Position>,
     baseMaterial: String,
+    ///纯竖直段的走线方式
+    #[serde(default)]
+    verticalStyle: VerticalStyle,
 }
-#[derive(Serialize, Deserialize,Clone)]
-struct Properties{
-    facing: String,
-    delay: i32,
-    locked: bool,
-    powered: bool,
-    power: i32
+#[derive(Serialize, Deserialize,Clone,Default)]
+///## Properties
+/// 方块状态，例如中继器的facing、delay，比较器的mode，拉杆的face，台阶的type等。
+/// 
+/// 值可以是字符串、数字或布尔值，放置时都转换成字符串写入方块属性。
+struct Properties(BTreeMap<String, serde_json::Value>);
+impl Properties {
+    ///取得一个属性的字符串值
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.0.get(key).map(|value| match value {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+    }
+    ///把属性写入方块，覆盖方块id中已有的同名属性
+    pub fn apply_to(&self, block: &mut Block) {
+        for key in self.0.keys() {
+            block.set_property(key, &self.get(key).unwrap());
+        }
+    }
 }
 impl Display for Properties {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f,"{{\n facing:{},\n delay:{},\n locked:{},\n powered:{},\n power:{}}}",
-            self.facing,self.delay,self.locked,self.powered,self.power)
-    }
-}
-impl Default for Properties {
-    fn default() -> Self {
-        Self { facing: Default::default(), delay: Default::default(), locked: Default::default(), powered: Default::default(), power: Default::default() }
+        write!(f,"{{")?;
+        for key in self.0.keys() {
+            write!(f,"\n {}:{},",key,self.get(key).unwrap())?;
+        }
+        write!(f,"}}")
     }
 }
 #[derive(Serialize, Deserialize)]
@@ -95,6 +165,13 @@ struct BlockInfo{
     id:String,
     properties:Option<Properties>
 }
+#[derive(Serialize, Deserialize, Clone, Debug)]
+///## Net
+/// 两个端口之间的逻辑连接，端口写成"元件名.端口名"，电路自身的输入输出直接写端口名
+struct Net{
+    from: String,
+    to: String,
+}
 #[derive(Serialize, Deserialize,Clone,PartialEq,Eq,Hash,Debug)]
 struct Port{
     name: String,
@@ -109,6 +186,9 @@ struct Circuit{
     imports: Vec<ImportItem>,
     components: Vec<Component>,
     wires: Vec<Wire>,
+    ///逻辑连接，布局时用来估计导线长度
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nets: Vec<Net>,
     blocks:Vec<BlockInfo>,
     inputs:Vec<Port>,
     outputs:Vec<Port>
@@ -121,9 +201,22 @@ trait ModelObject:Any {
     fn get_inputs(&self) -> &Vec<Port>;
     fn get_outputs(&self) -> &Vec<Port>;
     fn get_nbt_path(&self) -> Option<&str>;
+    fn get_size(&self) -> [i32;3];
+    ///信号从输入到输出的延迟(红石刻)，None表示按元件类型取默认值
+    fn get_delay(&self) -> Option<i32> {
+        None
+    }
+    ///仿真表达式，输出端口名 -> 表达式，None表示按元件名使用内置的行为
+    fn get_sim(&self) -> Option<&BTreeMap<String, String>> {
+        None
+    }
+    ///时钟的周期或脉冲延长的长度(红石刻)，None表示取默认值
+    fn get_period(&self) -> Option<i32> {
+        None
+    }
     fn as_any(&self) -> &dyn Any;
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 ///## ComponentModelObject
 /// 元件导入模型对象
 /// 
@@ -135,6 +228,15 @@ struct ComponentModelObject {
     size: [i32;3],
     inputs: Vec<Port>,
     outputs: Vec<Port>,
+    ///输入到输出的延迟(红石刻)，逐刻仿真时使用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    delay: Option<i32>,
+    ///每个输出端口的仿真表达式，例如 "output":"input0 & input1"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sim: Option<BTreeMap<String, String>>,
+    ///clock的周期、pulse_extender延长的长度(红石刻)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    period: Option<i32>,
 }
 impl ModelObject for ComponentModelObject {
     fn get_name(&self) -> &str {
@@ -156,6 +258,22 @@ impl ModelObject for ComponentModelObject {
     fn get_nbt_path(&self) -> std::option::Option<&str> {
         Some(self.nbt.as_str())
     }
+
+    fn get_size(&self) -> [i32;3] {
+        self.size
+    }
+
+    fn get_delay(&self) -> Option<i32> {
+        self.delay
+    }
+
+    fn get_sim(&self) -> Option<&BTreeMap<String, String>> {
+        self.sim.as_ref()
+    }
+
+    fn get_period(&self) -> Option<i32> {
+        self.period
+    }
     
     fn as_any(&self) -> &dyn Any {
         self
@@ -181,6 +299,10 @@ impl ModelObject for Circuit {
     fn get_nbt_path(&self) -> Option<&str> {
         None
     }
+
+    fn get_size(&self) -> [i32;3] {
+        self.size.to_slice()
+    }
     
     fn as_any(&self) -> &dyn Any {
         self
@@ -196,12 +318,14 @@ struct ModelObjectItem{
 #[derive(Parser,Debug)]
 #[command(version("1.0.0"),about, long_about=None)]
 struct CommandLineArgs{
-    #[clap(short, long)]
-    input_json: String,
+    #[clap(short, long, required_unless_present_any = ["decomp_path", "check_equivalence", "netlist"])]
+    input_json: Option<String>,
     #[clap(short, long)]
     output_path: String,
     #[clap(short, long)]
-    decomp_path: Option<String>,
+    decomp_path: Option<String>,//反编译的schematic文件，输出项目json到output_path
+    #[clap(long)]
+    netlist: Option<String>,//门级网表(.blif或结构化Verilog .v)，生成项目json到output_path
     #[clap(short, long)]
     generate_component_json: bool,
     #[clap(short, long)]
@@ -211,19 +335,84 @@ struct CommandLineArgs{
     #[clap(long)]
     graph_json:bool,//生成连接图的json文件
     #[clap(short, long)]
-    simulate_input_path:Option<String>//模拟电路运行输入文件路径
+    simulate_input_path:Option<String>,//模拟电路运行输入文件路径
+    #[clap(long)]
+    block_level:bool,//输入文件是元件json，按它的nbt中的方块仿真
+    #[clap(long)]
+    derive_sim:bool,//输入文件是元件json，枚举输入仿真它的nbt，把推导出的sim写到output_path
+    #[clap(long)]
+    force:bool,//推导出的sim和声明的不一致时仍然覆盖
+    #[clap(long)]
+    check_equivalence:bool,//比较元件声明的逻辑和nbt的方块级仿真，不指定输入文件时检查library下所有元件
+    #[clap(long)]
+    vcd:Option<String>,//逐刻仿真时输出VCD波形文件
+    #[clap(long)]
+    vcd_internal:bool,//VCD波形包含所有元件端口和导线端点
+    #[clap(long)]
+    testbench:Option<String>,//测试文件路径，运行后把结果写到output_path，有失败时以非零值退出
+    #[clap(long)]
+    truth_table:bool,//枚举输入生成真值表，按output_path的扩展名输出csv或json
+    #[clap(long, default_value_t=1024)]
+    truth_table_limit:u64,//真值表最多的行数，输入组合更多时均匀抽样
+    #[clap(long)]
+    place:bool,//自动放置没有位置或movable的元件，把项目json写到output_path
+    #[clap(long)]
+    route:bool,//按nets自动布线，把项目json写到output_path
+    #[clap(long)]
+    auto_repeater:bool,//在过长的导线上自动放置中继器
+    #[clap(long, value_enum)]
+    format:Option<OutputFormat>,//输出格式，不指定时按output_path的扩展名选择
+    #[clap(long)]
+    data_version:Option<i32>,//写入schematic的Minecraft数据版本
+    #[clap(long, num_args=3, value_names=["X","Y","Z"], allow_negative_numbers=true)]
+    anchor:Option<Vec<i32>>,//电路原点的世界坐标，用于mcfunction和写入存档
 }
 fn main() {
     let args=CommandLineArgs::parse();
-    let input_json=args.input_json;
     let output_path=args.output_path;
-    let decomp_path=args.decomp_path.unwrap_or_default();
-    let decomp_flag=false;
     //生成schematic的时候是否附带生成把它视为component的json文件
     let genereate_component_flag=args.generate_component_json;
-    if decomp_flag {
-        error_begin();
-        unimplemented!("Decompiling not implemented yet");
+    //反编译schematic，用library中的元件识别
+    if let Some(decomp_path)=args.decomp_path {
+        decompile_to_file(&decomp_path, &output_path, args.library.as_deref());
+        return;
+    }
+    //从网表生成项目，用library中的元件
+    if let Some(netlist_path)=args.netlist {
+        synthesize_to_file(&netlist_path, &output_path, args.library.as_deref());
+        return;
+    }
+    //比较元件的逻辑和方块
+    if args.check_equivalence {
+        let report=check_equivalence(args.input_json.as_deref(), args.library.as_deref());
+        std::fs::write(&output_path, serde_json::to_string_pretty(&report).expect("fatal: EquivalenceReport failed to_string")).unwrap_or_else(|e| {
+            error_begin();
+            panic!("failed to write equivalence report {}: {}",output_path,e);
+        });
+        if report.failed>0 {
+            error_begin();
+            println!("{} component(s) disagree with their blocks",report.failed);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let input_json=args.input_json.unwrap();
+    //从nbt推导元件的sim
+    if args.derive_sim {
+        derive_sim_to_file(&input_json, &output_path, args.library.as_deref(), args.force);
+        return;
+    }
+    //方块级仿真元件
+    if args.block_level {
+        let Some(simulate_input_path)=args.simulate_input_path else {
+            error_begin();
+            panic!("--block-level needs a simulate input file (-s)");
+        };
+        let inputs=std::fs::read_to_string(&simulate_input_path).expect("failed to read simulate input file");
+        let output_json=do_component_simulation(&input_json, &inputs, args.library.as_deref());
+        std::fs::write(&output_path, output_json).expect("failed to write output json file");
+        println!("generated output json file {}",output_path);
+        return;
     }
     
     //否则输入文件视为circuit文件
@@ -234,48 +423,57 @@ fn main() {
     let mut json_content=Vec::<u8>::new();
     jsonfile.read_to_end(&mut json_content).unwrap();
     let json_content=String::from_utf8_lossy(&json_content);
-    let obj:Circuit=serde_json::from_str(&json_content).unwrap_or_else(|x|{
+    let mut obj:Circuit=serde_json::from_str(&json_content).unwrap_or_else(|x|{
         error_begin();
         panic!("failed to parse input json file {}:\n{}",&input_json,x.to_string());
     });
     //存放读取的元件和子电路json对象，缓存
-    let mut model_objects:Vec<Box<dyn ModelObject>>=vec![];
-    let mut schem:Schematic=Schematic::new();
     //解析导入，存入缓存方便后面取用
-    for import_item in obj.imports.iter() {
-        let path={
-            if let Some(library_path)=args.library.clone() {
-                library_path+"/"+import_item.path.as_str()
-            }else {
-                import_item.path.clone()
-            }
-        };
-        let model_type=import_item.modelType.as_str();
-        let mut rd=OpenOptions::new().read(true)
-        .open(&path).expect(format!("failed to open import file {}",&path).as_str());
-        match model_type {
-            "component"=>{
-                //元件类型
-                let model_obj=Box::<ComponentModelObject>::new(serde_json::from_reader(rd).unwrap());
-                model_objects.push(model_obj);
-            },
-            "circuit"=>{
-                let model_obj=Box::<Circuit>::new(serde_json::from_reader(rd).unwrap());
-                model_objects.push(model_obj);
-            }
-            _=>{
-                error_begin();
-                panic!("Unsupported model type: {}",model_type);
-            }
+    let model_objects=load_imports(&obj.imports, args.library.as_deref());
+    //自动布局，有元件没有位置时也要先布局
+    if args.place || obj.components.iter().any(|c| c.position.is_none()) {
+        let count=place_components(&mut obj, &model_objects);
+        println!("placed {} component(s), total net length {}",count,total_net_length(&obj, &model_objects));
+    }
+    //自动布线
+    if args.route {
+        let report=route_nets(&mut obj, &model_objects);
+        println!("routed {} net(s), total wire length {}, {} repeater(s)",
+            report.routed,
+            report.length,
+            report.repeaters.iter().map(|r| r.repeaters.len()).sum::<usize>());
+        if !report.failed.is_empty() {
+            error_begin();
+            println!("{} net(s) could not be routed: {}",report.failed.len(),report.failed.join(", "));
         }
     }
+    if args.place || args.route {
+        std::fs::write(&output_path, serde_json::to_string_pretty(&obj).expect("fatal: Circuit failed to_string")).unwrap_or_else(|e| {
+            error_begin();
+            panic!("failed to write project json file {}: {}",output_path,e);
+        });
+        println!("Project json file saved to {}",output_path);
+        return;
+    }
+    //导出网表，不编译
+    if let Some(format)=args.format.or_else(|| OutputFormat::from_path(&output_path)).filter(|f| f.is_netlist()) {
+        let netlist=export_netlist(&obj, &model_objects, args.library.as_deref(), format);
+        std::fs::write(&output_path, netlist).unwrap_or_else(|e| {
+            error_begin();
+            panic!("failed to write netlist {}: {}",output_path,e);
+        });
+        println!("Netlist saved to {}",output_path);
+        return;
+    }
+    let mut schem:Schematic=Schematic::new();
 
     //TODO 仿真需要输入两个文件: 电路json和输入json
     //仿真
     if let Some(simulate_input_path)=args.simulate_input_path {
         let mut inputs=String::new();
         OpenOptions::new().read(true).open(simulate_input_path).expect("failed to open simulate input file").read_to_string(&mut inputs).expect("failed to read simulate input file");
-        let output_json=do_simulation(&obj, &inputs,&model_objects);
+        let vcd=args.vcd.map(|path| VcdOption{path, internal: args.vcd_internal});
+        let output_json=do_simulation(&obj, &inputs,&model_objects, args.library.as_deref(), vcd.as_ref());
         let mut output_file=OpenOptions::new().write(true).create(true).truncate(true).open(output_path.clone()).unwrap_or_else(|e| {
             error_begin();
             panic!("failed to open output json file {}",output_path.clone());
@@ -285,6 +483,52 @@ fn main() {
         return;
     }
     
+    //运行测试文件
+    if let Some(testbench_path)=args.testbench {
+        let testbench=std::fs::read_to_string(&testbench_path).unwrap_or_else(|e| {
+            error_begin();
+            panic!("failed to read testbench file {}: {}",testbench_path,e);
+        });
+        let report=run_testbench(&obj, &model_objects, args.library.as_deref(), &testbench);
+        std::fs::write(&output_path, serde_json::to_string_pretty(&report).expect("fatal: TestBenchReport failed to_string")).unwrap_or_else(|e| {
+            error_begin();
+            panic!("failed to write testbench report {}: {}",output_path,e);
+        });
+        if report.failed>0 {
+            error_begin();
+            println!("{} vector(s) of testbench {} failed",report.failed,testbench_path);
+            std::process::exit(1);
+        }
+        return;
+    }
+    //真值表
+    if args.truth_table {
+        let table=generate_truth_table(&obj, &model_objects, args.library.as_deref(), args.truth_table_limit);
+        print!("{}",table.to_pretty());
+        let content=if output_path.ends_with(".csv") {
+            table.to_csv()
+        }else{
+            serde_json::to_string_pretty(&table).expect("fatal: TruthTable failed to_string")
+        };
+        std::fs::write(&output_path, content).unwrap_or_else(|e| {
+            error_begin();
+            panic!("failed to write truth table {}: {}",output_path,e);
+        });
+        println!("generated truth table {}",output_path);
+        return;
+    }
+    
+    //自动放置中继器，作为方块加入电路，之后的检查和编译都会用到
+    if args.auto_repeater {
+        for report in insert_repeaters(&mut obj, &model_objects) {
+            println!("wire {}: placed {} repeater(s) at [{}], delay +{} redstone tick(s) ({} game ticks)",
+                report.wire,
+                report.repeaters.len(),
+                report.repeaters.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(", "),
+                report.delay,
+                report.delay*2);
+        }
+    }
     //检查电路
     if args.check_circuit && !check_circuit(&obj, &model_objects){
         error_begin();
@@ -308,97 +552,9 @@ fn main() {
     let global_region=Region::with_shape(obj.size.to_slice());
     schem.regions.push(global_region);
     let global_region=&mut schem.regions[0];
-    //解析元件
-    for component in obj.components {
-        println!("Component:{},Model:{},Position:({},{},{})",component.name,component.model,component.position.x,component.position.y,component.position.z);
-        let model_name=component.model.as_str();
-        //找到对应导入
-        let model_import_item=model_objects.iter().find(|&x| {
-            if x.get_name() == model_name {
-                return true;
-            }
-            false
-        }).unwrap_or_else(|| {
-            error_begin();
-            println!("Error: Model {} not found in imports",model_name);
-            panic!("Model {} not found in imports",model_name);
-        });
-        //根据不同的model_type进行处理，然后放置到schematic的region中
-        match model_import_item.get_type() {
-            "component"=>{
-                // 元件，寻找它的nbt
-                let nbt_path={
-                    let relative=model_import_item.get_nbt_path().unwrap();
-                    if let Some(library_path) = args.library.as_ref()  {
-                        library_path.to_owned()+"/"+relative
-                    }else {
-                        relative.to_string()
-                    }
-                };
-                let (mut nbt_obj,raw_meta)=Schematic::from_file(&nbt_path).unwrap_or_else(|x|{panic!("failed to load nbt file {}",nbt_path)});
-                //nbt合并到一个region防止多个region
-                nbt_obj.merge_regions(&Block::air());
-                //放置到schem的region
-                let component_shape=nbt_obj.shape();
-                //开始放置
-                for x in 0..component_shape[0] {
-                    for y in 0..component_shape[1] {
-                        for z in 0..component_shape[2] {
-                            if let Some(blk)=nbt_obj.regions[0].block_at([x,y,z]) {
-                                global_region.set_block(
-                                    [x+component.position.x,
-                                    y+component.position.y,
-                                    z+component.position.z], 
-                                    blk).unwrap_or_else(|_|{
-                                        println!("failed to place block {} at ({},{},{})",blk.id,x+component.position.x,y+component.position.y,z+component.position.z);
-                                        let global_shape=global_region.shape();
-                                        if  global_shape[0]<=x||global_shape[1]<=y||global_shape[2]<=z {
-                                            panic!("block position out of range: ({},{},{}), the component size is ({},{},{})",
-                                            x+component.position.x,y+component.position.y,z+component.position.z,
-                                            component_shape[0],component_shape[1],component_shape[2]);
-                                        }
-                                    });
-                            }
-                        }
-                    }
-                }
-            },
-            "circuit"=>{
-                error_begin();
-                unimplemented!("Circuit import not implemented yet")
-            },
-            _=>{
-                error_begin();
-                panic!("Unsupported model type: {}",model_import_item.get_type());
-            }
-        }
-    }
-    //解析导线
-    for wire in obj.wires {
-        let base_block=Block::from_id(&wire.baseMaterial)
-        .expect("err: invalid base material");
-        let mut start_pos=wire.start.to_slice();
-        let mut end_pos=wire.end.to_slice();
-        fill_block(start_pos, end_pos, base_block, global_region);
-        start_pos[1]+=1;
-        end_pos[1]+=1;
-        //放置导线
-        fill_block(start_pos, end_pos, Block::from_id("redstone_wire").unwrap(), global_region);
-
-    }
-    //解析方块
-    for block in obj.blocks {
-        let block_id=block.id.as_str();
-        let block_pos=block.position.to_slice();
-        let block_block=Block::from_id(block_id)
-        .expect(format!("err: invalid block id {}",block_id).as_str());
-        global_region.set_block(block_pos,&block_block).unwrap();
-    }
-    //完毕，保存
-    let save_option=LitematicaSaveOption{
-        compress_level: Compression::default(),
-        rename_duplicated_regions: true, 
-    };
+    //放置元件、导线和方块，子电路递归编译
+    let mut chain=vec![std::fs::canonicalize(&input_json).map(|p| p.to_string_lossy().to_string()).unwrap_or(input_json.clone())];
+    compile_circuit(&obj, &model_objects, args.library.as_deref(), global_region, Position{x:0,y:0,z:0}, &mut chain);
     //生成对应component的json文件
     if genereate_component_flag {
         let component_json=ComponentModelObject{
@@ -408,6 +564,9 @@ fn main() {
             size: obj.size.to_slice(),
             inputs: obj.inputs.clone().iter_mut().enumerate().map(|(i,p)| {p.name=format!("input{}",i);p.clone()}).collect(),
             outputs: obj.outputs.clone().iter_mut().enumerate().map(|(i,p)| {p.name=format!("output{}",i);p.clone()}).collect(),
+            delay: None,
+            sim: None,
+            period: None,
         };
         let mut component_json=serde_json::to_string(&component_json).unwrap();
         let mut component_file=OpenOptions::new()
@@ -417,20 +576,12 @@ fn main() {
         component_file.write_all(component_json.as_bytes()).unwrap();
         println!("Component json file saved to {}.json",output_path);
     }
-    schem.save_litematica_file(&output_path, &save_option).expect("error: failed to save schematic file");
+    //完毕，保存
+    save_schematic(&mut schem, &output_path, args.format, args.data_version, args.anchor.map(|a| [a[0],a[1],a[2]]));
     println!("Schematic saved to {}",output_path);
 }
 
 
-fn fill_block(start:[i32;3],end:[i32;3],block:Block,region:&mut Region){
-    for x in start[0]..=end[0] {
-        for y in start[1]..=end[1] {
-            for z in start[2]..=end[2] {
-                region.set_block([x,y,z],&block).unwrap();
-            }
-        }
-    }
-}
 fn error_begin(){
     print!("{}",Red.paint("error: "));
 }
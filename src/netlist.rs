@@ -0,0 +1,575 @@
+use crate::{
+    sim::{
+        CircuitNetlist, circuit_netlist, evaluate_component_logic, input_combinations,
+        sequential_period,
+    },
+    *,
+};
+
+/*
+把电路导出成门级网表，结构化Verilog或BLIF。
+
+每个电路(包括子电路)是一个模块，元件是它的模型的实例。
+元件的模型也各是一个模块：组合逻辑按0/15枚举输入化简成与或式，时序元件用锁存器或行为描述。
+导线是缓冲器，信号只有高低电平，能量的衰减不导出。
+多个驱动汇合的网络是它们的或。
+*/
+
+///Verilog的关键字，用作名字时要转义
+const VERILOG_KEYWORDS: [&str; 40] = [
+    "always", "and", "assign", "begin", "buf", "case", "default", "else", "end", "endcase",
+    "endmodule", "for", "forever", "if", "initial", "inout", "input", "integer", "module", "nand",
+    "negedge", "nor", "not", "or", "output", "parameter", "posedge", "reg", "supply0", "supply1",
+    "tri", "wait", "while", "wire", "xnor", "xor", "function", "task", "event", "time",
+];
+
+///Verilog的标识符，不合法的名字(例如and001.output)写成转义标识符
+fn verilog_ident(name: &str) -> String {
+    let plain = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !VERILOG_KEYWORDS.contains(&name);
+    if plain {
+        name.to_string()
+    } else {
+        format!("\\{} ", name.split_whitespace().collect::<Vec<&str>>().join("_"))
+    }
+}
+
+///BLIF的信号名不能有空白
+fn blif_ident(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<&str>>().join("_")
+}
+
+///网络的驱动
+enum Driver {
+    ///电路的输入端口
+    Input(String),
+    ///实例的输出(实例序号, 输出序号)
+    Output(usize, usize),
+}
+
+///## ModuleSignals
+/// 一个电路模块里每个网络和每个驱动使用的信号
+struct ModuleSignals {
+    ///读取网络时使用的信号，没有驱动的网络为None(恒为低)
+    net: Vec<Option<String>>,
+    ///实例每个输出写入的信号
+    outputs: Vec<Vec<String>>,
+    ///多个驱动的网络：(网络的信号, 各驱动的信号)
+    merges: Vec<(String, Vec<String>)>,
+    ///模块内部声明的信号
+    internal: Vec<String>,
+}
+impl ModuleSignals {
+    fn new(netlist: &CircuitNetlist) -> Self {
+        let mut drivers: Vec<Vec<Driver>> = (0..netlist.nets.len()).map(|_| Vec::new()).collect();
+        for (name, net) in netlist.inputs.iter() {
+            drivers[*net].push(Driver::Input(name.clone()));
+        }
+        for (i, instance) in netlist.instances.iter().enumerate() {
+            for (j, (_, net)) in instance.outputs.iter().enumerate() {
+                drivers[*net].push(Driver::Output(i, j));
+            }
+        }
+        let mut signals = ModuleSignals {
+            net: vec![None; netlist.nets.len()],
+            outputs: netlist
+                .instances
+                .iter()
+                .map(|inst| vec![String::new(); inst.outputs.len()])
+                .collect(),
+            merges: Vec::new(),
+            internal: Vec::new(),
+        };
+        let is_port = |name: &str| {
+            netlist
+                .inputs
+                .iter()
+                .chain(netlist.outputs.iter())
+                .any(|(port, _)| port == name)
+        };
+        for (net, drivers) in drivers.iter().enumerate() {
+            let name = &netlist.nets[net];
+            match drivers.as_slice() {
+                [] => {}
+                [Driver::Input(port)] => signals.net[net] = Some(port.clone()),
+                [Driver::Output(i, j)] => {
+                    signals.outputs[*i][*j] = name.clone();
+                    signals.net[net] = Some(name.clone());
+                    if !is_port(name) {
+                        signals.internal.push(name.clone());
+                    }
+                }
+                _ => {
+                    //输入端口不能被赋值，和其他驱动汇合时另起一个信号
+                    let merged = if netlist.inputs.iter().any(|(port, _)| port == name) {
+                        format!("{}.net", name)
+                    } else {
+                        name.clone()
+                    };
+                    let mut sources = Vec::new();
+                    for (k, driver) in drivers.iter().enumerate() {
+                        match driver {
+                            Driver::Input(port) => sources.push(port.clone()),
+                            Driver::Output(i, j) => {
+                                let signal = format!("{}.{}", merged, k);
+                                signals.outputs[*i][*j] = signal.clone();
+                                signals.internal.push(signal.clone());
+                                sources.push(signal);
+                            }
+                        }
+                    }
+                    if !is_port(&merged) {
+                        signals.internal.push(merged.clone());
+                    }
+                    signals.net[net] = Some(merged.clone());
+                    signals.merges.push((merged, sources));
+                }
+            }
+        }
+        signals
+    }
+}
+
+///元件模型的每个输出在所有输入组合下是否为高，下标是输入组合，第一个输入是最高位
+fn model_minterms(model: &dyn ModelObject) -> Vec<Vec<u32>> {
+    let n = model.get_inputs().len();
+    if n > MAX_DERIVE_INPUTS {
+        error_begin();
+        panic!(
+            "component {} has {} inputs, at most {} can be enumerated",
+            model.get_name(),
+            n,
+            MAX_DERIVE_INPUTS
+        );
+    }
+    let mut minterms = vec![Vec::new(); model.get_outputs().len()];
+    for (index, values) in input_combinations(n).enumerate() {
+        for (i, power) in evaluate_component_logic(model, &values).iter().enumerate() {
+            if *power > 0 {
+                minterms[i].push(index as u32);
+            }
+        }
+    }
+    minterms
+}
+
+///## NetlistWriter
+/// 网表的两种格式
+trait NetlistWriter {
+    ///元件模型的模块
+    fn component(&self, model: &dyn ModelObject) -> String;
+    ///电路的模块
+    fn circuit(&self, name: &str, netlist: &CircuitNetlist, signals: &ModuleSignals) -> String;
+}
+
+struct VerilogWriter;
+impl VerilogWriter {
+    fn header(name: &str, inputs: &[String], outputs: &[String]) -> String {
+        let ports = inputs
+            .iter()
+            .map(|p| format!("input {}", verilog_ident(p)))
+            .chain(outputs.iter().map(|p| format!("output {}", verilog_ident(p))))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!("module {}({});\n", verilog_ident(name), ports)
+    }
+    ///时序元件的行为描述，q$是锁存的状态，period是红石刻。内部信号带$，不会和端口重名
+    fn sequential(model: &dyn ModelObject, inputs: &[String], period: i32) -> String {
+        let input = |i: usize| inputs.get(i).map(|p| verilog_ident(p)).unwrap_or("1'b0".to_string());
+        //1红石刻 = 100ms
+        let delay = period * 100;
+        match model.get_name() {
+            "rs_latch" => format!(
+                "  always @* if ({s} && !{r}) q$ = 1'b1; else if ({r} && !{s}) q$ = 1'b0;\n  wire both$ = {s} && {r};\n",
+                s = input(0),
+                r = input(1)
+            ),
+            "d_latch" => format!("  always @* if (!{}) q$ = {};\n", input(1), input(0)),
+            "t_flip_flop" => format!("  always @(posedge {}) q$ <= !q$;\n", input(0)),
+            "pulse_extender" => format!(
+                "  //只用于仿真\n  always @(posedge {i}) q$ = 1'b1;\n  always @(negedge {i}) begin #{d}; if (!{i}) q$ = 1'b0; end\n",
+                i = input(0),
+                d = delay
+            ),
+            _ => format!(
+                "  //只用于仿真\n  always #{} q$ = !q$;\n  wire off$ = {};\n",
+                delay / 2,
+                input(0)
+            ),
+        }
+    }
+}
+impl NetlistWriter for VerilogWriter {
+    fn component(&self, model: &dyn ModelObject) -> String {
+        let inputs: Vec<String> = model.get_inputs().iter().map(|p| p.name.clone()).collect();
+        let outputs: Vec<String> = model.get_outputs().iter().map(|p| p.name.clone()).collect();
+        let mut text = Self::header(model.get_name(), &inputs, &outputs);
+        if let Some(period) = sequential_period(model) {
+            //clock开始时为高
+            text += &format!(
+                "  reg q$ = 1'b{};\n",
+                if model.get_name() == "clock" { 1 } else { 0 }
+            );
+            text += &Self::sequential(model, &inputs, period);
+            let gate = match model.get_name() {
+                "rs_latch" => " && !both$",
+                "clock" => " && !off$",
+                _ => "",
+            };
+            for (i, output) in outputs.iter().enumerate() {
+                let q = if i == 0 { "q$" } else { "!q$" };
+                text += &format!("  assign {} = {}{};\n", verilog_ident(output), q, gate);
+            }
+        } else {
+            let names: Vec<String> = inputs.iter().map(|p| verilog_ident(p)).collect();
+            for (output, minterms) in outputs.iter().zip(model_minterms(model)) {
+                let sop = match derive::minimize(&minterms, &names).as_str() {
+                    "0" => "1'b0".to_string(),
+                    "15" => "1'b1".to_string(),
+                    sop => sop.to_string(),
+                };
+                text += &format!("  assign {} = {};\n", verilog_ident(output), sop);
+            }
+        }
+        text + "endmodule\n"
+    }
+    fn circuit(&self, name: &str, netlist: &CircuitNetlist, signals: &ModuleSignals) -> String {
+        let inputs: Vec<String> = netlist.inputs.iter().map(|(p, _)| p.clone()).collect();
+        let outputs: Vec<String> = netlist.outputs.iter().map(|(p, _)| p.clone()).collect();
+        let mut text = Self::header(name, &inputs, &outputs);
+        for signal in signals.internal.iter() {
+            text += &format!("  wire {};\n", verilog_ident(signal));
+        }
+        let read = |net: usize| {
+            signals.net[net]
+                .as_ref()
+                .map(|s| verilog_ident(s))
+                .unwrap_or("1'b0".to_string())
+        };
+        for (i, instance) in netlist.instances.iter().enumerate() {
+            match &instance.model {
+                //导线是缓冲器
+                None => {
+                    let input = instance.inputs.first().map(|(_, net)| read(*net));
+                    for signal in signals.outputs[i].iter() {
+                        text += &format!(
+                            "  assign {} = {};\n",
+                            verilog_ident(signal),
+                            input.clone().unwrap_or("1'b0".to_string())
+                        );
+                    }
+                }
+                Some(model) => {
+                    let ports = instance
+                        .inputs
+                        .iter()
+                        .map(|(port, net)| format!(".{}({})", verilog_ident(port), read(*net)))
+                        .chain(
+                            instance
+                                .outputs
+                                .iter()
+                                .zip(signals.outputs[i].iter())
+                                .map(|((port, _), signal)| {
+                                    format!(".{}({})", verilog_ident(port), verilog_ident(signal))
+                                }),
+                        )
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    text += &format!(
+                        "  {} {}({});\n",
+                        verilog_ident(model),
+                        verilog_ident(&instance.name),
+                        ports
+                    );
+                }
+            }
+        }
+        for (merged, sources) in signals.merges.iter() {
+            text += &format!(
+                "  assign {} = {};\n",
+                verilog_ident(merged),
+                sources.iter().map(|s| verilog_ident(s)).collect::<Vec<String>>().join(" | ")
+            );
+        }
+        for (port, net) in netlist.outputs.iter() {
+            if signals.net[*net].as_ref() != Some(port) {
+                text += &format!("  assign {} = {};\n", verilog_ident(port), read(*net));
+            }
+        }
+        text + "endmodule\n"
+    }
+}
+
+struct BlifWriter;
+impl BlifWriter {
+    fn header(name: &str, inputs: &[String], outputs: &[String]) -> String {
+        let list = |ports: &[String]| {
+            ports
+                .iter()
+                .map(|p| blif_ident(p))
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+        format!(
+            ".model {}\n.inputs {}\n.outputs {}\n",
+            blif_ident(name),
+            list(inputs),
+            list(outputs)
+        )
+    }
+    ///输出为inputs中任一个为高的.names
+    fn or(inputs: &[String], output: &str) -> String {
+        let mut text = format!(
+            ".names {} {}\n",
+            inputs.iter().map(|s| blif_ident(s)).collect::<Vec<String>>().join(" "),
+            blif_ident(output)
+        );
+        for i in 0..inputs.len() {
+            let row: String = (0..inputs.len()).map(|j| if i == j { '1' } else { '-' }).collect();
+            text += &format!("{} 1\n", row);
+        }
+        text
+    }
+    ///时序元件，q$是锁存的状态。内部信号带$，不会和端口重名
+    fn sequential(model: &dyn ModelObject, inputs: &[String], outputs: &[String]) -> String {
+        let input = |i: usize| blif_ident(inputs.get(i).map(|s| s.as_str()).unwrap_or(""));
+        let mut text = match model.get_name() {
+            //同时为高时保持: next$ = s & !r | !r & q$ | s & q$
+            "rs_latch" => format!(
+                ".names {s} {r} q$ next$\n10- 1\n-01 1\n1-1 1\n.latch next$ q$ 0\n.names {s} {r} both$\n11 1\n",
+                s = input(0),
+                r = input(1)
+            ),
+            //lock为低时透明
+            "d_latch" => format!(".latch {} q$ al {} 0\n", input(0), input(1)),
+            "t_flip_flop" => format!(".names q$ nq$\n0 1\n.latch nq$ q$ re {} 0\n", input(0)),
+            //和时间有关，不能用BLIF描述
+            _ => return ".blackbox\n".to_string(),
+        };
+        for (i, output) in outputs.iter().enumerate() {
+            let on = if i == 0 { '1' } else { '0' };
+            if model.get_name() == "rs_latch" {
+                text += &format!(".names q$ both$ {}\n{}0 1\n", blif_ident(output), on);
+            } else {
+                text += &format!(".names q$ {}\n{} 1\n", blif_ident(output), on);
+            }
+        }
+        text
+    }
+}
+impl NetlistWriter for BlifWriter {
+    fn component(&self, model: &dyn ModelObject) -> String {
+        let inputs: Vec<String> = model.get_inputs().iter().map(|p| p.name.clone()).collect();
+        let outputs: Vec<String> = model.get_outputs().iter().map(|p| p.name.clone()).collect();
+        let mut text = Self::header(model.get_name(), &inputs, &outputs);
+        if sequential_period(model).is_some() {
+            text += &Self::sequential(model, &inputs, &outputs);
+        } else {
+            let n = inputs.len();
+            for (output, minterms) in outputs.iter().zip(model_minterms(model)) {
+                text += &format!(
+                    ".names {}\n",
+                    inputs
+                        .iter()
+                        .chain(std::iter::once(output))
+                        .map(|s| blif_ident(s))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                );
+                //每个为高的输入组合一行
+                for m in minterms {
+                    let row: String = (0..n)
+                        .map(|j| if m >> (n - 1 - j) & 1 == 1 { '1' } else { '0' })
+                        .collect();
+                    text += &if n == 0 { "1\n".to_string() } else { format!("{} 1\n", row) };
+                }
+            }
+        }
+        text + ".end\n"
+    }
+    fn circuit(&self, name: &str, netlist: &CircuitNetlist, signals: &ModuleSignals) -> String {
+        let inputs: Vec<String> = netlist.inputs.iter().map(|(p, _)| p.clone()).collect();
+        let outputs: Vec<String> = netlist.outputs.iter().map(|(p, _)| p.clone()).collect();
+        let mut text = Self::header(name, &inputs, &outputs);
+        //没有驱动的网络恒为低
+        let zero = format!("{}.zero", blif_ident(name));
+        let read = |net: usize| signals.net[net].clone().unwrap_or(zero.clone());
+        if signals.net.iter().any(|s| s.is_none()) {
+            text += &format!(".names {}\n", blif_ident(&zero));
+        }
+        for (i, instance) in netlist.instances.iter().enumerate() {
+            match &instance.model {
+                None => {
+                    let input = instance.inputs.first().map(|(_, net)| read(*net)).unwrap_or(zero.clone());
+                    for signal in signals.outputs[i].iter() {
+                        text += &format!(".names {} {}\n1 1\n", blif_ident(&input), blif_ident(signal));
+                    }
+                }
+                Some(model) => {
+                    let ports = instance
+                        .inputs
+                        .iter()
+                        .map(|(port, net)| format!("{}={}", blif_ident(port), blif_ident(&read(*net))))
+                        .chain(
+                            instance
+                                .outputs
+                                .iter()
+                                .zip(signals.outputs[i].iter())
+                                .map(|((port, _), signal)| format!("{}={}", blif_ident(port), blif_ident(signal))),
+                        )
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    text += &format!(".subckt {} {}\n", blif_ident(model), ports);
+                }
+            }
+        }
+        for (merged, sources) in signals.merges.iter() {
+            text += &Self::or(sources, merged);
+        }
+        for (port, net) in netlist.outputs.iter() {
+            if signals.net[*net].as_ref() != Some(port) {
+                text += &format!(".names {} {}\n1 1\n", blif_ident(&read(*net)), blif_ident(port));
+            }
+        }
+        text + ".end\n"
+    }
+}
+
+///递归地导出电路和它用到的模型，子模块在前。done记录已经导出的模块名
+fn export_modules(
+    writer: &dyn NetlistWriter,
+    circuit: &Circuit,
+    models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
+    done: &mut Vec<String>,
+    modules: &mut Vec<String>,
+) {
+    done.push(circuit.name.clone());
+    let netlist = circuit_netlist(circuit, models, library);
+    for instance in netlist.instances.iter() {
+        let Some(model_name) = &instance.model else {
+            continue;
+        };
+        if done.contains(model_name) {
+            continue;
+        }
+        let model = models.iter().find(|m| m.get_name() == model_name).unwrap();
+        match model.as_any().downcast_ref::<Circuit>() {
+            Some(sub_circuit) => {
+                let sub_models = load_imports(&sub_circuit.imports, library);
+                export_modules(writer, sub_circuit, &sub_models, library, done, modules);
+            }
+            None => {
+                done.push(model_name.clone());
+                modules.push(writer.component(model.as_ref()));
+            }
+        }
+    }
+    let signals = ModuleSignals::new(&netlist);
+    modules.push(writer.circuit(&circuit.name, &netlist, &signals));
+}
+
+///## 导出网表
+/// format是Verilog或Blif，顶层模块在最后(BLIF中在最前，第一个model是顶层)
+pub fn export_netlist(
+    circuit: &Circuit,
+    models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
+    format: OutputFormat,
+) -> String {
+    let mut modules = Vec::new();
+    match format {
+        OutputFormat::Verilog => {
+            export_modules(&VerilogWriter, circuit, models, library, &mut Vec::new(), &mut modules);
+            "`timescale 1ms / 1ms\n".to_string() + &modules.join("\n")
+        }
+        OutputFormat::Blif => {
+            export_modules(&BlifWriter, circuit, models, library, &mut Vec::new(), &mut modules);
+            modules.reverse();
+            modules.join("\n")
+        }
+        _ => unreachable!("{:?} is not a netlist format", format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{gate, test_library};
+
+    ///临时目录下的and和not，端口在z=1一侧输入，z=-1一侧输出
+    fn nand_library(test: &str) -> String {
+        test_library("netlist", test, &[gate("and", 2, 1), gate("not", 1, 1)])
+    }
+    ///y = !(a & b)，and的输出和not的输入在同一格
+    fn nand() -> Circuit {
+        serde_json::from_str(
+            r#"{"name":"nand","size":{"x":2,"y":1,"z":5},
+            "imports":[{"modelName":"and","modelType":"component","path":"and.json"},
+                {"modelName":"not","modelType":"component","path":"not.json"}],
+            "components":[{"name":"g1","model":"and","position":{"x":0,"y":0,"z":3}},
+                {"name":"g2","model":"not","position":{"x":0,"y":0,"z":1}}],
+            "wires":[],"blocks":[],
+            "inputs":[{"name":"a","position":{"x":0,"y":0,"z":4}},{"name":"b","position":{"x":1,"y":0,"z":4}}],
+            "outputs":[{"name":"y","position":{"x":0,"y":0,"z":0}}]}"#,
+        )
+        .unwrap()
+    }
+
+    const NAND_VERILOG: &str = r"`timescale 1ms / 1ms
+module \and (input input0, input input1, output \output );
+  assign \output  = input0 & input1;
+endmodule
+
+module \not (input input0, output \output );
+  assign \output  = !input0;
+endmodule
+
+module \nand (input a, input b, output y);
+  wire \g1.output ;
+  \and  g1(.input0(a), .input1(b), .\output (\g1.output ));
+  \not  g2(.input0(\g1.output ), .\output (y));
+endmodule
+";
+
+    const NAND_BLIF: &str = ".model nand
+.inputs a b
+.outputs y
+.subckt and input0=a input1=b output=g1.output
+.subckt not input0=g1.output output=y
+.end
+
+.model not
+.inputs input0
+.outputs output
+.names input0 output
+0 1
+.end
+
+.model and
+.inputs input0 input1
+.outputs output
+.names input0 input1 output
+11 1
+.end
+";
+
+    #[test]
+    fn two_gates_verilog() {
+        let library = nand_library("two_gates_verilog");
+        let circuit = nand();
+        let models = load_imports(&circuit.imports, Some(&library));
+        let netlist = export_netlist(&circuit, &models, Some(&library), OutputFormat::Verilog);
+        assert_eq!(netlist, NAND_VERILOG);
+    }
+
+    #[test]
+    fn two_gates_blif() {
+        let library = nand_library("two_gates_blif");
+        let circuit = nand();
+        let models = load_imports(&circuit.imports, Some(&library));
+        let netlist = export_netlist(&circuit, &models, Some(&library), OutputFormat::Blif);
+        assert_eq!(netlist, NAND_BLIF);
+    }
+}
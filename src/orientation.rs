@@ -0,0 +1,465 @@
+use mc_schem::{Block, Region, region::WorldSlice};
+
+use crate::*;
+
+///## Orientation
+/// 元件放置时的朝向。
+///
+/// 先按mirror_x/mirror_z镜像，再绕Y轴顺时针(俯视，北->东)旋转rotation度。
+/// 变换在元件自身的包围盒内进行，变换后的元件仍从position开始放置。
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
+pub struct Orientation {
+    ///0, 90, 180, 270
+    #[serde(default)]
+    pub rotation: i32,
+    ///沿X轴镜像(东西翻转)
+    #[serde(default)]
+    pub mirror_x: bool,
+    ///沿Z轴镜像(南北翻转)
+    #[serde(default)]
+    pub mirror_z: bool,
+}
+impl Orientation {
+    ///顺时针旋转90度的次数
+    fn quarter_turns(&self) -> i32 {
+        if self.rotation % 90 != 0 {
+            error_begin();
+            panic!(
+                "invalid rotation {}, only 0, 90, 180 and 270 are supported",
+                self.rotation
+            );
+        }
+        (self.rotation / 90).rem_euclid(4)
+    }
+    ///变换后的大小
+    pub fn transform_size(&self, size: [i32; 3]) -> [i32; 3] {
+        if self.quarter_turns() % 2 == 1 {
+            [size[2], size[1], size[0]]
+        } else {
+            size
+        }
+    }
+    ///把元件内的相对位置变换到朝向后的相对位置。
+    ///
+    /// size为元件变换前的大小，包围盒外的点(比如端口)也按同样的方式变换。
+    pub fn transform_position(&self, pos: Position, size: [i32; 3]) -> Position {
+        let mut x = pos.x;
+        let mut z = pos.z;
+        if self.mirror_x {
+            x = size[0] - 1 - x;
+        }
+        if self.mirror_z {
+            z = size[2] - 1 - z;
+        }
+        let (mut sx, mut sz) = (size[0], size[2]);
+        for _ in 0..self.quarter_turns() {
+            //北(z-)转到东(x+)
+            (x, z) = (sz - 1 - z, x);
+            (sx, sz) = (sz, sx);
+        }
+        Position { x, y: pos.y, z }
+    }
+    ///变换水平方向
+    fn transform_direction(&self, direction: &str) -> String {
+        let mut direction = direction.to_string();
+        if self.mirror_x {
+            direction = match direction.as_str() {
+                "east" => "west",
+                "west" => "east",
+                other => other,
+            }
+            .to_string();
+        }
+        if self.mirror_z {
+            direction = match direction.as_str() {
+                "north" => "south",
+                "south" => "north",
+                other => other,
+            }
+            .to_string();
+        }
+        for _ in 0..self.quarter_turns() {
+            direction = match direction.as_str() {
+                "north" => "east",
+                "east" => "south",
+                "south" => "west",
+                "west" => "north",
+                other => other,
+            }
+            .to_string();
+        }
+        direction
+    }
+    ///变换方块状态，包括facing、红石线等方块的连接方向、axis和rotation
+    pub fn transform_block(&self, block: &Block) -> Block {
+        if *self == Orientation::default() {
+            return block.clone();
+        }
+        let mut result = block.clone();
+        for (key, value) in block.attributes.iter() {
+            match key.as_str() {
+                "facing" => {
+                    result.set_property(key, &self.transform_direction(value));
+                }
+                "north" | "east" | "south" | "west" => {
+                    //连接方向本身就是键，需要把值移动到新的方向上
+                    result.set_property(&self.transform_direction(key), value);
+                }
+                "axis" if self.quarter_turns() % 2 == 1 => {
+                    let axis = match value.as_str() {
+                        "x" => "z",
+                        "z" => "x",
+                        other => other,
+                    };
+                    result.set_property(key, axis);
+                }
+                "rotation" => {
+                    //告示牌等16方向的方块
+                    if let Ok(mut rotation) = value.parse::<i32>() {
+                        if self.mirror_x {
+                            rotation = 16 - rotation;
+                        }
+                        if self.mirror_z {
+                            rotation = 8 - rotation;
+                        }
+                        rotation += self.quarter_turns() * 4;
+                        result.set_property(key, &rotation.rem_euclid(16));
+                    }
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
+///## 放置区域
+/// 把source中的方块按朝向变换后放置到region的position处。
+///
+/// size为元件的大小，位置变换以它为准。
+pub fn stamp_region(
+    source: &Region,
+    size: [i32; 3],
+    orientation: &Orientation,
+    position: Position,
+    region: &mut Region,
+) {
+    let shape = source.shape();
+    for x in 0..shape[0] {
+        for y in 0..shape[1] {
+            for z in 0..shape[2] {
+                if let Some(blk) = source.block_at([x, y, z]) {
+                    let blk = orientation.transform_block(blk);
+                    let pos = position + orientation.transform_position(Position { x, y, z }, size);
+                    region.set_block(pos.to_slice(), &blk).unwrap_or_else(|_| {
+                        println!("failed to place block {} at {}", blk.id, pos);
+                        let global_shape = region.shape();
+                        if global_shape[0] <= pos.x
+                            || global_shape[1] <= pos.y
+                            || global_shape[2] <= pos.z
+                            || pos.x < 0
+                            || pos.y < 0
+                            || pos.z < 0
+                        {
+                            error_begin();
+                            panic!(
+                                "block position out of range: {}, the component size is ({},{},{})",
+                                pos, shape[0], shape[1], shape[2]
+                            );
+                        }
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIRECTIONS: [(&str, Position); 4] = [
+        ("north", Position { x: 0, y: 0, z: -1 }),
+        ("east", Position { x: 1, y: 0, z: 0 }),
+        ("south", Position { x: 0, y: 0, z: 1 }),
+        ("west", Position { x: -1, y: 0, z: 0 }),
+    ];
+
+    ///所有旋转和镜像的组合
+    fn orientations() -> Vec<Orientation> {
+        let mut all = vec![];
+        for rotation in [0, 90, 180, 270] {
+            for (mirror_x, mirror_z) in [(false, false), (true, false), (false, true), (true, true)]
+            {
+                all.push(Orientation {
+                    rotation,
+                    mirror_x,
+                    mirror_z,
+                });
+            }
+        }
+        all
+    }
+    fn rotated(rotation: i32) -> Orientation {
+        Orientation {
+            rotation,
+            ..Default::default()
+        }
+    }
+    fn block(id: &str) -> Block {
+        Block::from_id(id).unwrap()
+    }
+
+    #[test]
+    fn size_swaps_on_quarter_turns() {
+        let size = [3, 4, 2];
+        assert_eq!(rotated(90).transform_size(size), [2, 4, 3]);
+        assert_eq!(rotated(180).transform_size(size), size);
+        assert_eq!(rotated(-90).transform_size(size), [2, 4, 3]);
+        let mirrored = Orientation {
+            mirror_x: true,
+            mirror_z: true,
+            ..Default::default()
+        };
+        assert_eq!(mirrored.transform_size(size), size);
+    }
+
+    #[test]
+    fn positions_stay_inside_the_box() {
+        let size = [3, 2, 2];
+        for orientation in orientations() {
+            let new_size = orientation.transform_size(size);
+            let mut seen = HashSet::new();
+            for x in 0..size[0] {
+                for y in 0..size[1] {
+                    for z in 0..size[2] {
+                        let p = orientation.transform_position(Position { x, y, z }, size);
+                        assert!(
+                            (0..new_size[0]).contains(&p.x)
+                                && p.y == y
+                                && (0..new_size[2]).contains(&p.z),
+                            "{:?} moves ({},{},{}) to {}",
+                            orientation,
+                            x,
+                            y,
+                            z,
+                            p
+                        );
+                        assert!(seen.insert(p));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quarter_turn_moves_north_to_east() {
+        let size = [3, 1, 2];
+        let turn = rotated(90);
+        //西北角到东北角，东北角到东南角
+        assert_eq!(
+            turn.transform_position(Position { x: 0, y: 0, z: 0 }, size),
+            Position { x: 1, y: 0, z: 0 }
+        );
+        assert_eq!(
+            turn.transform_position(Position { x: 2, y: 0, z: 0 }, size),
+            Position { x: 1, y: 0, z: 2 }
+        );
+        //包围盒北边的端口转到东边
+        assert_eq!(
+            turn.transform_position(Position { x: 0, y: 0, z: -1 }, size),
+            Position { x: 2, y: 0, z: 0 }
+        );
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let size = [3, 2, 4];
+        for rotation in [0, 90, 180, 270] {
+            let back = rotated(360 - rotation);
+            let turned = rotated(rotation).transform_size(size);
+            for mirror in [
+                Orientation {
+                    mirror_x: true,
+                    ..Default::default()
+                },
+                Orientation {
+                    mirror_z: true,
+                    ..Default::default()
+                },
+            ] {
+                for x in -1..=size[0] {
+                    for z in -1..=size[2] {
+                        let p = Position { x, y: 1, z };
+                        let q = rotated(rotation).transform_position(p, size);
+                        assert_eq!(back.transform_position(q, turned), p);
+                        assert_eq!(
+                            mirror.transform_position(mirror.transform_position(p, size), size),
+                            p
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn directions_follow_positions() {
+        //从中心走一步，变换后仍然是facing变换后的方向
+        let size = [3, 1, 3];
+        let center = Position { x: 1, y: 0, z: 1 };
+        for orientation in orientations() {
+            let from = orientation.transform_position(center, size);
+            for (direction, step) in DIRECTIONS {
+                let to = orientation.transform_position(center + step, size);
+                let facing = orientation
+                    .transform_block(&block(&format!("minecraft:repeater[facing={}]", direction)));
+                let expected = DIRECTIONS.iter().find(|(_, s)| from + *s == to).unwrap().0;
+                assert_eq!(
+                    facing.attributes["facing"], expected,
+                    "{:?} {}",
+                    orientation, direction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn block_states_round_trip() {
+        let states = [
+            "minecraft:repeater[delay=2,facing=north]",
+            "minecraft:oak_log[axis=x]",
+            "minecraft:oak_log[axis=y]",
+            "minecraft:oak_sign[rotation=3]",
+            "minecraft:redstone_wire[east=up,north=side,south=none,west=none]",
+        ];
+        for state in states {
+            let original = block(state);
+            for rotation in [90, 180, 270] {
+                let turned = rotated(rotation).transform_block(&original);
+                assert_eq!(
+                    rotated(360 - rotation).transform_block(&turned),
+                    original,
+                    "{} {}",
+                    state,
+                    rotation
+                );
+            }
+            for (mirror_x, mirror_z) in [(true, false), (false, true), (true, true)] {
+                let mirror = Orientation {
+                    rotation: 0,
+                    mirror_x,
+                    mirror_z,
+                };
+                assert_eq!(
+                    mirror.transform_block(&mirror.transform_block(&original)),
+                    original,
+                    "{} {:?}",
+                    state,
+                    mirror
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn block_states_after_transform() {
+        let full_id = |orientation: Orientation, state: &str| {
+            orientation.transform_block(&block(state)).full_id()
+        };
+        let mirror_x = Orientation {
+            mirror_x: true,
+            ..Default::default()
+        };
+        let mirror_z = Orientation {
+            mirror_z: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            full_id(rotated(90), "minecraft:repeater[facing=north]"),
+            "minecraft:repeater[facing=east]"
+        );
+        assert_eq!(
+            full_id(mirror_x, "minecraft:repeater[facing=east]"),
+            "minecraft:repeater[facing=west]"
+        );
+        assert_eq!(
+            full_id(mirror_x, "minecraft:repeater[facing=north]"),
+            "minecraft:repeater[facing=north]"
+        );
+        assert_eq!(
+            full_id(rotated(90), "minecraft:oak_log[axis=x]"),
+            "minecraft:oak_log[axis=z]"
+        );
+        assert_eq!(
+            full_id(rotated(180), "minecraft:oak_log[axis=x]"),
+            "minecraft:oak_log[axis=x]"
+        );
+        //16方向：0南，4西，8北，12东
+        assert_eq!(
+            full_id(rotated(90), "minecraft:oak_sign[rotation=1]"),
+            "minecraft:oak_sign[rotation=5]"
+        );
+        assert_eq!(
+            full_id(mirror_x, "minecraft:oak_sign[rotation=4]"),
+            "minecraft:oak_sign[rotation=12]"
+        );
+        assert_eq!(
+            full_id(mirror_z, "minecraft:oak_sign[rotation=0]"),
+            "minecraft:oak_sign[rotation=8]"
+        );
+        assert_eq!(
+            full_id(
+                rotated(90),
+                "minecraft:redstone_wire[east=none,north=side,south=up,west=none]"
+            ),
+            "minecraft:redstone_wire[east=side,north=none,south=none,west=up]"
+        );
+    }
+
+    #[test]
+    fn stamp_rotates_blocks_into_place() {
+        let mut source = Region::with_shape([2, 1, 3]);
+        source
+            .set_block([0, 0, 0], &block("minecraft:repeater[facing=north]"))
+            .unwrap();
+        source
+            .set_block([1, 0, 2], &block("minecraft:stone"))
+            .unwrap();
+        let mut region = Region::with_shape([5, 1, 5]);
+        stamp_region(
+            &source,
+            [2, 1, 3],
+            &rotated(90),
+            Position { x: 1, y: 0, z: 1 },
+            &mut region,
+        );
+        assert_eq!(
+            region.block_at([3, 0, 1]).unwrap().full_id(),
+            "minecraft:repeater[facing=east]"
+        );
+        assert_eq!(
+            region.block_at([1, 0, 2]).unwrap().full_id(),
+            "minecraft:stone"
+        );
+        let placed = (0..5)
+            .flat_map(|x| (0..5).map(move |z| [x, 0, z]))
+            .filter(|pos| !region.block_at(*pos).unwrap().is_air())
+            .count();
+        assert_eq!(placed, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "block position out of range")]
+    fn stamp_outside_region_rejected() {
+        let source = Region::with_shape([2, 1, 3]);
+        let mut region = Region::with_shape([3, 1, 3]);
+        stamp_region(
+            &source,
+            [2, 1, 3],
+            &rotated(90),
+            Position { x: 1, y: 0, z: 0 },
+            &mut region,
+        );
+    }
+}
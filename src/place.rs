@@ -0,0 +1,442 @@
+use crate::*;
+
+/*
+自动布局：给没有position或者movable的元件分配位置。
+
+1. 固定的元件、电路端口、方块和导线占用的格子都向外扩张PLACE_MARGIN格，作为禁区
+2. 每次选出和已放置部分连接最多的元件，在项目size范围内找一个不进入禁区的位置，
+   使它到已放置端点的连接总长度(曼哈顿距离)最小，相同时取y、z、x最小的位置
+3. 全部放置后逐个拿起元件重新放置，直到没有元件移动或者达到MAX_PLACE_PASSES
+
+元件占用的格子是它朝向变换后的包围盒，加上端口、端口外的两格(导线从这里离开)和它们上方放红石线的格子。
+*/
+
+///元件之间至少留出的空格数，给导线留位置
+pub const PLACE_MARGIN: i32 = 3;
+///放置完成后重新放置的最多轮数
+pub const MAX_PLACE_PASSES: usize = 5;
+
+///## Endpoint
+/// 连接的一端解析后的结果
+#[derive(Debug, Clone, Copy)]
+pub enum Endpoint {
+    ///电路自身的输入输出，位置固定
+    Port(Position),
+    ///元件的端口，offset是端口相对元件原点的位置(已考虑朝向)
+    Component { index: usize, offset: Position },
+}
+impl Endpoint {
+    ///端点在电路中的位置，元件还没有放置时为None
+    pub fn position(&self, circuit: &Circuit) -> Option<Position> {
+        match self {
+            Endpoint::Port(pos) => Some(*pos),
+            Endpoint::Component { index, offset } => circuit.components[*index]
+                .position
+                .map(|origin| origin + *offset),
+        }
+    }
+}
+
+///## 解析连接的端点
+/// "元件名.端口名"解析为元件的端口，否则按电路的输入输出端口名查找
+pub fn resolve_endpoint(
+    circuit: &Circuit,
+    model_objects: &[Box<dyn ModelObject>],
+    name: &str,
+) -> Endpoint {
+    if let Some((comp_name, port_name)) = name.rsplit_once('.')
+        && let Some(index) = circuit.components.iter().position(|c| c.name == comp_name)
+    {
+        let comp = &circuit.components[index];
+        let model = model_objects
+            .iter()
+            .find(|m| m.get_name() == comp.model)
+            .unwrap_or_else(|| {
+                error_begin();
+                panic!("Model {} not found in imports", comp.model);
+            });
+        let port = model
+            .get_inputs()
+            .iter()
+            .chain(model.get_outputs().iter())
+            .find(|p| p.name == port_name)
+            .unwrap_or_else(|| {
+                error_begin();
+                panic!(
+                    "component {} ({}) has no port named {}",
+                    comp_name, comp.model, port_name
+                );
+            });
+        return Endpoint::Component {
+            index,
+            offset: comp
+                .orientation
+                .transform_position(port.position, model.get_size()),
+        };
+    }
+    match circuit
+        .inputs
+        .iter()
+        .chain(circuit.outputs.iter())
+        .find(|p| p.name == name)
+    {
+        Some(port) => Endpoint::Port(port.position),
+        None => {
+            error_begin();
+            panic!(
+                "net endpoint {} is neither a component port nor a circuit port",
+                name
+            );
+        }
+    }
+}
+
+///## 端口的出口
+/// 端口在包围盒外时，导线从端口向远离元件的方向离开，返回这个方向。
+/// size为朝向变换后的大小，端口在包围盒上方或下方时返回None
+pub fn port_exit(port: Position, size: [i32; 3]) -> Option<Position> {
+    if port.x < 0 {
+        Some(Position { x: -1, y: 0, z: 0 })
+    } else if port.x >= size[0] {
+        Some(Position { x: 1, y: 0, z: 0 })
+    } else if port.z < 0 {
+        Some(Position { x: 0, y: 0, z: -1 })
+    } else if port.z >= size[2] {
+        Some(Position { x: 0, y: 0, z: 1 })
+    } else {
+        None
+    }
+}
+
+///## 元件的占位
+/// 相对原点的包围盒大小，以及包围盒外还要占用的格子(端口和它外面的两格)
+struct Footprint {
+    size: [i32; 3],
+    ports: Vec<Position>,
+}
+impl Footprint {
+    fn new(comp: &Component, model: &dyn ModelObject) -> Self {
+        let size = comp.orientation.transform_size(model.get_size());
+        let mut ports = vec![];
+        for port in model.get_inputs().iter().chain(model.get_outputs().iter()) {
+            let pos = comp
+                .orientation
+                .transform_position(port.position, model.get_size());
+            ports.push(pos);
+            //导线离开端口后至少直走两格才能拐弯
+            if let Some(exit) = port_exit(pos, size) {
+                ports.push(pos + exit);
+                ports.push(pos + exit + exit);
+            }
+        }
+        Footprint { size, ports }
+    }
+    ///放在origin时占用的所有格子
+    fn cells(&self, origin: Position) -> Vec<Position> {
+        let mut cells = vec![];
+        for x in 0..self.size[0] {
+            for y in 0..self.size[1] {
+                for z in 0..self.size[2] {
+                    cells.push(origin + Position { x, y, z });
+                }
+            }
+        }
+        for port in self.ports.iter() {
+            cells.push(origin + *port);
+            cells.push(origin + *port + Position { x: 0, y: 1, z: 0 });
+        }
+        cells
+    }
+}
+
+///## 禁区
+/// 每个格子被多少个已放置物体的扩张范围覆盖，重新放置元件时可以减去它原来的占用
+#[derive(Default)]
+struct Forbidden(HashMap<Position, u32>);
+impl Forbidden {
+    fn dilate(cells: &[Position]) -> HashSet<Position> {
+        let mut dilated = HashSet::new();
+        for cell in cells {
+            for dx in -PLACE_MARGIN..=PLACE_MARGIN {
+                for dy in -PLACE_MARGIN..=PLACE_MARGIN {
+                    for dz in -PLACE_MARGIN..=PLACE_MARGIN {
+                        dilated.insert(
+                            *cell
+                                + Position {
+                                    x: dx,
+                                    y: dy,
+                                    z: dz,
+                                },
+                        );
+                    }
+                }
+            }
+        }
+        dilated
+    }
+    fn add(&mut self, cells: &[Position]) {
+        for cell in Self::dilate(cells) {
+            *self.0.entry(cell).or_insert(0) += 1;
+        }
+    }
+    fn remove(&mut self, cells: &[Position]) {
+        for cell in Self::dilate(cells) {
+            if let Some(count) = self.0.get_mut(&cell) {
+                *count -= 1;
+                if *count == 0 {
+                    self.0.remove(&cell);
+                }
+            }
+        }
+    }
+    fn blocks(&self, cells: &[Position]) -> bool {
+        cells.iter().any(|c| self.0.contains_key(c))
+    }
+}
+
+///## 自动布局
+/// 给没有position或者movable为true的元件分配位置，返回放置的元件个数
+pub fn place_components(circuit: &mut Circuit, model_objects: &[Box<dyn ModelObject>]) -> usize {
+    let footprints: Vec<Footprint> = circuit
+        .components
+        .iter()
+        .map(|comp| {
+            let model = model_objects
+                .iter()
+                .find(|m| m.get_name() == comp.model)
+                .unwrap_or_else(|| {
+                    error_begin();
+                    panic!("Model {} not found in imports", comp.model);
+                });
+            Footprint::new(comp, model.as_ref())
+        })
+        .collect();
+    let nets: Vec<(Endpoint, Endpoint)> = circuit
+        .nets
+        .iter()
+        .map(|net| {
+            (
+                resolve_endpoint(circuit, model_objects, &net.from),
+                resolve_endpoint(circuit, model_objects, &net.to),
+            )
+        })
+        .collect();
+    let movable: Vec<usize> = (0..circuit.components.len())
+        .filter(|i| circuit.components[*i].position.is_none() || circuit.components[*i].movable)
+        .collect();
+    for i in movable.iter() {
+        circuit.components[*i].position = None;
+    }
+    //固定的部分
+    let mut forbidden = Forbidden::default();
+    for (comp, footprint) in circuit.components.iter().zip(footprints.iter()) {
+        if let Some(origin) = comp.position {
+            forbidden.add(&footprint.cells(origin));
+        }
+    }
+    for port in circuit.inputs.iter().chain(circuit.outputs.iter()) {
+        forbidden.add(&[port.position, port.position + Position { x: 0, y: 1, z: 0 }]);
+    }
+    forbidden.add(
+        &circuit
+            .blocks
+            .iter()
+            .map(|b| b.position)
+            .collect::<Vec<Position>>(),
+    );
+    for wire in circuit.wires.iter() {
+        let path = wire.path();
+        forbidden.add(&path);
+        forbidden.add(
+            &path
+                .iter()
+                .map(|p| *p + Position { x: 0, y: 1, z: 0 })
+                .collect::<Vec<Position>>(),
+        );
+    }
+    //先放和已放置部分连接最多的元件
+    let mut unplaced = movable.clone();
+    while !unplaced.is_empty() {
+        let (k, _) = unplaced
+            .iter()
+            .enumerate()
+            .max_by_key(|(k, i)| {
+                let links = nets
+                    .iter()
+                    .filter(|(a, b)| {
+                        (is_component(a, **i) && b.position(circuit).is_some())
+                            || (is_component(b, **i) && a.position(circuit).is_some())
+                    })
+                    .count();
+                (links, std::cmp::Reverse(*k))
+            })
+            .unwrap();
+        let index = unplaced.remove(k);
+        let origin = best_origin(circuit, &footprints[index], index, &nets, &forbidden);
+        circuit.components[index].position = Some(origin);
+        forbidden.add(&footprints[index].cells(origin));
+    }
+    //逐个拿起重新放置
+    for _ in 0..MAX_PLACE_PASSES {
+        let mut moved = false;
+        for index in movable.iter().copied() {
+            let old = circuit.components[index].origin();
+            forbidden.remove(&footprints[index].cells(old));
+            circuit.components[index].position = None;
+            let origin = best_origin(circuit, &footprints[index], index, &nets, &forbidden);
+            circuit.components[index].position = Some(origin);
+            forbidden.add(&footprints[index].cells(origin));
+            moved |= origin != old;
+        }
+        if !moved {
+            break;
+        }
+    }
+    movable.len()
+}
+
+fn is_component(endpoint: &Endpoint, index: usize) -> bool {
+    matches!(endpoint, Endpoint::Component { index: i, .. } if *i == index)
+}
+
+///所有连接的总长度，没有放置的端点不计
+pub fn total_net_length(circuit: &Circuit, model_objects: &[Box<dyn ModelObject>]) -> u64 {
+    circuit
+        .nets
+        .iter()
+        .filter_map(|net| {
+            let from = resolve_endpoint(circuit, model_objects, &net.from).position(circuit)?;
+            let to = resolve_endpoint(circuit, model_objects, &net.to).position(circuit)?;
+            Some(from.distance(to))
+        })
+        .sum()
+}
+
+///给元件index找连接总长度最小的合法位置
+fn best_origin(
+    circuit: &Circuit,
+    footprint: &Footprint,
+    index: usize,
+    nets: &[(Endpoint, Endpoint)],
+    forbidden: &Forbidden,
+) -> Position {
+    //(自身端口的偏移, 另一端已放置的位置)
+    let links: Vec<(Position, Position)> = nets
+        .iter()
+        .filter_map(|(a, b)| match (a, b) {
+            (Endpoint::Component { index: i, offset }, other)
+            | (other, Endpoint::Component { index: i, offset })
+                if *i == index && !is_component(other, index) =>
+            {
+                other.position(circuit).map(|pos| (*offset, pos))
+            }
+            _ => None,
+        })
+        .collect();
+    //包围盒和端口都要在项目范围内
+    let cells = footprint.cells(Position { x: 0, y: 0, z: 0 });
+    let min = |f: fn(&Position) -> i32| cells.iter().map(f).min().unwrap_or(0);
+    let max = |f: fn(&Position) -> i32| cells.iter().map(f).max().unwrap_or(0);
+    let size = circuit.size;
+    let mut candidates = vec![];
+    for x in -min(|p| p.x)..size.x - max(|p| p.x) {
+        for y in -min(|p| p.y)..size.y - max(|p| p.y) {
+            for z in -min(|p| p.z)..size.z - max(|p| p.z) {
+                let origin = Position { x, y, z };
+                let cost: u64 = links
+                    .iter()
+                    .map(|(offset, pos)| (origin + *offset).distance(*pos))
+                    .sum();
+                candidates.push((cost, y, z, x));
+            }
+        }
+    }
+    candidates.sort_unstable();
+    candidates
+        .into_iter()
+        .map(|(_, y, z, x)| Position { x, y, z })
+        .find(|origin| !forbidden.blocks(&footprint.cells(*origin)))
+        .unwrap_or_else(|| {
+            error_begin();
+            panic!(
+                "no room left for component {} in a circuit of size {}, enlarge the size or move fixed parts",
+                circuit.components[index].name, size
+            );
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/lib");
+
+    ///四个and串成一条链，a1固定，a3转了90度，其余等待布局
+    fn chain(size: [i32; 3]) -> Circuit {
+        serde_json::from_str(&format!(
+            r#"{{"name":"chain","size":{{"x":{},"y":{},"z":{}}},
+            "imports":[{{"modelName":"and","modelType":"component","path":"and.json"}}],
+            "components":[
+                {{"name":"a1","model":"and","position":{{"x":0,"y":0,"z":0}}}},
+                {{"name":"a2","model":"and"}},
+                {{"name":"a3","model":"and","orientation":{{"rotation":90}}}},
+                {{"name":"a4","model":"and"}}],
+            "nets":[{{"from":"a1.output","to":"a2.input0"}},{{"from":"a2.output","to":"a3.input0"}},
+                {{"from":"a3.output","to":"a4.input0"}},{{"from":"a4.output","to":"y"}}],
+            "wires":[],"blocks":[],"inputs":[],
+            "outputs":[{{"name":"y","position":{{"x":{},"y":1,"z":0}}}}]}}"#,
+            size[0],
+            size[1],
+            size[2],
+            size[0] - 1
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn placed_boxes_stay_inside_and_apart() {
+        let mut circuit = chain([30, 4, 30]);
+        let models = load_imports(&circuit.imports, Some(LIBRARY));
+        assert_eq!(place_components(&mut circuit, &models), 3);
+        let boxes: Vec<(Position, [i32; 3])> = circuit
+            .components
+            .iter()
+            .map(|comp| {
+                let size = comp.orientation.transform_size(models[0].get_size());
+                (comp.origin(), size)
+            })
+            .collect();
+        assert_eq!(boxes[0].0, Position { x: 0, y: 0, z: 0 });
+        for (origin, size) in boxes.iter() {
+            assert!(origin.x >= 0 && origin.y >= 0 && origin.z >= 0);
+            assert!(origin.x + size[0] <= circuit.size.x);
+            assert!(origin.y + size[1] <= circuit.size.y);
+            assert!(origin.z + size[2] <= circuit.size.z);
+        }
+        //任意两个包围盒之间至少在一个方向上隔开PLACE_MARGIN格以上
+        for (i, (a, sa)) in boxes.iter().enumerate() {
+            for (b, sb) in boxes.iter().skip(i + 1) {
+                let gap = |a0: i32, sa: i32, b0: i32, sb: i32| (b0 - (a0 + sa)).max(a0 - (b0 + sb));
+                let gap = [
+                    gap(a.x, sa[0], b.x, sb[0]),
+                    gap(a.y, sa[1], b.y, sb[1]),
+                    gap(a.z, sa[2], b.z, sb[2]),
+                ];
+                assert!(
+                    gap.iter().any(|g| *g >= PLACE_MARGIN),
+                    "{} and {} are too close",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "no room left")]
+    fn no_room_left() {
+        let mut circuit = chain([12, 4, 12]);
+        let models = load_imports(&circuit.imports, Some(LIBRARY));
+        place_components(&mut circuit, &models);
+    }
+}
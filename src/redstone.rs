@@ -0,0 +1,1013 @@
+use std::cmp::max;
+
+use mc_schem::region::WorldSlice;
+
+use crate::*;
+
+/*
+方块级的红石仿真，直接在元件的nbt上按游戏刻运行。
+
+每一游戏刻:
+1. 应用到期的状态变化(火把、中继器、比较器、按钮、侦测器、红石灯)
+2. 重新计算所有红石线的能量。红石线、方块的充能是即时的
+3. 检查每个有延迟的元件，输入和当前状态不符时安排在延迟之后变化
+4. 侦测器检查它看着的方块，状态变了就安排一个脉冲
+
+元件的端口位置是导线的底座方块(导线的红石线在它上方)，和编译时导线的位置一致:
+输入端口在上方放一格被强制充能的红石线，输出端口读取上方那格红石线的能量。
+端口位置本身就是红石线时直接使用这格红石线。
+*/
+
+///火把在这么多游戏刻内熄灭8次就会烧毁
+const BURNOUT_WINDOW: u64 = 60;
+const BURNOUT_TOGGLES: usize = 8;
+///烧毁的火把经过这么多游戏刻后恢复
+const BURNOUT_RECOVERY: u64 = 160;
+///火把、比较器、侦测器的延迟(游戏刻)
+const COMPONENT_DELAY: u64 = 2;
+///红石灯熄灭的延迟(游戏刻)
+const LAMP_OFF_DELAY: u64 = 4;
+
+///## BlockKind
+/// 仿真关心的方块种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    ///能被充能的实心方块
+    Solid,
+    ///不导电的方块，包括空气、下半砖、栅栏等
+    Transparent,
+    ///不导电但顶面完整的方块：玻璃、上半砖、倒置的楼梯，红石线可以放在上面
+    Platform,
+    Dust,
+    ///attached: 火把依附的方块所在方向
+    Torch { attached: GlobalDirection },
+    ///facing: 输入端所在方向，输出在反方向
+    Repeater { facing: GlobalDirection, delay: u64 },
+    Comparator { facing: GlobalDirection, subtract: bool },
+    Lever { attached: GlobalDirection },
+    ///duration: 按下后保持的游戏刻
+    Button { attached: GlobalDirection, duration: u64 },
+    RedstoneBlock,
+    ///facing: 侦测的方向，输出在反方向
+    Observer { facing: GlobalDirection },
+    ///红石灯也是能被充能的实心方块
+    Lamp,
+}
+
+///不导电的方块id片段
+const TRANSPARENT_IDS: [&str; 28] = [
+    "torch", "glass", "slab", "stairs", "leaves", "carpet", "glowstone", "ice", "sea_lantern", "piston",
+    "hopper", "chest", "sign", "fence", "wall", "pane", "bars", "rail", "pressure_plate", "door",
+    "snow", "structure_void", "cake", "head", "skull", "banner", "ladder", "scaffolding",
+];
+
+impl BlockKind {
+    fn of(block: &Block) -> BlockKind {
+        let id = block.id.as_str();
+        let attr = |key: &str| block.attributes.get(key).map(|v| v.as_str());
+        let facing = || GlobalDirection::from(attr("facing").unwrap_or("north"));
+        //墙上的东西facing指向离开墙的方向，依附在反方向
+        let attached_by_face = || match attr("face") {
+            Some("floor") => GlobalDirection::Down,
+            Some("ceiling") => GlobalDirection::Up,
+            _ => facing().opposite(),
+        };
+        if block.is_air() {
+            BlockKind::Transparent
+        } else if id == "redstone_wire" {
+            BlockKind::Dust
+        } else if id == "redstone_torch" {
+            BlockKind::Torch {
+                attached: GlobalDirection::Down,
+            }
+        } else if id == "redstone_wall_torch" {
+            BlockKind::Torch {
+                attached: facing().opposite(),
+            }
+        } else if id == "repeater" {
+            BlockKind::Repeater {
+                facing: facing(),
+                delay: attr("delay").and_then(|d| d.parse().ok()).unwrap_or(1),
+            }
+        } else if id == "comparator" {
+            BlockKind::Comparator {
+                facing: facing(),
+                subtract: attr("mode") == Some("subtract"),
+            }
+        } else if id == "lever" {
+            BlockKind::Lever {
+                attached: attached_by_face(),
+            }
+        } else if id.ends_with("_button") {
+            BlockKind::Button {
+                attached: attached_by_face(),
+                //石质按钮1秒，木质按钮1.5秒
+                duration: if id.contains("stone") { 20 } else { 30 },
+            }
+        } else if id == "redstone_block" {
+            BlockKind::RedstoneBlock
+        } else if id == "observer" {
+            BlockKind::Observer { facing: facing() }
+        } else if id == "redstone_lamp" {
+            BlockKind::Lamp
+        } else if id.contains("slab") {
+            match attr("type") {
+                Some("double") => BlockKind::Solid,
+                Some("top") => BlockKind::Platform,
+                _ => BlockKind::Transparent,
+            }
+        } else if id.ends_with("glass") || (id.contains("stairs") && attr("half") == Some("top")) {
+            BlockKind::Platform
+        } else if TRANSPARENT_IDS.iter().any(|t| id.contains(t)) {
+            BlockKind::Transparent
+        } else {
+            BlockKind::Solid
+        }
+    }
+    fn is_solid(self) -> bool {
+        matches!(self, BlockKind::Solid | BlockKind::Lamp)
+    }
+    ///红石线能不能放在上面，也就是能不能爬上这个方块
+    fn supports_dust(self) -> bool {
+        matches!(
+            self,
+            BlockKind::Solid | BlockKind::Lamp | BlockKind::Platform
+        )
+    }
+}
+
+///## Cell
+/// 一个方块和它在仿真中的状态
+#[derive(Debug, Clone)]
+struct Cell {
+    kind: BlockKind,
+    ///火把点亮、中继器/拉杆/按钮/侦测器激活、红石灯点亮
+    on: bool,
+    ///比较器的输出能量
+    level: i32,
+    ///中继器是否被锁定
+    locked: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    On(bool),
+    Level(i32),
+}
+
+///## BlockSimulator
+/// 方块级的逐刻红石仿真器
+#[derive(Clone)]
+pub struct BlockSimulator {
+    cells: HashMap<Position, Cell>,
+    ///每格红石线当前的能量
+    dust: HashMap<Position, i32>,
+    ///被强制充能的红石线(输入端口)
+    forced: HashMap<Position, i32>,
+    inputs: HashMap<String, Position>,
+    outputs: HashMap<String, Position>,
+    tick: u64,
+    ///事件队列: 游戏刻 -> (位置, 变化)
+    queue: BTreeMap<u64, Vec<(Position, Change)>>,
+    ///已经安排了变化的位置
+    pending: HashSet<Position>,
+    ///火把最近熄灭的时刻，用于判断烧毁
+    toggles: HashMap<Position, Vec<u64>>,
+    ///烧毁的火把恢复的时刻
+    burnt: HashMap<Position, u64>,
+    ///侦测器上一刻看到的方块状态
+    observed: HashMap<Position, String>,
+}
+
+impl BlockSimulator {
+    ///从region建立仿真，方块的初始状态取自方块属性(lit、powered等)
+    pub fn new(region: &Region) -> Self {
+        let mut cells = HashMap::new();
+        let shape = region.shape();
+        for x in 0..shape[0] {
+            for y in 0..shape[1] {
+                for z in 0..shape[2] {
+                    let block = region.block_at([x, y, z]).unwrap();
+                    if block.is_air() || block.is_structure_void() {
+                        continue;
+                    }
+                    let kind = BlockKind::of(block);
+                    let attr = |key: &str| block.attributes.get(key).map(|v| v.as_str());
+                    let on = match kind {
+                        BlockKind::Torch { .. } => attr("lit") != Some("false"),
+                        BlockKind::Lamp => attr("lit") == Some("true"),
+                        _ => attr("powered") == Some("true"),
+                    };
+                    cells.insert(
+                        Position { x, y, z },
+                        Cell {
+                            kind,
+                            on,
+                            level: 0,
+                            locked: attr("locked") == Some("true"),
+                        },
+                    );
+                }
+            }
+        }
+        let mut sim = Self {
+            cells,
+            dust: HashMap::new(),
+            forced: HashMap::new(),
+            inputs: HashMap::new(),
+            outputs: HashMap::new(),
+            tick: 0,
+            queue: BTreeMap::new(),
+            pending: HashSet::new(),
+            toggles: HashMap::new(),
+            burnt: HashMap::new(),
+            observed: HashMap::new(),
+        };
+        sim.update_dust();
+        let observers = sim.positions(|k| matches!(k, BlockKind::Observer { .. }));
+        for pos in observers {
+            let seen = sim.observer_target(pos);
+            sim.observed.insert(pos, sim.state_of(seen));
+        }
+        sim
+    }
+    fn kind_at(&self, pos: Position) -> BlockKind {
+        self.cells
+            .get(&pos)
+            .map(|c| c.kind)
+            .unwrap_or(BlockKind::Transparent)
+    }
+    fn positions(&self, filter: impl Fn(BlockKind) -> bool) -> Vec<Position> {
+        let mut positions: Vec<Position> = self
+            .cells
+            .iter()
+            .filter(|(_, c)| filter(c.kind))
+            .map(|(p, _)| *p)
+            .collect();
+        //固定顺序，让仿真结果可重复
+        positions.sort_by_key(|p| (p.x, p.y, p.z));
+        positions
+    }
+    ///端口位置上用来接导线的红石线：端口本身是红石线时就是它，否则是它上方一格。
+    /// 没有方块的地方补上底座和红石线
+    fn port_dust(&mut self, pos: Position) -> Position {
+        if self.kind_at(pos) == BlockKind::Dust {
+            return pos;
+        }
+        let dust = pos + GlobalDirection::Up.offset();
+        self.cells.entry(pos).or_insert(Cell {
+            kind: BlockKind::Solid,
+            on: false,
+            level: 0,
+            locked: false,
+        });
+        self.cells.entry(dust).or_insert(Cell {
+            kind: BlockKind::Dust,
+            on: false,
+            level: 0,
+            locked: false,
+        });
+        dust
+    }
+    ///添加一个输入端口，端口是拉杆或按钮时操作它，否则给端口的红石线充能
+    pub fn add_input(&mut self, name: &str, pos: Position) {
+        let pos = match self.kind_at(pos) {
+            BlockKind::Lever { .. } | BlockKind::Button { .. } => pos,
+            _ => self.port_dust(pos),
+        };
+        self.inputs.insert(name.to_string(), pos);
+    }
+    ///添加一个输出端口
+    pub fn add_output(&mut self, name: &str, pos: Position) {
+        let pos = match self.kind_at(pos) {
+            BlockKind::Transparent | BlockKind::Platform | BlockKind::Solid | BlockKind::Dust => {
+                self.port_dust(pos)
+            }
+            _ => pos,
+        };
+        self.outputs.insert(name.to_string(), pos);
+    }
+    ///设置输入端口的能量，在下一次step时生效
+    pub fn set_input(&mut self, name: &str, power: i32) {
+        let pos = *self.inputs.get(name).unwrap_or_else(|| {
+            error_begin();
+            panic!("input {} not found in block simulation", name);
+        });
+        let tick = self.tick;
+        let cell = self.cells.get_mut(&pos).unwrap();
+        match cell.kind {
+            BlockKind::Lever { .. } => cell.on = power > 0,
+            BlockKind::Button { duration, .. } => {
+                //按下按钮，到时间后弹起
+                if power > 0 && !cell.on {
+                    cell.on = true;
+                    self.queue
+                        .entry(tick + duration)
+                        .or_default()
+                        .push((pos, Change::On(false)));
+                    self.pending.insert(pos);
+                }
+            }
+            _ => {
+                self.forced.insert(pos, power.clamp(0, 15));
+            }
+        }
+    }
+    ///端口当前的能量
+    pub fn output(&self, name: &str) -> i32 {
+        let pos = *self.outputs.get(name).unwrap_or_else(|| {
+            error_begin();
+            panic!("output {} not found in block simulation", name);
+        });
+        self.power_at(pos)
+    }
+    ///端口当前的能量，输入端口也可以读取
+    pub fn port_power(&self, name: &str) -> i32 {
+        match self.inputs.get(name) {
+            Some(pos) if !self.outputs.contains_key(name) => self.power_at(*pos),
+            _ => self.output(name),
+        }
+    }
+    ///一个位置上的能量：红石线的能量、元件的输出或方块被充能的等级
+    pub fn power_at(&self, pos: Position) -> i32 {
+        let Some(cell) = self.cells.get(&pos) else {
+            return 0;
+        };
+        match cell.kind {
+            BlockKind::Dust => self.dust.get(&pos).copied().unwrap_or(0),
+            BlockKind::Comparator { .. } => cell.level,
+            BlockKind::Solid | BlockKind::Lamp => self.block_power(pos),
+            BlockKind::Transparent | BlockKind::Platform => 0,
+            _ => {
+                if cell.on {
+                    15
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    ///元件输出指向的位置，不是元件时返回None
+    fn output_target(&self, pos: Position) -> Option<Position> {
+        match self.kind_at(pos) {
+            BlockKind::Repeater { facing, .. }
+            | BlockKind::Comparator { facing, .. }
+            | BlockKind::Observer { facing } => Some(pos + facing.opposite().offset()),
+            _ => None,
+        }
+    }
+    ///from处的中继器、比较器或侦测器输出到to的能量
+    fn directed_output(&self, from: Position, to: Position) -> i32 {
+        if self.output_target(from) != Some(to) {
+            return 0;
+        }
+        let cell = &self.cells[&from];
+        match cell.kind {
+            BlockKind::Comparator { .. } => cell.level,
+            _ => {
+                if cell.on {
+                    15
+                } else {
+                    0
+                }
+            }
+        }
+    }
+    ///实心方块被强充能的等级：中继器/比较器/侦测器指向它，下方的火把，依附在它上面的拉杆/按钮
+    fn strong_power(&self, pos: Position) -> i32 {
+        let mut power = 0;
+        for dir in GlobalDirection::ALL {
+            let n = pos + dir.offset();
+            let Some(cell) = self.cells.get(&n) else {
+                continue;
+            };
+            let p = match cell.kind {
+                BlockKind::Repeater { .. }
+                | BlockKind::Comparator { .. }
+                | BlockKind::Observer { .. } => self.directed_output(n, pos),
+                BlockKind::Torch { .. } if dir == GlobalDirection::Down && cell.on => 15,
+                BlockKind::Lever { attached } | BlockKind::Button { attached, .. }
+                    if n + attached.offset() == pos && cell.on =>
+                {
+                    15
+                }
+                _ => 0,
+            };
+            power = max(power, p);
+        }
+        power
+    }
+    ///实心方块被弱充能的等级：上方的红石线，或者指向它的红石线
+    fn weak_power(&self, pos: Position) -> i32 {
+        let mut power = 0;
+        let above = pos + GlobalDirection::Up.offset();
+        if self.kind_at(above) == BlockKind::Dust {
+            power = max(power, self.dust.get(&above).copied().unwrap_or(0));
+        }
+        for dir in GlobalDirection::HORIZONTAL {
+            let n = pos + dir.offset();
+            if self.kind_at(n) == BlockKind::Dust && self.dust_shape(n).contains(&dir.opposite()) {
+                power = max(power, self.dust.get(&n).copied().unwrap_or(0));
+            }
+        }
+        power
+    }
+    ///实心方块被充能的等级
+    fn block_power(&self, pos: Position) -> i32 {
+        if !self.kind_at(pos).is_solid() {
+            return 0;
+        }
+        max(self.strong_power(pos), self.weak_power(pos))
+    }
+    ///红石线在水平方向上和dir方向的东西是否相连
+    fn dust_connects(&self, pos: Position, dir: GlobalDirection) -> bool {
+        let n = pos + dir.offset();
+        let up = GlobalDirection::Up.offset();
+        match self.kind_at(n) {
+            BlockKind::Dust
+            | BlockKind::Torch { .. }
+            | BlockKind::Lever { .. }
+            | BlockKind::Button { .. }
+            | BlockKind::RedstoneBlock
+            | BlockKind::Comparator { .. } => true,
+            //中继器只在前后两端相连
+            BlockKind::Repeater { facing, .. } => facing == dir || facing == dir.opposite(),
+            //侦测器只在输出端相连
+            BlockKind::Observer { .. } => self.output_target(n) == Some(pos),
+            kind => {
+                //上坡：上方不是实心方块，旁边能放红石线；下坡：旁边不是实心方块
+                (self.kind_at(n + up) == BlockKind::Dust
+                    && !self.kind_at(pos + up).is_solid()
+                    && kind.supports_dust())
+                    || (!kind.is_solid()
+                        && self.kind_at(n + GlobalDirection::Down.offset()) == BlockKind::Dust)
+            }
+        }
+    }
+    ///红石线指向的水平方向。只连一边时也指向对面，一边都不连时是十字形
+    fn dust_shape(&self, pos: Position) -> Vec<GlobalDirection> {
+        let mut shape: Vec<GlobalDirection> = GlobalDirection::HORIZONTAL
+            .into_iter()
+            .filter(|d| self.dust_connects(pos, *d))
+            .collect();
+        if shape.len() == 1 {
+            shape.push(shape[0].opposite());
+        } else if shape.is_empty() {
+            shape = GlobalDirection::HORIZONTAL.to_vec();
+        }
+        shape
+    }
+    ///红石线从红石线以外的东西得到的能量
+    fn dust_source(&self, pos: Position) -> i32 {
+        let mut power = self.forced.get(&pos).copied().unwrap_or(0);
+        for dir in GlobalDirection::ALL {
+            let n = pos + dir.offset();
+            let Some(cell) = self.cells.get(&n) else {
+                continue;
+            };
+            let p = match cell.kind {
+                BlockKind::RedstoneBlock => 15,
+                BlockKind::Torch { attached } if n + attached.offset() != pos && cell.on => 15,
+                BlockKind::Lever { .. } | BlockKind::Button { .. } if cell.on => 15,
+                BlockKind::Repeater { .. }
+                | BlockKind::Comparator { .. }
+                | BlockKind::Observer { .. } => self.directed_output(n, pos),
+                //只有强充能的方块才能给红石线充能
+                BlockKind::Solid | BlockKind::Lamp => self.strong_power(n),
+                _ => 0,
+            };
+            power = max(power, p);
+        }
+        power
+    }
+    ///这格红石线的能量能传到的红石线，包括上下坡。
+    /// 上坡只要求上方不是实心方块，所以能爬上玻璃和上半砖；
+    /// 下坡要求旁边不是实心方块、脚下是实心方块，所以玻璃和上半砖上的红石线传不下去
+    pub fn dust_neighbors(&self, pos: Position) -> Vec<Position> {
+        let up = GlobalDirection::Up.offset();
+        let down = GlobalDirection::Down.offset();
+        let mut neighbors = Vec::new();
+        for dir in GlobalDirection::HORIZONTAL {
+            let n = pos + dir.offset();
+            let kind = self.kind_at(n);
+            if kind == BlockKind::Dust {
+                neighbors.push(n);
+            } else if kind.supports_dust()
+                && self.kind_at(n + up) == BlockKind::Dust
+                && !self.kind_at(pos + up).is_solid()
+            {
+                neighbors.push(n + up);
+            } else if !kind.is_solid()
+                && self.kind_at(n + down) == BlockKind::Dust
+                && self.kind_at(pos + down).is_solid()
+            {
+                neighbors.push(n + down);
+            }
+        }
+        neighbors
+    }
+    ///重新计算所有红石线的能量：先取各自的能量源，再沿红石线每格衰减1传播
+    fn update_dust(&mut self) {
+        let dusts = self.positions(|k| k == BlockKind::Dust);
+        let mut power: HashMap<Position, i32> =
+            dusts.iter().map(|p| (*p, self.dust_source(*p))).collect();
+        let mut work: Vec<Position> = dusts.clone();
+        while let Some(pos) = work.pop() {
+            let p = power[&pos] - 1;
+            if p <= 0 {
+                continue;
+            }
+            for n in self.dust_neighbors(pos) {
+                if power[&n] < p {
+                    power.insert(n, p);
+                    work.push(n);
+                }
+            }
+        }
+        self.dust = power;
+    }
+    ///component处的元件从dir方向得到的输入能量
+    fn input_power(&self, pos: Position, dir: GlobalDirection) -> i32 {
+        let n = pos + dir.offset();
+        let Some(cell) = self.cells.get(&n) else {
+            return 0;
+        };
+        match cell.kind {
+            BlockKind::Dust => self.dust.get(&n).copied().unwrap_or(0),
+            BlockKind::Solid | BlockKind::Lamp => self.block_power(n),
+            BlockKind::RedstoneBlock => 15,
+            BlockKind::Torch { .. } | BlockKind::Lever { .. } | BlockKind::Button { .. } => {
+                if cell.on {
+                    15
+                } else {
+                    0
+                }
+            }
+            BlockKind::Repeater { .. }
+            | BlockKind::Comparator { .. }
+            | BlockKind::Observer { .. } => self.directed_output(n, pos),
+            BlockKind::Transparent | BlockKind::Platform => 0,
+        }
+    }
+    ///中继器和比较器侧面的输入：比较器接受红石线、中继器、比较器和红石块，
+    /// 中继器只被中继器和比较器锁定(dust为false)
+    fn side_power(&self, pos: Position, facing: GlobalDirection, dust: bool) -> i32 {
+        GlobalDirection::HORIZONTAL
+            .into_iter()
+            .filter(|d| *d != facing && *d != facing.opposite())
+            .map(|d| {
+                let n = pos + d.offset();
+                match self.kind_at(n) {
+                    BlockKind::Dust if dust => self.dust.get(&n).copied().unwrap_or(0),
+                    BlockKind::RedstoneBlock if dust => 15,
+                    BlockKind::Repeater { .. } | BlockKind::Comparator { .. } => {
+                        self.directed_output(n, pos)
+                    }
+                    _ => 0,
+                }
+            })
+            .max()
+            .unwrap_or(0)
+    }
+    ///旁边有激活的火把、拉杆、按钮或红石块，红石灯会被它们直接点亮
+    fn next_to_source(&self, pos: Position) -> bool {
+        GlobalDirection::ALL.iter().any(|d| {
+            let n = pos + d.offset();
+            self.cells.get(&n).is_some_and(|cell| match cell.kind {
+                BlockKind::RedstoneBlock => true,
+                BlockKind::Torch { attached } => cell.on && n + attached.offset() != pos,
+                BlockKind::Lever { .. } | BlockKind::Button { .. } => cell.on,
+                _ => false,
+            })
+        })
+    }
+    ///方块的可观察状态，给侦测器比较用
+    fn state_of(&self, pos: Position) -> String {
+        match self.cells.get(&pos) {
+            None => String::new(),
+            Some(cell) => match cell.kind {
+                BlockKind::Dust => format!("dust{}", self.dust.get(&pos).copied().unwrap_or(0)),
+                BlockKind::Comparator { .. } => format!("comparator{}", cell.level),
+                _ => format!("{:?}{}{}", cell.kind, cell.on, cell.locked),
+            },
+        }
+    }
+    fn observer_target(&self, pos: Position) -> Position {
+        match self.kind_at(pos) {
+            BlockKind::Observer { facing } => pos + facing.offset(),
+            _ => pos,
+        }
+    }
+    fn schedule(&mut self, pos: Position, delay: u64, change: Change) {
+        self.queue
+            .entry(self.tick + delay)
+            .or_default()
+            .push((pos, change));
+        self.pending.insert(pos);
+    }
+    ///应用到期的变化
+    fn apply_due(&mut self) {
+        for (pos, change) in self.queue.remove(&self.tick).unwrap_or_default() {
+            self.pending.remove(&pos);
+            let tick = self.tick;
+            let cell = self.cells.get_mut(&pos).unwrap();
+            match change {
+                Change::On(on) => {
+                    if matches!(cell.kind, BlockKind::Torch { .. }) && cell.on && !on {
+                        self.toggles.entry(pos).or_default().push(tick);
+                    }
+                    cell.on = on;
+                }
+                Change::Level(level) => cell.level = level,
+            }
+            //侦测器的脉冲持续2游戏刻
+            if matches!(cell.kind, BlockKind::Observer { .. }) && change == Change::On(true) {
+                self.schedule(pos, COMPONENT_DELAY, Change::On(false));
+            }
+        }
+    }
+    ///检查每个元件，安排需要的变化
+    fn update_components(&mut self) {
+        let components = self.positions(|k| {
+            !matches!(
+                k,
+                BlockKind::Solid
+                    | BlockKind::Transparent
+                    | BlockKind::Platform
+                    | BlockKind::Dust
+                    | BlockKind::RedstoneBlock
+            )
+        });
+        for pos in components {
+            let cell = self.cells[&pos].clone();
+            if self.pending.contains(&pos) {
+                continue;
+            }
+            match cell.kind {
+                BlockKind::Torch { attached } => {
+                    if let Some(until) = self.burnt.get(&pos) {
+                        if self.tick < *until {
+                            continue;
+                        }
+                        self.burnt.remove(&pos);
+                    }
+                    let lit = self.block_power(pos + attached.offset()) == 0;
+                    if lit == cell.on {
+                        continue;
+                    }
+                    //烧毁：短时间内熄灭太多次
+                    let tick = self.tick;
+                    let recent = self.toggles.entry(pos).or_default();
+                    recent.retain(|t| t + BURNOUT_WINDOW > tick);
+                    if lit && recent.len() >= BURNOUT_TOGGLES {
+                        self.burnt.insert(pos, tick + BURNOUT_RECOVERY);
+                        recent.clear();
+                        continue;
+                    }
+                    self.schedule(pos, COMPONENT_DELAY, Change::On(lit));
+                }
+                BlockKind::Repeater { facing, delay } => {
+                    let locked = self.side_power(pos, facing, false) > 0;
+                    self.cells.get_mut(&pos).unwrap().locked = locked;
+                    let powered = self.input_power(pos, facing) > 0;
+                    if !locked && powered != cell.on {
+                        self.schedule(pos, delay * 2, Change::On(powered));
+                    }
+                }
+                BlockKind::Comparator { facing, subtract } => {
+                    let rear = self.input_power(pos, facing);
+                    let side = self.side_power(pos, facing, true);
+                    let level = if subtract {
+                        max(rear - side, 0)
+                    } else if rear >= side {
+                        rear
+                    } else {
+                        0
+                    };
+                    if level != cell.level {
+                        self.schedule(pos, COMPONENT_DELAY, Change::Level(level));
+                    }
+                }
+                BlockKind::Observer { .. } => {
+                    let seen = self.state_of(self.observer_target(pos));
+                    if self.observed.get(&pos) != Some(&seen) {
+                        self.observed.insert(pos, seen);
+                        if !cell.on {
+                            self.schedule(pos, COMPONENT_DELAY, Change::On(true));
+                        }
+                    }
+                }
+                BlockKind::Lamp => {
+                    let lit = self.block_power(pos) > 0 || self.next_to_source(pos);
+                    if lit && !cell.on {
+                        self.cells.get_mut(&pos).unwrap().on = true;
+                    } else if !lit && cell.on {
+                        self.schedule(pos, LAMP_OFF_DELAY, Change::On(false));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    ///运行一游戏刻
+    pub fn step(&mut self) {
+        self.apply_due();
+        self.update_dust();
+        self.update_components();
+        self.tick += 1;
+    }
+    ///一直运行到没有待处理的变化，返回经过的游戏刻数。超过max_ticks时返回None
+    pub fn run_until_stable(&mut self, max_ticks: u64) -> Option<u64> {
+        let start = self.tick;
+        for _ in 0..max_ticks {
+            self.step();
+            if self.queue.is_empty() {
+                return Some(self.tick - start);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(x: i32, y: i32, z: i32) -> Position {
+        Position { x, y, z }
+    }
+    ///在空的region里放置方块，建立仿真
+    fn simulator(blocks: &[([i32; 3], &str)]) -> BlockSimulator {
+        let mut region = Region::with_shape([20, 8, 6]);
+        for (p, id) in blocks {
+            region.set_block(*p, &Block::from_id(id).unwrap()).unwrap();
+        }
+        BlockSimulator::new(&region)
+    }
+    ///运行ticks游戏刻，返回每一刻之后输出端口的能量
+    fn trace(sim: &mut BlockSimulator, name: &str, ticks: u64) -> Vec<i32> {
+        (0..ticks)
+            .map(|_| {
+                sim.step();
+                sim.output(name)
+            })
+            .collect()
+    }
+    ///x从0开始的一排石头，上面放红石线
+    fn dust_line(len: i32, z: i32) -> Vec<([i32; 3], &'static str)> {
+        let mut blocks = vec![];
+        for x in 0..len {
+            blocks.push(([x, 0, z], "stone"));
+            blocks.push(([x, 1, z], "redstone_wire"));
+        }
+        blocks
+    }
+
+    #[test]
+    fn dust_decays_one_per_block() {
+        let mut sim = simulator(&dust_line(17, 0));
+        sim.add_input("a", pos(0, 0, 0));
+        sim.set_input("a", 15);
+        sim.step();
+        for x in 0..17 {
+            assert_eq!(
+                sim.power_at(pos(x, 1, 0)),
+                max(15 - x, 0),
+                "dust at x={}",
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn dust_climbs_glass_and_top_slabs_but_does_not_descend() {
+        let blocks = [
+            ([0, 0, 0], "stone"),
+            ([0, 1, 0], "redstone_wire"),
+            ([1, 1, 0], "glass"),
+            ([1, 2, 0], "redstone_wire"),
+            ([2, 2, 0], "smooth_stone_slab[type=top]"),
+            ([2, 3, 0], "redstone_wire"),
+        ];
+        let mut sim = simulator(&blocks);
+        sim.add_input("bottom", pos(0, 1, 0));
+        sim.set_input("bottom", 15);
+        sim.step();
+        assert_eq!(sim.power_at(pos(1, 2, 0)), 14);
+        assert_eq!(sim.power_at(pos(2, 3, 0)), 13);
+
+        let mut sim = simulator(&blocks);
+        sim.add_input("top", pos(2, 3, 0));
+        sim.set_input("top", 15);
+        sim.step();
+        assert_eq!(sim.power_at(pos(1, 2, 0)), 0);
+        assert_eq!(sim.power_at(pos(0, 1, 0)), 0);
+    }
+
+    #[test]
+    fn slabs_by_type() {
+        let kind = |id: &str| BlockKind::of(&Block::from_id(id).unwrap());
+        assert_eq!(kind("smooth_stone_slab[type=double]"), BlockKind::Solid);
+        assert_eq!(kind("smooth_stone_slab[type=top]"), BlockKind::Platform);
+        assert_eq!(
+            kind("smooth_stone_slab[type=bottom]"),
+            BlockKind::Transparent
+        );
+        assert_eq!(kind("stone_stairs[half=top]"), BlockKind::Platform);
+        assert_eq!(kind("stone_stairs[half=bottom]"), BlockKind::Transparent);
+        assert_eq!(kind("glass_pane"), BlockKind::Transparent);
+    }
+
+    ///红石线(0,1,0)指向石头(1,1,0)，石头侧面(2,1,0)是墙上的火把
+    fn torch_sim() -> BlockSimulator {
+        let mut sim = simulator(&[
+            ([0, 0, 0], "stone"),
+            ([1, 1, 0], "stone"),
+            ([2, 1, 0], "redstone_wall_torch[facing=east]"),
+        ]);
+        sim.add_input("a", pos(0, 0, 0));
+        sim.add_output("o", pos(2, 1, 0));
+        sim
+    }
+
+    #[test]
+    fn torch_inverts_after_two_ticks() {
+        let mut sim = torch_sim();
+        sim.run_until_stable(100).unwrap();
+        assert_eq!(sim.output("o"), 15);
+        sim.set_input("a", 15);
+        assert_eq!(trace(&mut sim, "o", 4), vec![15, 15, 0, 0]);
+        sim.set_input("a", 0);
+        assert_eq!(trace(&mut sim, "o", 4), vec![0, 0, 15, 15]);
+    }
+
+    #[test]
+    fn torch_burns_out_after_eight_toggles() {
+        let mut sim = torch_sim();
+        sim.run_until_stable(100).unwrap();
+        for _ in 0..BURNOUT_TOGGLES {
+            sim.set_input("a", 15);
+            trace(&mut sim, "o", 4);
+            sim.set_input("a", 0);
+            trace(&mut sim, "o", 3);
+        }
+        //第8次熄灭之后不再点亮，直到恢复
+        assert!(trace(&mut sim, "o", 20).iter().all(|p| *p == 0));
+        trace(&mut sim, "o", BURNOUT_RECOVERY);
+        assert_eq!(sim.output("o"), 15);
+    }
+
+    ///输入红石线(0,1,0)，中继器或比较器(1,1,0)朝西，输出红石线(2,1,0)，侧面(1,1,1)
+    fn diode_sim(id: &str, side: &str) -> BlockSimulator {
+        let mut sim = simulator(&[
+            ([0, 0, 0], "stone"),
+            ([1, 0, 0], "stone"),
+            ([1, 1, 0], id),
+            ([2, 0, 0], "stone"),
+            ([1, 0, 1], "stone"),
+            ([1, 1, 1], side),
+            ([1, 0, 2], "stone"),
+        ]);
+        sim.add_input("a", pos(0, 0, 0));
+        sim.add_input("side", pos(1, 0, 2));
+        sim.add_output("o", pos(2, 0, 0));
+        sim
+    }
+
+    #[test]
+    fn repeater_delay_is_two_ticks_per_step() {
+        for delay in 1..=4 {
+            let mut sim = diode_sim(&format!("repeater[facing=west,delay={}]", delay), "air");
+            sim.set_input("a", 15);
+            let powers = trace(&mut sim, "o", 10);
+            let first = powers.iter().position(|p| *p == 15).unwrap() as u64;
+            assert_eq!(first, delay * 2, "delay {}", delay);
+        }
+    }
+
+    #[test]
+    fn repeater_locked_by_side_repeater() {
+        let mut sim = diode_sim("repeater[facing=west]", "repeater[facing=south]");
+        sim.set_input("side", 15);
+        sim.run_until_stable(100).unwrap();
+        sim.set_input("a", 15);
+        sim.run_until_stable(100).unwrap();
+        assert_eq!(sim.output("o"), 0);
+        sim.set_input("side", 0);
+        sim.run_until_stable(100).unwrap();
+        assert_eq!(sim.output("o"), 15);
+        //锁定时保持打开的状态
+        sim.set_input("side", 15);
+        sim.run_until_stable(100).unwrap();
+        sim.set_input("a", 0);
+        sim.run_until_stable(100).unwrap();
+        assert_eq!(sim.output("o"), 15);
+    }
+
+    #[test]
+    fn comparator_compare_and_subtract() {
+        for (mode, side, expected) in [
+            ("compare", 7, 10),
+            ("compare", 10, 10),
+            ("compare", 12, 0),
+            ("subtract", 7, 3),
+            ("subtract", 12, 0),
+        ] {
+            let mut sim = diode_sim(
+                &format!("comparator[facing=west,mode={}]", mode),
+                "redstone_wire",
+            );
+            //侧面的红石线离输入端口一格，多衰减1
+            sim.set_input("a", 10);
+            sim.set_input("side", side + 1);
+            sim.run_until_stable(100).unwrap();
+            assert_eq!(sim.power_at(pos(1, 1, 1)), side);
+            assert_eq!(sim.output("o"), expected, "{} with side {}", mode, side);
+        }
+    }
+
+    #[test]
+    fn button_pulse_length() {
+        for (id, ticks) in [("stone_button", 20), ("oak_button", 30)] {
+            let mut sim = simulator(&[
+                ([0, 0, 0], "stone"),
+                ([0, 1, 0], &format!("{}[face=floor]", id)),
+            ]);
+            sim.add_input("b", pos(0, 1, 0));
+            sim.add_output("b", pos(0, 1, 0));
+            sim.set_input("b", 15);
+            let powers = trace(&mut sim, "b", 40);
+            assert_eq!(powers.iter().filter(|p| **p == 15).count(), ticks, "{}", id);
+        }
+    }
+
+    #[test]
+    fn observer_pulse() {
+        let mut sim = simulator(&[
+            ([0, 0, 0], "stone"),
+            ([1, 1, 0], "observer[facing=west]"),
+            ([2, 0, 0], "stone"),
+        ]);
+        sim.add_input("a", pos(0, 0, 0));
+        sim.add_output("o", pos(2, 0, 0));
+        sim.set_input("a", 15);
+        let powers = trace(&mut sim, "o", 10);
+        assert_eq!(powers.iter().filter(|p| **p == 15).count(), 2);
+        assert_eq!(powers.last(), Some(&0));
+    }
+
+    #[test]
+    fn strong_and_weak_powering() {
+        //红石线弱充能的方块不能给旁边的红石线充能
+        let mut blocks = vec![
+            ([0, 0, 0], "stone"),
+            ([1, 1, 0], "stone"),
+            ([2, 0, 0], "stone"),
+            ([2, 1, 0], "redstone_wire"),
+        ];
+        //中继器强充能的方块可以
+        blocks.extend([
+            ([0, 0, 2], "stone"),
+            ([1, 0, 2], "stone"),
+            ([1, 1, 2], "repeater[facing=west]"),
+            ([2, 1, 2], "stone"),
+            ([3, 0, 2], "stone"),
+            ([3, 1, 2], "redstone_wire"),
+        ]);
+        let mut sim = simulator(&blocks);
+        sim.add_input("weak", pos(0, 0, 0));
+        sim.add_input("strong", pos(0, 0, 2));
+        sim.set_input("weak", 15);
+        sim.set_input("strong", 15);
+        sim.run_until_stable(100).unwrap();
+        assert_eq!(sim.power_at(pos(1, 1, 0)), 15);
+        assert_eq!(sim.power_at(pos(2, 1, 0)), 0);
+        assert_eq!(sim.power_at(pos(2, 1, 2)), 15);
+        assert_eq!(sim.power_at(pos(3, 1, 2)), 15);
+    }
+
+    ///库里的and.nbt，每个方块要按它自己的pos读取
+    #[test]
+    fn library_and_gate() {
+        let nbt = concat!(env!("CARGO_MANIFEST_DIR"), "/lib/nbt/and.nbt");
+        let region = load_nbt_region(nbt);
+        let id = |p: [i32; 3]| region.block_at(p).unwrap().id.clone();
+        assert_eq!(id([1, 1, 0]), "redstone_wall_torch");
+        assert_eq!(id([2, 1, 0]), "repeater");
+        assert_eq!(id([1, 2, 1]), "redstone_wire");
+        assert_eq!(id([0, 0, 0]), "air");
+        let mut settled = BlockSimulator::new(&region);
+        settled.add_input("input0", pos(0, 2, 4));
+        settled.add_input("input1", pos(2, 2, 4));
+        settled.add_output("output", pos(3, 1, 0));
+        assert!(settled.run_until_stable(1000).is_some());
+        for (a, b, expected) in [(0, 0, 0), (15, 0, 0), (0, 15, 0), (15, 15, 15)] {
+            let mut sim = settled.clone();
+            sim.set_input("input0", a);
+            sim.set_input("input1", b);
+            assert!(sim.run_until_stable(1000).is_some());
+            assert_eq!(sim.output("output"), expected, "inputs {} {}", a, b);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::*;
+
+///## RepeaterReport
+/// 一根导线自动插入中继器的结果
+pub struct RepeaterReport {
+    pub wire: String,
+    pub repeaters: Vec<Position>,
+    ///增加的延迟，单位为红石刻(1红石刻=2游戏刻)
+    pub delay: i32,
+}
+
+///## 信号方向
+/// 根据连接关系推断每根导线的信号方向，true表示从start流向end。
+///
+/// 电路输入和元件输出是信号源，从信号源出发沿导线传播；无法推断的导线不在结果中。
+pub fn wire_signal_directions(
+    circuit: &Circuit,
+    model_objects: &[Box<dyn ModelObject>],
+) -> HashMap<String, bool> {
+    let mut sources: HashSet<Position> = circuit.inputs.iter().map(|p| p.position).collect();
+    let mut sinks: HashSet<Position> = circuit.outputs.iter().map(|p| p.position).collect();
+    for comp in circuit.components.iter() {
+        let model = model_objects
+            .iter()
+            .find(|m| m.get_name() == comp.model)
+            .unwrap_or_else(|| {
+                error_begin();
+                panic!("Model {} not found in imports", comp.model);
+            });
+        for port in model.get_outputs() {
+            sources.insert(comp.port_position(port, model.as_ref()));
+        }
+        for port in model.get_inputs() {
+            sinks.insert(comp.port_position(port, model.as_ref()));
+        }
+    }
+    let mut directions = HashMap::new();
+    //信号沿导线逐根传播，直到没有新的导线能确定方向
+    loop {
+        let mut changed = false;
+        for wire in circuit.wires.iter() {
+            if directions.contains_key(&wire.name) {
+                continue;
+            }
+            let forward = if sources.contains(&wire.start) || sinks.contains(&wire.end) {
+                true
+            } else if sources.contains(&wire.end) || sinks.contains(&wire.start) {
+                false
+            } else {
+                continue;
+            };
+            sources.insert(if forward { wire.end } else { wire.start });
+            directions.insert(wire.name.clone(), forward);
+            changed = true;
+        }
+        if !changed {
+            break;
+        }
+    }
+    directions
+}
+
+///这一格上是否已经有中继器
+fn has_repeater(blocks: &[BlockInfo], new_blocks: &[BlockInfo], cell: Position) -> bool {
+    blocks
+        .iter()
+        .chain(new_blocks.iter())
+        .any(|b| b.id.contains("repeater") && on_wire_cell(b.position, cell))
+}
+
+///## 自动插入中继器
+/// 对长度超过MAX_REDSTONE_DISTANCE的导线，按信号方向每隔一段放置一个中继器，
+/// 中继器作为方块加入circuit.blocks，编译时放置在红石线的位置。
+///
+/// 阶梯和塔上不能放中继器，这时向信号源方向寻找最近的平地。
+/// 几根导线共用一段路径时，分叉的格子上不放中继器，已经放置的中继器也会被后面的导线沿用。
+pub fn insert_repeaters(
+    circuit: &mut Circuit,
+    model_objects: &[Box<dyn ModelObject>],
+) -> Vec<RepeaterReport> {
+    let directions = wire_signal_directions(circuit, model_objects);
+    //每一格在所有导线上相邻的格子，超过两个的是分叉
+    let mut adjacent: HashMap<Position, HashSet<Position>> = HashMap::new();
+    for wire in circuit.wires.iter() {
+        let path = wire.path();
+        for pair in path.windows(2) {
+            adjacent.entry(pair[0]).or_default().insert(pair[1]);
+            adjacent.entry(pair[1]).or_default().insert(pair[0]);
+        }
+    }
+    let mut reports = vec![];
+    let mut new_blocks = vec![];
+    for wire in circuit.wires.iter() {
+        //信号源处的红石线能量是15，走MAX_REDSTONE_DISTANCE格后衰减到0
+        if wire.length() < MAX_REDSTONE_DISTANCE {
+            continue;
+        }
+        //按信号前进的顺序排列
+        let forward = directions.get(&wire.name).copied().unwrap_or_else(|| {
+            println!(
+                "warning: cannot tell the signal direction of wire {}, assuming {} -> {}",
+                wire.name, wire.start, wire.end
+            );
+            true
+        });
+        let mut headings = wire.headings();
+        if !forward {
+            //反向时，每一格的方向是正向时下一格方向的反方向
+            let forward = headings.clone();
+            for (i, heading) in headings.iter_mut().enumerate() {
+                heading.1 = forward[(i + 1).min(forward.len() - 1)].1.opposite();
+            }
+            headings.reverse();
+        }
+        let last = headings.len() - 1;
+        let flat = |i: usize| {
+            let y = headings[i].0.y;
+            i > 0
+                && i < last
+                && headings[i - 1].0.y == y
+                && headings[i + 1].0.y == y
+                && adjacent[&headings[i].0].len() <= 2
+        };
+        let mut repeaters = vec![];
+        //上一个中继器(或信号源)的位置
+        let mut previous = 0;
+        while previous + MAX_REDSTONE_DISTANCE as usize <= last {
+            let ideal = previous + MAX_REDSTONE_DISTANCE as usize;
+            if let Some(index) = (previous + 1..=ideal)
+                .rev()
+                .find(|&i| has_repeater(&circuit.blocks, &new_blocks, headings[i].0))
+            {
+                previous = index;
+                continue;
+            }
+            if ideal == last && previous > 0 {
+                //中继器输出到下一格的能量是15，终点本身就是端口，信号刚好能到达
+                break;
+            }
+            let index = (previous + 1..=ideal).rev().find(|&i| flat(i)).unwrap_or_else(|| {
+                error_begin();
+                panic!(
+                    "cannot place a repeater on wire {} between {} and {}: no flat cell to put it on",
+                    wire.name, headings[previous].0, headings[ideal].0
+                );
+            });
+            let (pos, heading) = headings[index];
+            //中继器的facing指向输入端
+            let mut properties = BTreeMap::new();
+            properties.insert(
+                "facing".to_string(),
+                serde_json::Value::String(heading.opposite().to_string()),
+            );
+            properties.insert("delay".to_string(), serde_json::Value::from(1));
+            let position = pos + Position { x: 0, y: 1, z: 0 };
+            new_blocks.push(BlockInfo {
+                position,
+                id: "repeater".to_string(),
+                properties: Some(Properties(properties)),
+            });
+            repeaters.push(position);
+            previous = index;
+        }
+        reports.push(RepeaterReport {
+            wire: wire.name.clone(),
+            delay: repeaters.len() as i32,
+            repeaters,
+        });
+    }
+    circuit.blocks.extend(new_blocks);
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///从a到y的一根导线，points是起点、拐点和终点，signal_from_start为false时信号从终点流向起点
+    fn circuit(points: &[[i32; 3]], signal_from_start: bool) -> Circuit {
+        let json = |p: &[i32; 3]| format!(r#"{{"x":{},"y":{},"z":{}}}"#, p[0], p[1], p[2]);
+        let (first, last) = (json(&points[0]), json(points.last().unwrap()));
+        let (input, output) = if signal_from_start {
+            (&first, &last)
+        } else {
+            (&last, &first)
+        };
+        serde_json::from_str(&format!(
+            r#"{{"name":"test","size":{{"x":64,"y":8,"z":8}},"imports":[],"components":[],
+            "wires":[{{"name":"w","start":{},"end":{},"waypoints":[{}],"baseMaterial":"stone"}}],
+            "blocks":[],"inputs":[{{"name":"a","position":{}}}],"outputs":[{{"name":"y","position":{}}}]}}"#,
+            first,
+            last,
+            points[1..points.len() - 1].iter().map(json).collect::<Vec<String>>().join(","),
+            input,
+            output
+        ))
+        .unwrap()
+    }
+    ///插入的中继器的位置和facing
+    fn repeaters(circuit: &Circuit) -> Vec<(Position, String)> {
+        circuit
+            .blocks
+            .iter()
+            .map(|b| {
+                (
+                    b.position,
+                    b.properties.as_ref().unwrap().get("facing").unwrap(),
+                )
+            })
+            .collect()
+    }
+    fn at(x: i32, y: i32) -> Position {
+        Position { x, y, z: 0 }
+    }
+
+    #[test]
+    fn repeaters_every_fifteen_blocks_facing_the_source() {
+        let mut forward = circuit(&[[0, 0, 0], [40, 0, 0]], true);
+        let reports = insert_repeaters(&mut forward, &[]);
+        assert_eq!(reports[0].repeaters, [at(15, 1), at(30, 1)]);
+        assert_eq!(reports[0].delay, 2);
+        assert_eq!(
+            repeaters(&forward),
+            [
+                (at(15, 1), "west".to_string()),
+                (at(30, 1), "west".to_string())
+            ]
+        );
+        //已经有中继器时不再插入
+        assert!(insert_repeaters(&mut forward, &[])[0].repeaters.is_empty());
+        assert_eq!(forward.blocks.len(), 2);
+        //导线反着写，信号方向不变
+        let mut reversed = circuit(&[[40, 0, 0], [0, 0, 0]], false);
+        insert_repeaters(&mut reversed, &[]);
+        assert_eq!(repeaters(&reversed), repeaters(&forward));
+        //信号从x=40流向x=0
+        let mut westward = circuit(&[[0, 0, 0], [40, 0, 0]], false);
+        insert_repeaters(&mut westward, &[]);
+        assert_eq!(
+            repeaters(&westward),
+            [
+                (at(25, 1), "east".to_string()),
+                (at(10, 1), "east".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn short_wire_needs_no_repeater() {
+        let mut short = circuit(&[[0, 0, 0], [14, 0, 0]], true);
+        assert!(insert_repeaters(&mut short, &[]).is_empty());
+        //终点在第15格时能量衰减到0，终点是端口，中继器放在前一格
+        let mut exact = circuit(&[[0, 0, 0], [15, 0, 0]], true);
+        assert_eq!(insert_repeaters(&mut exact, &[])[0].repeaters, [at(14, 1)]);
+    }
+
+    #[test]
+    fn repeater_moves_off_the_staircase() {
+        //第15到17格是阶梯，第14格下一格就是台阶，只能放在第13格
+        let mut stairs = circuit(&[[0, 0, 0], [14, 0, 0], [17, 3, 0], [25, 3, 0]], true);
+        insert_repeaters(&mut stairs, &[]);
+        assert_eq!(repeaters(&stairs), [(at(13, 1), "west".to_string())]);
+    }
+}
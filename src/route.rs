@@ -0,0 +1,490 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::*;
+
+/*
+按circuit.nets自动布线。
+
+每根连接生成一根从from的端口到to的端口的导线，用A*在项目范围内寻找路径:
+    每一步水平前进一格，Y可以同时升降一格(阶梯)，拐弯和升降都多算一格的代价
+    每一格占用底座和红石线，阶梯处低处红石线的上方还要留空，否则会切断阶梯
+    别的导线可以从红石线上方越过(底座压在红石线上)
+    元件的包围盒向外扩张一格是禁区，导线只有首尾两格可以进入，也就是从端口垂直离开元件
+    端口外的一格(出口)和它周围只有这个端口的信号可以经过，免得别的导线把端口堵住
+    不同信号的红石线水平相邻或者斜上下相邻时会连在一起，所以必须隔开
+同一个端口出发的导线可以从已经布好的导线的任意一格分叉，共用前面的一段。
+信号用并查集确定：一根连接的两端、已有导线的两端属于同一个信号。
+
+先布短的连接。有连接失败时把它们挪到最前面重新布线，保留失败最少的一次。
+布线完成后和--auto-repeater一样插入中继器。
+*/
+
+///布线生成的导线底座材质
+pub const ROUTE_BASE_MATERIAL: &str = "stone";
+///有连接布线失败时，调整顺序重新布线的最多次数
+pub const MAX_ROUTE_ATTEMPTS: usize = 8;
+
+///## RouteReport
+/// 自动布线的结果
+pub struct RouteReport {
+    ///生成的导线数
+    pub routed: usize,
+    ///没有找到路径的连接
+    pub failed: Vec<String>,
+    ///生成的导线总长度
+    pub length: i32,
+    pub repeaters: Vec<RepeaterReport>,
+}
+
+const UP: Position = Position { x: 0, y: 1, z: 0 };
+///水平的四个方向，下标也用作A*状态中的来向，4表示没有来向
+const DIRECTIONS: [Position; 4] = [
+    Position { x: 1, y: 0, z: 0 },
+    Position { x: -1, y: 0, z: 0 },
+    Position { x: 0, y: 0, z: 1 },
+    Position { x: 0, y: 0, z: -1 },
+];
+
+///## Signals
+/// 端口位置的并查集，同一个集合的端口是同一个信号
+#[derive(Default)]
+struct Signals {
+    index: HashMap<Position, usize>,
+    parent: Vec<usize>,
+}
+impl Signals {
+    fn id(&mut self, pos: Position) -> usize {
+        if let Some(i) = self.index.get(&pos) {
+            return self.find(*i);
+        }
+        self.parent.push(self.parent.len());
+        self.index.insert(pos, self.parent.len() - 1);
+        self.parent.len() - 1
+    }
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+    fn union(&mut self, a: Position, b: Position) {
+        let (a, b) = (self.id(a), self.id(b));
+        self.parent[a] = b;
+    }
+}
+
+///## Grid
+/// 布线时项目范围内每一格的占用情况
+#[derive(Clone)]
+struct Grid {
+    size: Position,
+    ///元件(向外扩张一格)和方块
+    obstacles: HashSet<Position>,
+    ///导线和端口的底座、红石线、阶梯上方留空的格子属于哪个信号
+    reserved: HashMap<Position, usize>,
+    ///红石线属于哪个信号
+    dust: HashMap<Position, usize>,
+    ///端口外的一格，只有这个端口的信号可以经过
+    exits: HashMap<Position, usize>,
+}
+impl Grid {
+    ///导线的一格，底座在cell
+    fn reserve(&mut self, cell: Position, signal: usize) {
+        self.reserved.insert(cell, signal);
+        self.reserved.insert(cell + UP, signal);
+        self.dust.insert(cell + UP, signal);
+    }
+    ///导线的每一格，阶梯处低处红石线的上方也要留空
+    fn reserve_path(&mut self, path: &[Position], signal: usize) {
+        for cell in path.iter() {
+            self.reserve(*cell, signal);
+        }
+        for pair in path.windows(2) {
+            if pair[0].y != pair[1].y {
+                let lower = if pair[0].y < pair[1].y {
+                    pair[0]
+                } else {
+                    pair[1]
+                };
+                self.reserved.insert(lower + UP + UP, signal);
+            }
+        }
+    }
+    ///信号signal的导线能不能经过cell
+    fn is_free(&self, cell: Position, signal: usize) -> bool {
+        if cell.x < 0
+            || cell.z < 0
+            || cell.y < 0
+            || cell.x >= self.size.x
+            || cell.z >= self.size.z
+            || cell.y + 1 >= self.size.y
+        {
+            return false;
+        }
+        if self.obstacles.contains(&cell) || self.obstacles.contains(&(cell + UP)) {
+            return false;
+        }
+        if self.reserved.contains_key(&cell) || self.reserved.contains_key(&(cell + UP)) {
+            return false;
+        }
+        //别的端口的出口，以及出口旁边会连到出口红石线的格子
+        for offset in [Position { x: 0, y: 0, z: 0 }]
+            .iter()
+            .chain(DIRECTIONS.iter())
+        {
+            for dy in -1..=1 {
+                let pos = cell + *offset + Position { x: 0, y: dy, z: 0 };
+                if self.exits.get(&pos).is_some_and(|s| *s != signal) {
+                    return false;
+                }
+            }
+        }
+        //周围不能有别的信号的红石线
+        let dust = cell + UP;
+        for offset in [Position { x: 0, y: 0, z: 0 }]
+            .iter()
+            .chain(DIRECTIONS.iter())
+        {
+            for dy in -1..=1 {
+                let pos = dust + *offset + Position { x: 0, y: dy, z: 0 };
+                if self.dust.get(&pos).is_some_and(|s| *s != signal) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+    ///升降时低处红石线的上方必须是空的
+    fn can_climb(&self, from: Position, to: Position, signal: usize) -> bool {
+        let lower = if to.y > from.y { from } else { to };
+        let above = lower + UP + UP;
+        !self.obstacles.contains(&above) && self.reserved.get(&above).is_none_or(|s| *s == signal)
+    }
+}
+
+///## 自动布线
+/// 给circuit.nets中还没有导线相连的连接生成导线，然后插入中继器
+pub fn route_nets(circuit: &mut Circuit, model_objects: &[Box<dyn ModelObject>]) -> RouteReport {
+    let mut nets: Vec<(String, Position, Position)> = circuit
+        .nets
+        .iter()
+        .map(|net| {
+            let position = |name: &str| {
+                resolve_endpoint(circuit, model_objects, name).position(circuit).unwrap_or_else(|| {
+                    error_begin();
+                    panic!("net endpoint {} belongs to a component without a position, place it with --place", name);
+                })
+            };
+            let name = format!("{}__{}", net.from, net.to).replace('.', "_");
+            (name, position(&net.from), position(&net.to))
+        })
+        .collect();
+    //已经有导线直接相连的不再布线
+    nets.retain(|(_, from, to)| {
+        !circuit
+            .wires
+            .iter()
+            .any(|w| (w.start == *from && w.end == *to) || (w.start == *to && w.end == *from))
+    });
+    //先布短的
+    nets.sort_by_key(|(_, from, to)| from.distance(*to));
+
+    let mut signals = Signals::default();
+    for (_, from, to) in nets.iter() {
+        signals.union(*from, *to);
+    }
+    for wire in circuit.wires.iter() {
+        signals.union(wire.start, wire.end);
+    }
+    let mut grid = Grid {
+        size: circuit.size,
+        obstacles: circuit.blocks.iter().map(|b| b.position).collect(),
+        reserved: HashMap::new(),
+        dust: HashMap::new(),
+        exits: HashMap::new(),
+    };
+    for comp in circuit.components.iter() {
+        let model = model_objects
+            .iter()
+            .find(|m| m.get_name() == comp.model)
+            .unwrap_or_else(|| {
+                error_begin();
+                panic!("Model {} not found in imports", comp.model);
+            });
+        let size = comp.orientation.transform_size(model.get_size());
+        let origin = comp.origin();
+        for x in -1..=size[0] {
+            for y in -1..=size[1] {
+                for z in -1..=size[2] {
+                    grid.obstacles.insert(origin + Position { x, y, z });
+                }
+            }
+        }
+        //没有连接的端口也要和别的导线隔开，端口外的一格留给它自己的导线
+        for port in model.get_inputs().iter().chain(model.get_outputs().iter()) {
+            let pos = comp.port_position(port, model.as_ref());
+            let signal = signals.id(pos);
+            grid.reserve(pos, signal);
+            let local = comp
+                .orientation
+                .transform_position(port.position, model.get_size());
+            if let Some(exit) = port_exit(local, size) {
+                grid.exits.insert(pos + exit, signal);
+            }
+        }
+    }
+    for port in circuit.inputs.iter().chain(circuit.outputs.iter()) {
+        let signal = signals.id(port.position);
+        grid.reserve(port.position, signal);
+    }
+    for wire in circuit.wires.iter() {
+        let signal = signals.id(wire.start);
+        grid.reserve_path(&wire.path(), signal);
+    }
+
+    //布线失败时把失败的连接挪到最前面重新布线，保留失败最少的一次
+    let nets: Vec<(String, Position, Position, usize)> = nets
+        .into_iter()
+        .map(|(name, from, to)| {
+            let signal = signals.id(from);
+            (name, from, to, signal)
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..nets.len()).collect();
+    let mut best: Option<(Vec<Wire>, Vec<usize>)> = None;
+    for _ in 0..MAX_ROUTE_ATTEMPTS {
+        let (wires, failed) = route_in_order(&grid, &nets, &order);
+        let done = failed.is_empty();
+        order.retain(|i| !failed.contains(i));
+        order.splice(0..0, failed.iter().copied());
+        if best.as_ref().is_none_or(|(_, f)| failed.len() < f.len()) {
+            best = Some((wires, failed));
+        }
+        if done {
+            break;
+        }
+    }
+    let (wires, failed) = best.unwrap_or_default();
+    let mut report = RouteReport {
+        routed: wires.len(),
+        failed: failed.iter().map(|i| nets[*i].0.clone()).collect(),
+        length: wires.iter().map(|w| w.length()).sum(),
+        repeaters: vec![],
+    };
+    for i in failed {
+        let (name, from, to, _) = &nets[i];
+        println!(
+            "warning: no route found for net {} from {} to {}",
+            name, from, to
+        );
+    }
+    circuit.wires.extend(wires);
+    report.repeaters = insert_repeaters(circuit, model_objects);
+    report
+}
+
+///按order的顺序逐根布线，返回生成的导线和失败的连接
+fn route_in_order(
+    grid: &Grid,
+    nets: &[(String, Position, Position, usize)],
+    order: &[usize],
+) -> (Vec<Wire>, Vec<usize>) {
+    let mut grid = grid.clone();
+    let mut wires = vec![];
+    let mut failed = vec![];
+    //每个起点已经布好的路径，后面的导线可以从上面分叉
+    let mut trees: HashMap<Position, Vec<Vec<Position>>> = HashMap::new();
+    for i in order.iter().copied() {
+        let (name, from, to, signal) = &nets[i];
+        let tree = trees.entry(*from).or_default();
+        let Some(path) = find_path(&grid, *from, *to, *signal, tree) else {
+            failed.push(i);
+            continue;
+        };
+        grid.reserve_path(&path, *signal);
+        wires.push(path_to_wire(name.clone(), &path));
+        tree.push(path);
+    }
+    (wires, failed)
+}
+
+///A*的状态：底座位置和来向
+type State = (Position, usize);
+
+///从from到to寻找路径，tree中的路径的每一格(终点除外)都可以作为起点
+fn find_path(
+    grid: &Grid,
+    from: Position,
+    to: Position,
+    signal: usize,
+    tree: &[Vec<Position>],
+) -> Option<Vec<Position>> {
+    let heuristic = |pos: Position| {
+        let horizontal = (pos.x - to.x).abs() + (pos.z - to.z).abs();
+        horizontal.max((pos.y - to.y).abs()) as u32
+    };
+    let mut cost: HashMap<State, u32> = HashMap::new();
+    let mut parent: HashMap<State, State> = HashMap::new();
+    //从已有路径分叉的起点 -> (路径下标, 格子下标)
+    let mut branches: HashMap<State, (usize, usize)> = HashMap::new();
+    let mut open = BinaryHeap::new();
+    let mut push_start = |state: State, g: u32, open: &mut BinaryHeap<_>| {
+        if cost.get(&state).is_none_or(|c| g < *c) {
+            cost.insert(state, g);
+            open.push(Reverse((
+                g + heuristic(state.0),
+                g,
+                state.0.to_slice(),
+                state.1,
+            )));
+            true
+        } else {
+            false
+        }
+    };
+    push_start((from, 4), 0, &mut open);
+    for (p, path) in tree.iter().enumerate() {
+        for i in 1..path.len() - 1 {
+            let heading = DIRECTIONS
+                .iter()
+                .position(|d| d.x == path[i].x - path[i - 1].x && d.z == path[i].z - path[i - 1].z)
+                .unwrap();
+            if push_start((path[i], heading), i as u32, &mut open) {
+                branches.insert((path[i], heading), (p, i));
+            }
+        }
+    }
+    while let Some(Reverse((_, g, [x, y, z], heading))) = open.pop() {
+        let pos = Position { x, y, z };
+        if cost.get(&(pos, heading)).is_some_and(|c| *c < g) {
+            continue;
+        }
+        if pos == to {
+            //沿parent回到起点或者分叉点
+            let mut state = (pos, heading);
+            let mut path = vec![pos];
+            while let Some(prev) = parent.get(&state) {
+                state = *prev;
+                path.push(state.0);
+            }
+            path.reverse();
+            if let Some((p, i)) = branches.get(&state) {
+                let mut prefix = tree[*p][..*i].to_vec();
+                prefix.extend(path);
+                path = prefix;
+            }
+            return Some(path);
+        }
+        for (d, direction) in DIRECTIONS.iter().enumerate() {
+            for dy in -1..=1 {
+                let next = pos + *direction + Position { x: 0, y: dy, z: 0 };
+                if next != to && !grid.is_free(next, signal) {
+                    continue;
+                }
+                if dy != 0 && !grid.can_climb(pos, next, signal) {
+                    continue;
+                }
+                let step = 1 + (dy != 0) as u32 + (heading != 4 && heading != d) as u32;
+                let state = (next, d);
+                if cost.get(&state).is_none_or(|c| g + step < *c) {
+                    cost.insert(state, g + step);
+                    parent.insert(state, (pos, heading));
+                    open.push(Reverse((
+                        g + step + heuristic(next),
+                        g + step,
+                        next.to_slice(),
+                        d,
+                    )));
+                }
+            }
+        }
+    }
+    None
+}
+
+///把逐格的路径转换成导线，水平方向改变或者从平地开始升降的地方是拐点
+fn path_to_wire(name: String, path: &[Position]) -> Wire {
+    let mut waypoints = vec![];
+    //当前段的水平方向、升降方向、是否已经有平的一步
+    let mut segment: Option<(Position, i32, bool)> = None;
+    for i in 1..path.len() {
+        let step = path[i]
+            + Position {
+                x: -path[i - 1].x,
+                y: -path[i - 1].y,
+                z: -path[i - 1].z,
+            };
+        let direction = Position { y: 0, ..step };
+        let continues = match segment {
+            Some((d, climb, flat)) => d == direction && (step.y == 0 || (step.y == climb && !flat)),
+            None => true,
+        };
+        if !continues {
+            waypoints.push(path[i - 1]);
+            segment = None;
+        }
+        segment = Some(match segment {
+            Some((d, climb, flat)) => (d, climb, flat || step.y == 0),
+            None => (direction, step.y, step.y == 0),
+        });
+    }
+    Wire {
+        name,
+        start: path[0],
+        end: *path.last().unwrap(),
+        waypoints,
+        baseMaterial: ROUTE_BASE_MATERIAL.to_string(),
+        verticalStyle: VerticalStyle::Staircase,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/lib");
+
+    #[test]
+    fn detours_around_a_component() {
+        //a和y之间的直线被and挡住
+        let mut circuit: Circuit = serde_json::from_str(
+            r#"{"name":"detour","size":{"x":15,"y":4,"z":14},
+            "imports":[{"modelName":"and","modelType":"component","path":"and.json"}],
+            "components":[{"name":"g","model":"and","position":{"x":6,"y":0,"z":4}}],
+            "nets":[{"from":"a","to":"y"}],
+            "wires":[],"blocks":[],
+            "inputs":[{"name":"a","position":{"x":0,"y":0,"z":5}}],
+            "outputs":[{"name":"y","position":{"x":14,"y":0,"z":5}}]}"#,
+        )
+        .unwrap();
+        let models = load_imports(&circuit.imports, Some(LIBRARY));
+        let report = route_nets(&mut circuit, &models);
+        assert_eq!(report.routed, 1);
+        assert!(report.failed.is_empty());
+        let wire = &circuit.wires[0];
+        assert_eq!(
+            (wire.start, wire.end),
+            (
+                Position { x: 0, y: 0, z: 5 },
+                Position { x: 14, y: 0, z: 5 }
+            )
+        );
+        assert!(wire.length() > 14);
+        for cell in wire.path() {
+            for pos in [cell, cell + UP] {
+                assert!(
+                    pos.x >= 0 && pos.x < 15 && pos.y >= 0 && pos.y < 4 && pos.z >= 0 && pos.z < 14
+                );
+                //元件的包围盒
+                assert!(
+                    !((6..9).contains(&pos.x)
+                        && (0..4).contains(&pos.y)
+                        && (4..8).contains(&pos.z)),
+                    "wire goes through the component at {}",
+                    pos
+                );
+            }
+        }
+    }
+}
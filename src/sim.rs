@@ -1,214 +1,170 @@
 use std::{
     cmp::max,
-    collections::HashMap,
-    fs::OpenOptions,
-    io::BufReader,
-    iter::Map,
-    ops::{Add, Index},
+    collections::{BTreeMap, HashMap},
 };
 
-use mc_schem::{Block, Schematic, block};
+use ansi_term::Color::{Green, Red};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::{
-    Circuit, CommandLineArgs, ComponentModelObject, ModelObject, Port, Position, Wire,
-    check::{GlobalDirection, Graph},
-    error_begin, sim,
+    BlockSimulator, Circuit, ComponentModelObject, Expr, ModelObject, Position, VcdOption, Wire,
+    error_begin, generate_vcd, library_path, load_imports, load_nbt_region, on_wire_cell,
+    parse_model_sim,
 };
 
-///记录一个方块是否从某个方向计算过红石能量
-struct CalcRecord {
-    north: bool,
-    south: bool,
-    west: bool,
-    east: bool,
-    up: bool,
-    down: bool,
+///建立元件nbt的方块级仿真，并在输入全低时运行到稳定
+fn component_block_simulator(model_object: &dyn ModelObject, library: Option<&str>) -> BlockSimulator {
+    let nbt = library_path(library, model_object.get_nbt_path().unwrap());
+    let region = load_nbt_region(&nbt);
+    let mut sim = BlockSimulator::new(&region);
+    for port in model_object.get_inputs() {
+        sim.add_input(&port.name, port.position);
+    }
+    for port in model_object.get_outputs() {
+        sim.add_output(&port.name, port.position);
+    }
+    //nbt里保存的方块状态不一定和输入全低时一致
+    if sim.run_until_stable(MAX_STABLE_TICKS).is_none() {
+        println!(
+            "warning: component {} does not settle with all inputs low",
+            model_object.get_name()
+        );
+    }
+    sim
 }
-impl CalcRecord {
-    pub fn new() -> Self {
+///## ComponentSimulator
+/// 元件的方块级仿真，nbt只读取一次。每组输入都从输入全低时的稳定状态开始仿真
+pub struct ComponentSimulator<'a> {
+    model_object: &'a dyn ModelObject,
+    settled: BlockSimulator,
+}
+impl<'a> ComponentSimulator<'a> {
+    pub fn new(model_object: &'a dyn ModelObject, library: Option<&str>) -> Self {
         Self {
-            north: false,
-            south: false,
-            west: false,
-            east: false,
-            up: false,
-            down: false,
+            model_object,
+            settled: component_block_simulator(model_object, library),
         }
     }
-    pub fn get_direct_result(&self, direction: GlobalDirection) -> bool {
-        match direction {
-            GlobalDirection::North => self.north,
-            GlobalDirection::South => self.south,
-            GlobalDirection::West => self.west,
-            GlobalDirection::East => self.east,
-            GlobalDirection::Up => self.up,
-            GlobalDirection::Down => self.down,
+    ///根据输入的红石能量生成输出红石能量表
+    pub fn simulate(&self, inputs: &HashMap<String, i32>) -> HashMap<String, i32> {
+        let mut sim = self.settled.clone();
+        for (name, power) in inputs.iter() {
+            sim.set_input(name, *power);
         }
-    }
-    pub fn set_direct_result(&mut self, direction: GlobalDirection, result: bool) {
-        match direction {
-            GlobalDirection::North => self.north = result,
-            GlobalDirection::South => self.south = result,
-            GlobalDirection::West => self.west = result,
-            GlobalDirection::East => self.east = result,
-            GlobalDirection::Up => self.up = result,
-            GlobalDirection::Down => self.down = result,
+        if sim.run_until_stable(MAX_STABLE_TICKS).is_none() {
+            println!(
+                "warning: component {} does not settle within {} ticks, reading the last powers",
+                self.model_object.get_name(),
+                MAX_STABLE_TICKS
+            );
         }
+        self.model_object
+            .get_outputs()
+            .iter()
+            .map(|p| (p.name.clone(), sim.output(&p.name)))
+            .collect()
     }
 }
 ///
-/// 对一个元件进行仿真，根据输入的红石能量生成输出红石能量表
+/// 对一个元件进行方块级仿真，根据输入的红石能量生成输出红石能量表
 pub fn simulate_component(
     model_object: &dyn ModelObject,
     inputs: &HashMap<String, i32>,
-    lib_path: &str,
+    library: Option<&str>,
 ) -> HashMap<String, i32> {
-    let nbt = lib_path.to_string().clone() + "/" + model_object.get_nbt_path().unwrap();
-    let (mut nbt_obj, raw_meta) =
-        Schematic::from_file(&nbt).unwrap_or_else(|x| panic!("failed to load nbt file {}", nbt));
-    //合并成一个region
-    nbt_obj.merge_regions(&Block::air());
-    //准备输出表
-    let mut outputs: HashMap<Port, i32> = HashMap::new();
-    for port in model_object.get_outputs() {
-        outputs.insert(port.clone(), 0);
-    }
-    //准备红石能量记录表
-    let mut power_records: HashMap<Position, i32> = HashMap::new();
-    let mut visited: HashMap<Position, CalcRecord> = HashMap::new();
-    //初始化输入表
-    for (p, power) in inputs.iter() {
-        power_records.insert(
-            model_object
-                .get_inputs()
+    ComponentSimulator::new(model_object, library).simulate(inputs)
+}
+///读取元件json
+pub fn load_component_model(model_path: &str) -> ComponentModelObject {
+    serde_json::from_str(&std::fs::read_to_string(model_path).unwrap_or_else(|e| {
+        error_begin();
+        panic!("failed to read component json file {}: {}", model_path, e);
+    }))
+    .unwrap_or_else(|e| {
+        error_begin();
+        panic!("failed to parse component json file {}:\n{}", model_path, e);
+    })
+}
+///方块级仿真的入口，model_path是元件json。
+/// 输入json里有"ticks"时按游戏刻仿真，否则仿真到稳定状态
+pub fn do_component_simulation(model_path: &str, inputs: &str, library: Option<&str>) -> String {
+    let model = load_component_model(model_path);
+    let value: Value =
+        serde_json::from_str(inputs).expect("failed reading input json: format incorrect");
+    if value.get("ticks").is_none() {
+        let input: SimulationPowerAssign =
+            serde_json::from_value(value).expect("failed reading input json: format incorrect");
+        let outputs = simulate_component(
+            &model,
+            &input
+                .assignments
                 .iter()
-                .find(|x| x.name == *p)
-                .unwrap()
-                .position
-                .clone(),
-            *power,
+                .map(|(name, power)| (name.clone(), assigned_power(name, power)))
+                .collect(),
+            library,
         );
+        let out = SimulationPowerAssign {
+            assignments: outputs
+                .into_iter()
+                .map(|(name, power)| (name, Value::from(power)))
+                .collect(),
+        };
+        return serde_json::to_string_pretty(&out)
+            .expect("fatal: SimulationPowerAssign failed to_string");
     }
-    //开始仿真，BSF
-    let mut to_visit: Vec<Position> = inputs
-        .keys()
-        .map(|x| {
-            model_object
-                .get_inputs()
-                .iter()
-                .find(|y| y.name == *x)
-                .unwrap()
-                .position
-        })
-        .collect();
-    while let Some(pos) = to_visit.pop() {
-        println!("visiting {}", pos);
-        for neigh in pos.neighbors() {
-            if let Some(blk) = nbt_obj.first_block_at(neigh.to_slice()) {
-                println!("visiting neighbor {}:{}", neigh, blk.id);
+    let mut input: TickSimulationInput = serde_json::from_value(value)
+        .expect("failed reading tick simulation input json: format incorrect");
+    if input.watch.is_empty() {
+        input.watch = model.outputs.iter().map(|p| p.name.clone()).collect();
+    }
+    let mut sim = component_block_simulator(&model, library);
+    let mut outputs: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+    for tick in 0..input.ticks {
+        for event in input.events.iter().filter(|e| e.tick == tick) {
+            for (name, power) in event.assignments.iter() {
+                sim.set_input(name, assigned_power(name, power));
             }
-            /* 计算红石能量有几点：
-            1. 能传播能量
-            2. 有方块
-            3. 有的点会需要重复计算取最高能量，为了防止BFS陷入死循环，需要记录已经计算过的点的哪些方向
-            被计算过了
-            */
-            let relative_direct = GlobalDirection::direct(pos, neigh);
-            if let Some(block) = nbt_obj.first_block_at(neigh.to_slice())
-                && redstone_propagatable(block, relative_direct.clone())
-                && (!visited.contains_key(&neigh)
-                    || !visited[&neigh].get_direct_result(relative_direct))
-            {
-                //衰减后的能量
-                let decayed_power = max(power_records[&pos] - 1, 0);
-                //传播红石能量
-                if block.id.contains("repeater")
-                    || block.id.contains("torch")
-                    || block.id.contains("redstone_block")
-                {
-                    //中继器
-                    power_records.insert(neigh, 15);
-                } else {
-                    //计算能量，如果已有则取高
-                    power_records
-                        .entry(neigh)
-                        .and_modify(|p| *p = max(*p, decayed_power))
-                        .or_insert(decayed_power);
-                }
-                println!("calculatin power at {}:{}", neigh, power_records[&neigh]);
-                //修改对应方向为计算过了
-                visited
-                    .entry(neigh)
-                    .and_modify(|r| r.set_direct_result(relative_direct, true))
-                    .or_insert({
-                        let mut rec = CalcRecord::new();
-                        rec.set_direct_result(relative_direct, true);
-                        rec
-                    });
-                //能量衰减为零还是要继续传播的，这样才能计算出为零的输出口
-                to_visit.push(neigh);
+        }
+        sim.step();
+        for name in input.watch.iter() {
+            let power = sim.port_power(name);
+            let record = outputs.entry(name.clone()).or_default();
+            let before = record.last().copied().unwrap_or(0);
+            if power != before {
+                println!("tick {}: {} {} -> {}", tick, name, before, power);
             }
+            record.push(power);
         }
     }
-    //设置输出表
-    outputs.iter_mut().for_each(|(p, power)| {
-        if let Some(pow) = power_records.get(&p.position) {
-            *power = *pow;
-        } else {
-            println!("warning: output {} is not connected to any input", p.name);
-            *power = 0;
-        }
-    });
-    outputs
-        .iter()
-        .map(|(p, power)| (p.name.clone(), *power))
-        .collect()
-}
-///检查红石能量能否传播到这个方块
-/// power_source: 能源方向，从能源指向这个方块
-fn redstone_propagatable(block: &Block, power_source: GlobalDirection) -> bool {
-    block.id.contains("redstone_wire")
-        || block.id.contains("redstone_torch")
-        || block.id.contains("redstone_wall_torch")
-        || block.id.contains("redstone_lamp")
-        || block.id.contains("redstone_block")
-        || !(block.id.contains("glass"))
-        || (block.id.contains("repeater") && {
-            //检查输入是不是从中继器的输入口输入
-            let facing = GlobalDirection::from(block.attributes.get("facing").unwrap().as_str());
-            //e.g. 中继器朝北，输入是从南向北输入，则不能传播
-            facing == power_source
-        })
+    serde_json::to_string_pretty(&TickSimulationOutput {
+        ticks: input.ticks,
+        outputs,
+    })
+    .expect("fatal: TickSimulationOutput failed to_string")
 }
-
 /// 红石模拟结果
-struct TruthTable {
-    //列标题
-    header: Vec<String>,
-    //数据
+#[derive(Debug, Clone, Serialize)]
+pub struct TruthTable {
+    //输入列标题
+    inputs: Vec<String>,
+    //输出列标题
+    outputs: Vec<String>,
+    //数据，每行先是输入再是输出
     rows: Vec<Vec<i32>>,
 }
 impl TruthTable {
-    fn new(header: Vec<String>) -> Self {
+    pub fn new(inputs: Vec<String>, outputs: Vec<String>) -> Self {
         Self {
-            header,
+            inputs,
+            outputs,
             rows: Vec::new(),
         }
     }
-    ///设置一个情况(一行)
-    fn set(&mut self, inputs: Vec<i32>, outputs: Vec<i32>) {
-        let mut row = Vec::new();
-        inputs.iter().for_each(|x| row.push(*x));
-        outputs.iter().for_each(|x| row.push(*x));
-        //去掉已有的情况
-        for (i, ro) in &mut self.rows.iter().enumerate() {
-            if ro == &row {
-                self.rows.remove(i);
-                break;
-            }
-        }
+    ///追加一个情况(一行)。输入组合是按顺序枚举的，不会重复
+    pub fn push(&mut self, inputs: Vec<i32>, outputs: Vec<i32>) {
+        let mut row = inputs;
+        row.extend(outputs);
         self.rows.push(row);
     }
     fn get(&self, inputs: Vec<i32>) -> Option<Vec<i32>> {
@@ -219,26 +175,121 @@ impl TruthTable {
         }
         None
     }
-}
-///模拟一个图，生成一个真值表
-fn simulate_graph(graph: Graph) {
-    let mut inp = vec![0; graph.inputs.len()];
-    loop {
-        //二进制方式加一
-        todo!("一个输入情况的模拟，使用testch.lithematic_graph.json里面的图");
-        for i in 0..inp.len() {
-            inp[i] += 1;
-            if inp[i] == 2 {
-                inp[i] = 0;
-                if i < inp.len() - 1 {
-                    inp[i + 1] += 1;
+    ///列标题，先输入后输出
+    pub fn header(&self) -> Vec<String> {
+        self.inputs.iter().chain(self.outputs.iter()).cloned().collect()
+    }
+    pub fn to_csv(&self) -> String {
+        let mut csv = self.header().join(",") + "\n";
+        for row in &self.rows {
+            csv += &(row.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(",") + "\n");
+        }
+        csv
+    }
+    ///对齐的文本表格，输入和输出之间用||分开
+    pub fn to_pretty(&self) -> String {
+        let header = self.header();
+        let widths: Vec<usize> = header.iter().map(|h| max(h.len(), 2)).collect();
+        let line = |cells: Vec<String>| {
+            let mut text = String::new();
+            for (i, cell) in cells.iter().enumerate() {
+                if i == self.inputs.len() {
+                    text += " ||";
+                } else if i > 0 {
+                    text += " |";
                 }
+                text += &format!(" {:>width$}", cell, width = widths[i]);
             }
+            text + "\n"
+        };
+        let mut table = line(header.clone());
+        table += &widths
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                let sep = if i == 0 {
+                    ""
+                } else if i == self.inputs.len() {
+                    "++"
+                } else {
+                    "+"
+                };
+                sep.to_string() + &"-".repeat(w + 2)
+            })
+            .collect::<String>();
+        table += "\n";
+        for row in &self.rows {
+            table += &line(row.iter().map(|v| v.to_string()).collect());
         }
-        if inp.iter().all(|x| *x == 1) {
-            break;
+        table
+    }
+}
+///真值表每一行最多仿真的游戏刻，超过时认为电路不会稳定
+const MAX_STABLE_TICKS: u64 = 10000;
+///第index个输入组合中各输入的能量，第一个输入是最高位，高为15，低为0
+pub fn input_levels(index: u128, n: usize) -> Vec<i32> {
+    (0..n)
+        .map(|j| if index >> (n - 1 - j) & 1 == 1 { 15 } else { 0 })
+        .collect()
+}
+///依次枚举n个输入的所有高低电平组合
+pub fn input_combinations(n: usize) -> impl Iterator<Item = Vec<i32>> {
+    (0..1u128 << n).map(move |index| input_levels(index, n))
+}
+///枚举电路输入的所有高低电平组合，仿真得到真值表。
+///
+/// 组合数超过limit时，均匀抽取limit个组合(包括全低和全高)。
+pub fn generate_truth_table(
+    circuit: &Circuit,
+    models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
+    limit: u64,
+) -> TruthTable {
+    let n = circuit.inputs.len();
+    if n == 0 {
+        error_begin();
+        panic!("circuit {} has no inputs to enumerate", circuit.name);
+    }
+    if n > 63 {
+        error_begin();
+        panic!("circuit {} has {} inputs, at most 63 are supported", circuit.name, n);
+    }
+    let simulation = generate_simulation_info(circuit, models, library);
+    let outputs = default_watch(circuit, &simulation);
+    let mut table = TruthTable::new(
+        circuit.inputs.iter().map(|p| p.name.clone()).collect(),
+        outputs.clone(),
+    );
+    //第一个输入是最高位
+    let total: u128 = 1 << n;
+    let limit = max(limit, 1) as u128;
+    let indices: Vec<u128> = if total <= limit {
+        (0..total).collect()
+    } else {
+        println!("{} input combinations, sampling {} of them", total, limit);
+        if limit == 1 {
+            vec![0]
+        } else {
+            (0..limit).map(|i| i * (total - 1) / (limit - 1)).collect()
         }
+    };
+    for index in indices {
+        let values = input_levels(index, n);
+        let mut assignments = serde_json::Map::new();
+        for (port, value) in circuit.inputs.iter().zip(values.iter()) {
+            assignments.insert(port.name.clone(), Value::from(*value));
+        }
+        let mut sim = TickSimulator::new(simulation.clone());
+        if sim.run_until_stable(&assignments, MAX_STABLE_TICKS).is_none() {
+            println!(
+                "warning: inputs {:?} do not settle within {} ticks, recording the last powers",
+                values, MAX_STABLE_TICKS
+            );
+        }
+        let result = outputs.iter().map(|name| sim.power(name)).collect();
+        table.push(values, result);
     }
+    table
 }
 /*
 对于SimPoint用法的说明：
@@ -254,6 +305,47 @@ enum SimFuncs {
     AND,
     OR,
     NOT,
+    ///电路自身的输入输出端口，不做运算
+    PORT,
+    ///按元件模型中"sim"的表达式计算，每个输出一个表达式
+    EXPR,
+    ///RS-NOR锁存器，输入s、r，输出q和反相的q
+    RSLATCH,
+    ///中继器锁存，输入d和lock，lock为低时输出跟随d，为高时保持
+    DLATCH,
+    ///T触发器，输入的上升沿翻转输出，输出q和反相的q
+    TFLIPFLOP,
+    ///脉冲延长，输入变低后输出再保持vars[0]游戏刻
+    PULSEEXTENDER,
+    ///时钟，周期vars[0]游戏刻，前半周期为高。有输入时只在第一个输入为低时运行
+    CLOCK,
+    ///比较器的比较模式，第一个输入是后端，其余是侧面：后端不小于侧面时输出后端的能量
+    COMPARATOR,
+    ///比较器的减法模式，输出后端减去侧面的能量
+    SUBTRACTOR,
+}
+impl SimFuncs {
+    ///有状态的单元，输出和之前的输入有关
+    fn is_sequential(self) -> bool {
+        matches!(
+            self,
+            SimFuncs::RSLATCH
+                | SimFuncs::DLATCH
+                | SimFuncs::TFLIPFLOP
+                | SimFuncs::PULSEEXTENDER
+                | SimFuncs::CLOCK
+        )
+    }
+}
+///时序单元在仿真过程中保存的状态
+#[derive(Debug, Clone, Default)]
+struct UnitState {
+    ///锁存的输出是否为高
+    q: bool,
+    ///上一次计算时第一个输入是否为高，用于检测上升沿
+    last_input: bool,
+    ///第一个输入最近一次为高的游戏刻
+    last_high: Option<u64>,
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 ///仿真节点，导线的两端，元件的端口均使用此结构。
@@ -263,6 +355,14 @@ struct CalculationUnit {
     //信号通过该节点时所做的运算
     func: SimFuncs,
     vars: Vec<u64>,
+    ///输入变化到输出变化的延迟(游戏刻)
+    delay: u64,
+    ///EXPR单元的输入端口名，顺序和输入能量点相同
+    input_names: Vec<String>,
+    ///EXPR单元每个输出的表达式，顺序和输出能量点相同
+    exprs: Vec<Expr>,
+    ///WIRE单元中间被读取的格：(衰减, 是否在中继器之后)，顺序和end之后的输出能量点相同
+    taps: Vec<(u64, bool)>,
 }
 impl CalculationUnit {
     pub fn new(name: &str, position: Position, func: SimFuncs) -> Self {
@@ -271,17 +371,64 @@ impl CalculationUnit {
             position,
             func,
             vars: Vec::new(),
+            delay: 0,
+            input_names: Vec::new(),
+            exprs: Vec::new(),
+            taps: Vec::new(),
         }
     }
     pub fn set_vars(&mut self, vars: Vec<u64>) {
         self.vars = vars;
     }
+    ///设置延迟，单位是红石刻(1红石刻=2游戏刻)
+    pub fn set_delay(&mut self, redstone_ticks: i32) {
+        self.delay = max(redstone_ticks, 0) as u64 * 2;
+    }
+    ///元件没有指定周期时的默认值(红石刻)
+    pub fn default_period(func: SimFuncs) -> i32 {
+        match func {
+            //1秒
+            SimFuncs::CLOCK => 10,
+            _ => 4,
+        }
+    }
+    ///元件没有指定延迟时的默认值(红石刻)
+    pub fn default_delay(func: SimFuncs) -> i32 {
+        match func {
+            //输入端的火把和输出端的火把
+            SimFuncs::AND => 2,
+            SimFuncs::NOT => 1,
+            //交叉的火把、中继器和比较器
+            SimFuncs::RSLATCH
+            | SimFuncs::DLATCH
+            | SimFuncs::TFLIPFLOP
+            | SimFuncs::PULSEEXTENDER
+            | SimFuncs::COMPARATOR
+            | SimFuncs::SUBTRACTOR => 1,
+            _ => 0,
+        }
+    }
+    ///设置EXPR单元的表达式
+    pub fn set_exprs(&mut self, model: &dyn ModelObject, sim: &BTreeMap<String, String>) {
+        self.input_names = model.get_inputs().iter().map(|p| p.name.clone()).collect();
+        self.exprs = parse_model_sim(model, sim);
+    }
     pub fn get_func_from_model(model: &dyn ModelObject) -> SimFuncs {
-        //暂时先写成硬编码，之后要改成可拓展形式
+        if model.get_sim().is_some() {
+            return SimFuncs::EXPR;
+        }
+        //没有sim的元件按名字使用内置的行为
         match model.get_name() {
             "and" => SimFuncs::AND,
             "or" => SimFuncs::OR,
             "not" => SimFuncs::NOT,
+            "rs_latch" => SimFuncs::RSLATCH,
+            "d_latch" => SimFuncs::DLATCH,
+            "t_flip_flop" => SimFuncs::TFLIPFLOP,
+            "pulse_extender" => SimFuncs::PULSEEXTENDER,
+            "clock" => SimFuncs::CLOCK,
+            "comparator" => SimFuncs::COMPARATOR,
+            "comparator_subtract" => SimFuncs::SUBTRACTOR,
             _ => SimFuncs::COPY,
         }
     }
@@ -387,10 +534,46 @@ impl Simulation {
             powerpoints: Vec::new(),
         }
     }
+    ///每个能量点所在的网络，网络用其中一个能量点的序号表示
+    pub fn nets(&self) -> Vec<usize> {
+        //并查集合并直接相连的能量点
+        let mut parent: Vec<usize> = (0..self.powerpoints.len()).collect();
+        fn find(parent: &mut [usize], i: usize) -> usize {
+            let mut root = i;
+            while parent[root] != root {
+                root = parent[root];
+            }
+            parent[i] = root;
+            root
+        }
+        for c in self.connections.iter() {
+            let (a, b) = (find(&mut parent, c.from), find(&mut parent, c.to));
+            parent[a] = b;
+        }
+        (0..parent.len()).map(|i| find(&mut parent, i)).collect()
+    }
 }
 
 ///根据项目json文件，生成连接图。
-fn generate_simulation_info(project: &Circuit, models: &Vec<Box<dyn ModelObject>>) -> Simulation {
+///
+/// 模型是子电路的元件被展开：子电路的单元和能量点加上"元件名."前缀并入这场仿真，
+/// 子电路的输入输出端口就是元件的端口，例如adder1.ha0.and001.output。
+fn generate_simulation_info(
+    project: &Circuit,
+    models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
+) -> Simulation {
+    generate_simulation_info_in(project, models, library, &mut Vec::new(), true)
+}
+///生成连接图。flatten为true时展开子电路，chain记录了正在展开的子电路文件路径，用于检测循环导入；
+/// 为false时子电路和元件一样是一个单元
+fn generate_simulation_info_in(
+    project: &Circuit,
+    models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
+    chain: &mut Vec<String>,
+    flatten: bool,
+) -> Simulation {
     //TODO 先生成 Vec<Connection>
     let mut simpoints = Vec::<CalculationUnit>::new();
     let mut pps = Vec::<PhysicalPoint>::new();
@@ -399,20 +582,11 @@ fn generate_simulation_info(project: &Circuit, models: &Vec<Box<dyn ModelObject>
     //首先，给所有的导线、元件端口都赋予一个点
     for wire in project.wires.iter() {
         let mut wiresimp = CalculationUnit::new(&wire.name, wire.start.clone(), SimFuncs::WIRE);
-        //TODO 导线的功能计算是需要设置导线长度的，这里还需要这样的代码
-        wiresimp.set_vars(vec![calc_wire_effective_length(wire, &project)]);
-        //从头至尾添加PhysicalPoint
-        let pos_start = if wire.start.x == wire.end.x {
-            wire.start.z
-        } else {
-            wire.start.x
-        };
-        let pos_end = if wire.end.x == wire.end.x {
-            wire.end.z
-        } else {
-            wire.end.x
-        };
-
+        //有中继器时还要记录到第一个中继器的长度
+        let mut vars = vec![calc_wire_effective_length(wire, project)];
+        vars.extend(calc_wire_first_segment_length(wire, project));
+        wiresimp.set_vars(vars);
+        wiresimp.set_delay(wire.repeater_delay(&project.blocks));
         //首尾添加PowerPoint
         powerpoints.push(PowerPoint::new(
             (wiresimp.name.clone() + ".start").as_str(),
@@ -428,36 +602,22 @@ fn generate_simulation_info(project: &Circuit, models: &Vec<Box<dyn ModelObject>
             PowerPointType::OUTPUT,
         ));
         let end_pp_index = powerpoints.len() - 1;
-        for j in (pos_start)..(pos_end + 1) {
+        //从头至尾添加PhysicalPoint
+        let path = wire.path();
+        let last = path.len() - 1;
+        for (j, pos) in path.iter().enumerate() {
             pps.push(PhysicalPoint::new(
                 (wiresimp.name.clone() + j.to_string().as_str()).as_str(),
                 simpoints.len(),
-                wire.start.clone().add(Position {
-                    x: if wire.start.x == wire.end.x {
-                        wire.start.x
-                    } else {
-                        j
-                    },
-                    y: 0,
-                    z: if wire.start.x == wire.end.x {
-                        j
-                    } else {
-                        wire.start.z
-                    },
-                }),
+                *pos,
                 //首尾两点特殊类型
-                if j == pos_start || j == pos_end {
+                if j == 0 || j == last {
                     PointType::ENDING
                 } else {
                     PointType::PART_OF_WIRE
                 },
-                if j == pos_start {
-                    start_pp_index
-                } else if j == pos_end {
-                    end_pp_index
-                } else {
-                    usize::MAX
-                },
+                //中间的点接到导线的起点上：接在导线中间的信号和导线起点的信号相同
+                if j == last { end_pp_index } else { start_pp_index },
             ));
         }
 
@@ -471,12 +631,93 @@ fn generate_simulation_info(project: &Circuit, models: &Vec<Box<dyn ModelObject>
             panic!("model {} not found for component {}", comp.model, comp.name);
         }
         let realmodel = model_obj.unwrap().as_ref();
+        if let Some(sub_circuit) = realmodel.as_any().downcast_ref::<Circuit>()
+            && flatten
+        {
+            let import_item = project
+                .imports
+                .iter()
+                .find(|x| x.modelName == comp.model)
+                .unwrap_or_else(|| {
+                    error_begin();
+                    panic!(
+                        "import of sub-circuit {} not found for component {}",
+                        comp.model, comp.name
+                    )
+                });
+            let path = library_path(library, &import_item.path);
+            let path = std::fs::canonicalize(&path)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or(path);
+            if chain.contains(&path) {
+                error_begin();
+                panic!(
+                    "circular import: circuit {} imports itself through {}",
+                    sub_circuit.name,
+                    chain.join(" -> ") + " -> " + &path
+                );
+            }
+            let sub_models = load_imports(&sub_circuit.imports, library);
+            chain.push(path);
+            let sub = generate_simulation_info_in(sub_circuit, &sub_models, library, chain, true);
+            chain.pop();
+            let unit_offset = simpoints.len();
+            let pp_offset = powerpoints.len();
+            let prefix = comp.name.clone() + ".";
+            //子电路的端口能量点，在父电路中的位置就是元件端口的位置。
+            //sub由同一个电路生成，它的每个输入输出都有PORT能量点
+            for port in realmodel.get_inputs().iter().chain(realmodel.get_outputs().iter()) {
+                let index = sub
+                    .powerpoints
+                    .iter()
+                    .position(|pp| {
+                        pp.name == port.name && sub.units[pp.calcunit_index].func == SimFuncs::PORT
+                    })
+                    .expect("fatal: sub-circuit port has no power point");
+                pps.push(PhysicalPoint::new(
+                    &(prefix.clone() + &port.name),
+                    unit_offset + sub.powerpoints[index].calcunit_index,
+                    comp.port_position(port, realmodel),
+                    PointType::ENDING,
+                    pp_offset + index,
+                ));
+            }
+            simpoints.extend(sub.units.into_iter().map(|mut unit| {
+                unit.name = prefix.clone() + &unit.name;
+                unit
+            }));
+            powerpoints.extend(sub.powerpoints.into_iter().map(|mut pp| {
+                pp.name = prefix.clone() + &pp.name;
+                pp.calcunit_index += unit_offset;
+                pp
+            }));
+            cons.extend(
+                sub.connections
+                    .iter()
+                    .map(|c| Connection::new(c.from + pp_offset, c.to + pp_offset)),
+            );
+            continue;
+        }
         //
-        let comppoint = CalculationUnit::new(
+        let mut comppoint = CalculationUnit::new(
             &comp.name,
-            comp.position.clone(),
+            comp.origin(),
             CalculationUnit::get_func_from_model(realmodel),
         );
+        if let Some(sim) = realmodel.get_sim() {
+            comppoint.set_exprs(realmodel, sim);
+        }
+        comppoint.set_delay(
+            realmodel
+                .get_delay()
+                .unwrap_or(CalculationUnit::default_delay(comppoint.func)),
+        );
+        if comppoint.func.is_sequential() {
+            let period = realmodel
+                .get_period()
+                .unwrap_or(CalculationUnit::default_period(comppoint.func));
+            comppoint.set_vars(vec![max(period, 1) as u64 * 2]);
+        }
         simpoints.push(comppoint);
         //因为之后的判断线和元件连接是通过位置判断的，所以这里需要把元件的输入输出端口位置计算出来
         /*
@@ -486,7 +727,7 @@ fn generate_simulation_info(project: &Circuit, models: &Vec<Box<dyn ModelObject>
          * 然后根据Point连接关系，生成SimPoint的连接关系。
          */
         for port in realmodel.get_inputs().iter() {
-            let pos = comp.position + port.position;
+            let pos = comp.port_position(port, realmodel);
             //输入端口作为PowerPoint
             powerpoints.push(PowerPoint::new(
                 &(comp.name.clone() + "." + &port.name),
@@ -505,7 +746,7 @@ fn generate_simulation_info(project: &Circuit, models: &Vec<Box<dyn ModelObject>
         }
         //add to ports
         for port in realmodel.get_outputs().iter() {
-            let pos = comp.position + port.position;
+            let pos = comp.port_position(port, realmodel);
             //输出端口作为PowerPoint
             powerpoints.push(PowerPoint::new(
                 &(comp.name.clone() + "." + &port.name),
@@ -523,6 +764,33 @@ fn generate_simulation_info(project: &Circuit, models: &Vec<Box<dyn ModelObject>
             ));
         }
     }
+    //电路自身的输入输出端口也各算一个点，能量点直接用端口名
+    //电路输入向电路内部输出信号，所以是OUTPUT类型，电路输出反之
+    for (ports, powerpoint_type) in [
+        (&project.inputs, PowerPointType::OUTPUT),
+        (&project.outputs, PowerPointType::INPUT),
+    ] {
+        for port in ports.iter() {
+            simpoints.push(CalculationUnit::new(
+                &port.name,
+                port.position,
+                SimFuncs::PORT,
+            ));
+            powerpoints.push(PowerPoint::new(
+                &port.name,
+                simpoints.len() - 1,
+                0,
+                powerpoint_type,
+            ));
+            pps.push(PhysicalPoint::new(
+                &port.name,
+                simpoints.len() - 1,
+                port.position,
+                PointType::ENDING,
+                powerpoints.len() - 1,
+            ));
+        }
+    }
     print!("SimPoints generated:{}\n", simpoints.len());
     print!("PhysicalPoints generated:{}\n", pps.len());
     //现在已经具备了记录物理地址的点pps，接下来开始根据pps建立联系。
@@ -534,22 +802,62 @@ fn generate_simulation_info(project: &Circuit, models: &Vec<Box<dyn ModelObject>
     4. 所有B点处理完毕后，把A点从列表中移除
     5. 重复1-4，直到列表为空
      */
+    //从导线中间读取信号的点：(导线中间的点, 读取的能量点)
+    let mut taps: Vec<(PhysicalPoint, PowerPointIndex)> = Vec::new();
     while pps.len() > 0 {
         let a = pps.remove(0);
         //寻找位置相同的点,去除
-        let _ = pps.iter().filter(|&point| {
+        pps.retain(|point| {
             if point.position == a.position//位置相同
             //不能都是PART_OF_WIRE类型
                 && !(a.point_type == PointType::PART_OF_WIRE
                     && point.point_type == PointType::PART_OF_WIRE)
             {
-                //建立连接
-                cons.push(Connection::new(a.powerpoint, point.powerpoint));
-                return true;
+                //输入接在导线中间时读取衰减到这一格的能量，其他的点接到导线起点
+                let tap = match (a.point_type, point.point_type) {
+                    (PointType::PART_OF_WIRE, _) => Some((&a, point)),
+                    (_, PointType::PART_OF_WIRE) => Some((point, &a)),
+                    _ => None,
+                };
+                match tap {
+                    Some((wire_point, reader))
+                        if powerpoints[reader.powerpoint].powerpoint_type
+                            == PowerPointType::INPUT =>
+                    {
+                        taps.push((wire_point.clone(), reader.powerpoint));
+                    }
+                    //建立连接
+                    _ => cons.push(Connection::new(a.powerpoint, point.powerpoint)),
+                }
+                return false;
             }
-            false
+            true
         });
     }
+    //每个被读取的格给导线加一个输出能量点，名字是"导线名.格的序号"
+    for (wire_point, reader) in taps {
+        let unit = wire_point.simpoint;
+        let wire = project
+            .wires
+            .iter()
+            .find(|w| w.name == simpoints[unit].name)
+            .unwrap();
+        let cell = wire
+            .path()
+            .iter()
+            .position(|p| *p == wire_point.position)
+            .unwrap();
+        let name = format!("{}.{}", wire.name, cell);
+        let tap_pp = match powerpoints.iter().position(|pp| pp.name == name) {
+            Some(index) => index,
+            None => {
+                simpoints[unit].taps.push(calc_wire_tap(wire, project, cell));
+                powerpoints.push(PowerPoint::new(&name, unit, 0, PowerPointType::OUTPUT));
+                powerpoints.len() - 1
+            }
+        };
+        cons.push(Connection::new(tap_pp, reader));
+    }
 
     print!("Connections generated:{}\n", cons.len());
     print!("Powerpoints generated:{}\n", powerpoints.len());
@@ -566,6 +874,212 @@ fn generate_simulation_info(project: &Circuit, models: &Vec<Box<dyn ModelObject>
 struct SimulationPowerAssign {
     assignments: serde_json::Map<String, Value>,
 }
+///容器的格数
+fn container_slots(container: &str) -> Option<u64> {
+    match container.trim_start_matches("minecraft:") {
+        "chest" | "trapped_chest" | "barrel" | "shulker_box" => Some(27),
+        "double_chest" => Some(54),
+        "hopper" | "brewing_stand" => Some(5),
+        "dispenser" | "dropper" | "crafter" => Some(9),
+        "furnace" | "blast_furnace" | "smoker" => Some(3),
+        id if id.ends_with("_shulker_box") => Some(27),
+        _ => None,
+    }
+}
+///输入赋值的能量：数字，或者比较器读取的容器，例如{"container":"chest","items":100,"stack":64}。
+/// stack是物品的最大堆叠数，缺省为64
+fn assigned_power(name: &str, value: &Value) -> i32 {
+    let Some(container) = value.get("container") else {
+        return (value.as_i64().unwrap_or(0) as i32).clamp(0, 15);
+    };
+    let slots = container.as_str().and_then(container_slots).unwrap_or_else(|| {
+        error_begin();
+        panic!("input {} is assigned an unknown container {}", name, container);
+    });
+    let items = value.get("items").and_then(|v| v.as_u64()).unwrap_or(0);
+    let stack = value.get("stack").and_then(|v| v.as_u64()).unwrap_or(64).max(1);
+    if items == 0 {
+        return 0;
+    }
+    //1 + 装满的比例 * 14，向下取整
+    let signal = 1 + items * 14 / (stack * slots);
+    signal.min(15) as i32
+}
+
+///计算一个单元在给定输入能量下每个输出的能量，时序单元读写state，tick是当前的游戏刻
+fn evaluate(
+    unit: &CalculationUnit,
+    state: &mut UnitState,
+    inputs: &[i32],
+    outputs: usize,
+    tick: u64,
+) -> Vec<i32> {
+    if unit.func.is_sequential() {
+        return evaluate_sequential(unit, state, inputs, outputs, tick);
+    }
+    if unit.func == SimFuncs::EXPR {
+        let lookup = |name: &str| {
+            unit.input_names
+                .iter()
+                .position(|n| n == name)
+                .and_then(|i| inputs.get(i).copied())
+                .unwrap_or(0)
+        };
+        return unit
+            .exprs
+            .iter()
+            .map(|e| e.eval(&lookup).clamp(0, 15))
+            .collect();
+    }
+    let mut results = vec![evaluate_builtin(unit, inputs); outputs];
+    //导线中间被读取的格
+    if unit.func == SimFuncs::WIRE {
+        let input = inputs.first().copied().unwrap_or(0);
+        for (result, (decay, behind_repeater)) in results.iter_mut().skip(1).zip(unit.taps.iter()) {
+            *result = wire_power(unit, input, *decay, *behind_repeater);
+        }
+    }
+    results
+}
+///导线上衰减了decay格的能量。在中继器之后时，信号到达第一个中继器后从15重新衰减
+fn wire_power(unit: &CalculationUnit, input: i32, decay: u64, behind_repeater: bool) -> i32 {
+    if !behind_repeater {
+        return max(input - decay as i32, 0);
+    }
+    //中继器由它前一格的红石线触发，输出到后一格的能量是15
+    let first_segment = unit.vars.get(1).copied().unwrap_or(0) as i32;
+    if input > 0 && input >= first_segment {
+        max(16 - max(decay as i32, 1), 0)
+    } else {
+        0
+    }
+}
+///按元件的sim表达式或内置行为计算它的输出，inputs的顺序和模型的输入端口相同
+pub fn evaluate_component_logic(model: &dyn ModelObject, inputs: &[i32]) -> Vec<i32> {
+    let func = CalculationUnit::get_func_from_model(model);
+    let mut unit = CalculationUnit::new(model.get_name(), Position { x: 0, y: 0, z: 0 }, func);
+    if let Some(sim) = model.get_sim() {
+        unit.set_exprs(model, sim);
+    }
+    evaluate(&unit, &mut UnitState::default(), inputs, model.get_outputs().len(), 0)
+}
+///时序元件的周期(红石刻)，不是时序元件时返回None
+pub fn sequential_period(model: &dyn ModelObject) -> Option<i32> {
+    let func = CalculationUnit::get_func_from_model(model);
+    func.is_sequential()
+        .then(|| model.get_period().unwrap_or(CalculationUnit::default_period(func)))
+}
+///时序单元的计算，第一个输出是q，第二个输出(如果有)是反相的q
+fn evaluate_sequential(
+    unit: &CalculationUnit,
+    state: &mut UnitState,
+    inputs: &[i32],
+    outputs: usize,
+    tick: u64,
+) -> Vec<i32> {
+    let high = |i: usize| inputs.get(i).is_some_and(|p| *p > 0);
+    let period = unit.vars.first().copied().unwrap_or(2);
+    if high(0) {
+        state.last_high = Some(tick);
+    }
+    let mut both_low = false;
+    match unit.func {
+        SimFuncs::RSLATCH => {
+            if high(0) && high(1) {
+                //置位和复位同时为高时两个输出都是低
+                both_low = true;
+            } else if high(0) {
+                state.q = true;
+            } else if high(1) {
+                state.q = false;
+            }
+        }
+        SimFuncs::DLATCH => {
+            if !high(1) {
+                state.q = high(0);
+            }
+        }
+        SimFuncs::TFLIPFLOP => {
+            if high(0) && !state.last_input {
+                state.q = !state.q;
+            }
+        }
+        SimFuncs::PULSEEXTENDER => {
+            state.q = state.last_high.is_some_and(|t| tick < t + period);
+        }
+        SimFuncs::CLOCK => {
+            state.q = !high(0) && tick % period < period.div_ceil(2);
+        }
+        _ => unreachable!("{:?} is not sequential", unit.func),
+    }
+    state.last_input = high(0);
+    let power = |on: bool| if on && !both_low { 15 } else { 0 };
+    (0..outputs)
+        .map(|i| if i == 0 { power(state.q) } else { power(!state.q) })
+        .collect()
+}
+///输入不变时时序单元的输出是否还会变化：运行中的时钟，或者输入变低后还在保持的脉冲延长器
+fn changes_with_tick(unit: &CalculationUnit, state: &UnitState) -> bool {
+    match unit.func {
+        SimFuncs::CLOCK => !state.last_input && unit.vars.first().copied().unwrap_or(2) > 1,
+        SimFuncs::PULSEEXTENDER => state.q && !state.last_input,
+        _ => false,
+    }
+}
+///内置行为的计算，所有输出能量相同
+fn evaluate_builtin(unit: &CalculationUnit, inputs: &[i32]) -> i32 {
+    let input = inputs.first().copied().unwrap_or(0);
+    match unit.func {
+        //直接复制输入到输出
+        SimFuncs::COPY => input,
+        SimFuncs::WIRE => {
+            //导线行为，衰减后输出。没有中继器时导线长度作为衰减值，
+            //有中继器时信号到达了第一个中继器，之后从最后一个中继器以15重新衰减
+            wire_power(unit, input, unit.vars[0], unit.vars.len() > 1)
+        }
+        //与门，所有输入均为高则输出高
+        SimFuncs::AND => {
+            if inputs.iter().all(|p| *p > 0) {
+                15
+            } else {
+                0
+            }
+        }
+        //或门，任一输入为高则输出高
+        SimFuncs::OR => {
+            if inputs.iter().any(|p| *p > 0) {
+                15
+            } else {
+                0
+            }
+        }
+        //非门，输入为高则输出低，输入为低则输出高
+        SimFuncs::NOT => {
+            if input > 0 {
+                0
+            } else {
+                15
+            }
+        }
+        //比较器，侧面取最大的输入
+        SimFuncs::COMPARATOR | SimFuncs::SUBTRACTOR => {
+            let side = inputs.iter().skip(1).copied().max().unwrap_or(0);
+            if unit.func == SimFuncs::SUBTRACTOR {
+                max(input - side, 0)
+            } else if input >= side {
+                input
+            } else {
+                0
+            }
+        }
+        SimFuncs::PORT | SimFuncs::EXPR => input,
+        SimFuncs::RSLATCH
+        | SimFuncs::DLATCH
+        | SimFuncs::TFLIPFLOP
+        | SimFuncs::PULSEEXTENDER
+        | SimFuncs::CLOCK => unreachable!("{:?} is evaluated by evaluate_sequential", unit.func),
+    }
+}
 
 fn simulate(simulation: &mut Simulation, inputs: SimulationPowerAssign) -> SimulationPowerAssign {
     //根据连接图和输入，进行仿真
@@ -595,7 +1109,7 @@ fn simulate(simulation: &mut Simulation, inputs: SimulationPowerAssign) -> Simul
             );
         }
         let pp = pp_opt.unwrap();
-        pp.power = power.as_i64().unwrap_or(0) as i32;
+        pp.power = assigned_power(path, power);
     }
     //初值设定完毕，开始传播计算
     let conmap = generate_connention_map(&simulation.connections, &simulation.powerpoints);
@@ -612,6 +1126,8 @@ fn simulate(simulation: &mut Simulation, inputs: SimulationPowerAssign) -> Simul
     let powerpoints = &mut simulation.powerpoints;
     let connections = &simulation.connections;
     let units = &simulation.units;
+    //时序单元的状态，不按游戏刻仿真时都在第0刻
+    let mut states = vec![UnitState::default(); units.len()];
     loop {
         for (unit_index, unit) in units.iter().enumerate() {
             println!("Calculating unit {}", unit.name);
@@ -647,100 +1163,27 @@ fn simulate(simulation: &mut Simulation, inputs: SimulationPowerAssign) -> Simul
                 (inp, outp)
             };
             //根据func进行计算
-            match unit.func {
-                SimFuncs::COPY => {
-                    //直接复制输入到输出
-                    output_pp
-                        .iter_mut()
-                        .for_each(|&mut pp| powerpoints[pp].power = powerpoints[input_pp[0]].power);
-                    println!(
-                        "  COPY: {} -> {}",
-                        powerpoints[input_pp[0]].power, powerpoints[output_pp[0]].power
-                    );
-                }
-                SimFuncs::WIRE => {
-                    //导线行为，衰减后输出
-                    let decay = unit.vars[0] as i32; //导线长度作为衰减值
-                    let input_pp_power = powerpoints[input_pp[0]].power;
-                    let newpower = max(input_pp_power - decay, 0);
-                    output_pp.iter_mut().for_each(|&mut pp| {
-                        let pp = &mut powerpoints[pp];
-                        if pp.power < newpower {
-                            pp.power = newpower;
-                            changed = true;
-                        }
-                    });
-                    println!("  WIRE: {} - {} -> {}", input_pp_power, decay, newpower);
-                }
-                SimFuncs::AND => {
-                    //与门，所有输入均为高则输出高
-                    let result = if input_pp.iter().all(|pp| powerpoints[*pp].power > 0) {
-                        15
-                    } else {
-                        0
-                    };
-                    output_pp.iter_mut().for_each(|pp| {
-                        let pp = powerpoints.get_mut(*pp).unwrap();
-                        if pp.power != result {
-                            changed = true;
-                        }
-                        pp.power = result
-                    });
-                    println!(
-                        "  AND: inputs {:?} -> {}",
-                        input_pp
-                            .iter()
-                            .map(|pp| powerpoints[*pp].power)
-                            .collect::<Vec<i32>>(),
-                        powerpoints[output_pp[0]].power
-                    );
-                }
-                SimFuncs::OR => {
-                    //或门，任一输入为高则输出高
-                    let result = if input_pp.iter().any(|pp| powerpoints[*pp].power > 0) {
-                        15
-                    } else {
-                        0
-                    };
-                    output_pp.iter_mut().for_each(|pp| {
-                        let pp = powerpoints.get_mut(*pp).unwrap();
-                        if pp.power != result {
-                            changed = true;
-                        }
-                        pp.power = result
-                    });
-                    println!(
-                        "  OR: inputs {:?} -> {}",
-                        input_pp
-                            .iter()
-                            .map(|pp| powerpoints[*pp].power)
-                            .collect::<Vec<i32>>(),
-                        powerpoints[output_pp[0]].power
-                    );
-                }
-                SimFuncs::NOT => {
-                    //非门，输入为高则输出低，输入为低则输出高
-                    let result = if powerpoints[input_pp[0]].power > 0 {
-                        0
-                    } else {
-                        15
-                    };
-                    output_pp.iter_mut().for_each(|pp| {
-                        let pp = powerpoints.get_mut(*pp).unwrap();
-                        if pp.power != result {
-                            changed = true;
-                        }
-                        pp.power = result
-                    });
-                    println!(
-                        "  NOT: input {} -> {}",
-                        powerpoints[input_pp[0]].power, powerpoints[output_pp[0]].power
-                    );
-                }
-                _ => {
-                    //其他逻辑门暂未实现
-                    println!("  Function {:?} not implemented yet", unit.func);
-                }
+            let inputs = input_pp
+                .iter()
+                .map(|pp| powerpoints[*pp].power)
+                .collect::<Vec<i32>>();
+            //电路端口的能量来自赋值和连接，不做计算
+            if unit.func != SimFuncs::PORT {
+                let results = evaluate(
+                    unit,
+                    &mut states[unit_index],
+                    &inputs,
+                    output_pp.len(),
+                    0,
+                );
+                output_pp.iter_mut().zip(results.iter()).for_each(|(pp, &result)| {
+                    let pp = powerpoints.get_mut(*pp).unwrap();
+                    //导线可能被多个方向充能，只取更高的能量
+                    if pp.power != result && (unit.func != SimFuncs::WIRE || pp.power < result) {
+                        pp.power = result;
+                        changed = true;
+                    }
+                });
             }
             //将直接相连的PowerPoint也进行更新
             for pp in connected_pps.iter() {
@@ -751,6 +1194,7 @@ fn simulate(simulation: &mut Simulation, inputs: SimulationPowerAssign) -> Simul
                         let neighbor_pp = &mut powerpoints[neighbor_index];
                         if neighbor_pp.power < newpower {
                             neighbor_pp.power = newpower;
+                            changed = true;
                         }
                     }
                 }
@@ -780,25 +1224,613 @@ fn simulate_circuit(
     circuit: &Circuit,
     inputs: SimulationPowerAssign,
     models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
 ) -> SimulationPowerAssign {
-    let mut simulation = generate_simulation_info(circuit, models);
+    let mut simulation = generate_simulation_info(circuit, models, library);
     simulate(&mut simulation, inputs)
 }
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+///逐刻仿真输入
+struct TickSimulationInput {
+    ///仿真的游戏刻数
+    ticks: u64,
+    ///在指定游戏刻改变的输入
+    #[serde(default)]
+    events: Vec<TickEvent>,
+    ///需要记录的能量点，缺省时记录电路的输出
+    #[serde(default)]
+    watch: Vec<String>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+///在某一游戏刻开始时给能量点赋值
+struct TickEvent {
+    tick: u64,
+    assignments: serde_json::Map<String, Value>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+///逐刻仿真输出
+struct TickSimulationOutput {
+    ticks: u64,
+    ///每个记录的能量点在每一游戏刻结束时的能量
+    outputs: BTreeMap<String, Vec<i32>>,
+}
+
+///同一游戏刻内无延迟单元反复计算的上限，超过说明有无延迟的环路
+const MAX_SETTLE_ROUNDS: usize = 1000;
+
+/*
+逐刻仿真的思路：
+直接相连的能量点构成一个网络，网络的能量是网络里所有OUTPUT能量点和外部赋值中的最大值。
+每个单元读取输入能量点所在网络的能量，计算输出：
+    延迟为0的单元(导线、或门等)在同一游戏刻内立即更新；
+    有延迟的单元把输出的变化放进事件队列，到时刻再更新。
+每一游戏刻：先应用输入事件，再应用到期的事件，然后反复计算直到稳定，最后记录能量。
+ */
+///## TickSimulator
+/// 事件驱动的逐刻仿真器
+struct TickSimulator {
+    simulation: Simulation,
+    ///每个能量点所在的网络
+    net_of: Vec<usize>,
+    ///每个网络中的OUTPUT能量点
+    net_drivers: Vec<Vec<PowerPointIndex>>,
+    ///外部赋值，按网络记录
+    forced: HashMap<usize, i32>,
+    unit_inputs: Vec<Vec<PowerPointIndex>>,
+    unit_outputs: Vec<Vec<PowerPointIndex>>,
+    ///每个单元最近一次安排的输出，包括还没有到期的
+    pending: Vec<Vec<i32>>,
+    ///时序单元的状态
+    states: Vec<UnitState>,
+    ///事件队列: 游戏刻 -> (单元, 各输出的能量)
+    queue: BTreeMap<u64, Vec<(usize, Vec<i32>)>>,
+}
+impl TickSimulator {
+    pub fn new(simulation: Simulation) -> Self {
+        let net_of = simulation.nets();
+        let mut net_drivers = vec![Vec::new(); net_of.len()];
+        let mut unit_inputs = vec![Vec::new(); simulation.units.len()];
+        let mut unit_outputs = vec![Vec::new(); simulation.units.len()];
+        for (i, pp) in simulation.powerpoints.iter().enumerate() {
+            match pp.powerpoint_type {
+                PowerPointType::INPUT => unit_inputs[pp.calcunit_index].push(i),
+                PowerPointType::OUTPUT => {
+                    unit_outputs[pp.calcunit_index].push(i);
+                    net_drivers[net_of[i]].push(i);
+                }
+            }
+        }
+        Self {
+            pending: unit_outputs.iter().map(|o| vec![0; o.len()]).collect(),
+            states: vec![UnitState::default(); simulation.units.len()],
+            simulation,
+            net_of,
+            net_drivers,
+            forced: HashMap::new(),
+            unit_inputs,
+            unit_outputs,
+            queue: BTreeMap::new(),
+        }
+    }
+    fn powerpoint_index(&self, name: &str) -> PowerPointIndex {
+        self.simulation
+            .powerpoints
+            .iter()
+            .position(|pp| pp.name == name)
+            .unwrap_or_else(|| {
+                error_begin();
+                panic!("PowerPoint {} not found in simulation", name);
+            })
+    }
+    ///能量点所在网络的能量
+    fn power_of(&self, pp: PowerPointIndex) -> i32 {
+        let net = self.net_of[pp];
+        self.net_drivers[net]
+            .iter()
+            .map(|d| self.simulation.powerpoints[*d].power)
+            .chain(self.forced.get(&net).copied())
+            .max()
+            .unwrap_or(0)
+    }
+    fn set_output(&mut self, unit: usize, powers: &[i32]) -> bool {
+        let mut changed = false;
+        for (pp, power) in self.unit_outputs[unit].iter().zip(powers.iter()) {
+            let pp = &mut self.simulation.powerpoints[*pp];
+            if pp.power != *power {
+                pp.power = *power;
+                changed = true;
+            }
+        }
+        changed
+    }
+    ///在当前游戏刻内计算到稳定
+    fn settle(&mut self, tick: u64) {
+        for _ in 0..MAX_SETTLE_ROUNDS {
+            let mut changed = false;
+            for unit_index in 0..self.simulation.units.len() {
+                let unit = &self.simulation.units[unit_index];
+                if unit.func == SimFuncs::PORT {
+                    continue;
+                }
+                let inputs = self.unit_inputs[unit_index]
+                    .iter()
+                    .map(|pp| self.power_of(*pp))
+                    .collect::<Vec<i32>>();
+                let result = evaluate(
+                    unit,
+                    &mut self.states[unit_index],
+                    &inputs,
+                    self.unit_outputs[unit_index].len(),
+                    tick,
+                );
+                if unit.delay == 0 {
+                    changed |= self.set_output(unit_index, &result);
+                } else if result != self.pending[unit_index] {
+                    self.pending[unit_index] = result.clone();
+                    self.queue
+                        .entry(tick + unit.delay)
+                        .or_default()
+                        .push((unit_index, result));
+                }
+            }
+            if !changed {
+                return;
+            }
+        }
+        error_begin();
+        panic!(
+            "signal does not settle at tick {}, the circuit has a loop without delay",
+            tick
+        );
+    }
+    ///外部给能量点赋值
+    fn assign(&mut self, assignments: &serde_json::Map<String, Value>) {
+        for (name, power) in assignments.iter() {
+            let net = self.net_of[self.powerpoint_index(name)];
+            self.forced.insert(net, assigned_power(name, power));
+        }
+    }
+    ///应用到期的事件，然后计算到稳定
+    fn step(&mut self, tick: u64) {
+        for (unit, powers) in self.queue.remove(&tick).unwrap_or_default() {
+            self.set_output(unit, &powers);
+        }
+        self.settle(tick);
+    }
+    ///一个能量点当前的能量
+    pub fn power(&self, name: &str) -> i32 {
+        self.power_of(self.powerpoint_index(name))
+    }
+    ///赋值后一直运行到没有待处理的事件、也没有随游戏刻变化的时序单元，返回稳定时的游戏刻。
+    /// 超过max_ticks还没有稳定(例如有运行中的时钟)时返回None
+    pub fn run_until_stable(
+        &mut self,
+        assignments: &serde_json::Map<String, Value>,
+        max_ticks: u64,
+    ) -> Option<u64> {
+        self.assign(assignments);
+        (0..max_ticks).find(|tick| {
+            self.step(*tick);
+            self.queue.is_empty()
+                && !self
+                    .simulation
+                    .units
+                    .iter()
+                    .zip(self.states.iter())
+                    .any(|(unit, state)| changes_with_tick(unit, state))
+        })
+    }
+    ///运行仿真，返回每个记录的能量点在每一游戏刻的能量。
+    /// traced中的能量点也会被记录，但不出现在输出里，也不打印变化
+    pub fn run(
+        &mut self,
+        input: &TickSimulationInput,
+        traced: &[String],
+    ) -> (TickSimulationOutput, BTreeMap<String, Vec<i32>>) {
+        let recorded = input
+            .watch
+            .iter()
+            .chain(traced.iter())
+            .map(|name| (name.clone(), self.powerpoint_index(name)))
+            .collect::<Vec<(String, PowerPointIndex)>>();
+        let mut records: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+        for tick in 0..input.ticks {
+            for event in input.events.iter().filter(|e| e.tick == tick) {
+                self.assign(&event.assignments);
+            }
+            self.step(tick);
+            for (i, (name, pp)) in recorded.iter().enumerate() {
+                let power = self.power_of(*pp);
+                let record = records.entry(name.clone()).or_default();
+                if record.len() as u64 > tick {
+                    //watch和traced中重复的名字
+                    continue;
+                }
+                let before = record.last().copied().unwrap_or(0);
+                if power != before && i < input.watch.len() {
+                    println!("tick {}: {} {} -> {}", tick, name, before, power);
+                }
+                record.push(power);
+            }
+        }
+        let outputs = input
+            .watch
+            .iter()
+            .map(|name| (name.clone(), records[name].clone()))
+            .collect();
+        (
+            TickSimulationOutput {
+                ticks: input.ticks,
+                outputs,
+            },
+            records,
+        )
+    }
+}
+fn simulate_circuit_ticks(
+    circuit: &Circuit,
+    mut input: TickSimulationInput,
+    models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
+    vcd: Option<&VcdOption>,
+) -> TickSimulationOutput {
+    let simulation = generate_simulation_info(circuit, models, library);
+    if input.watch.is_empty() {
+        input.watch = default_watch(circuit, &simulation);
+    }
+    //波形里有电路的输入输出、watch，需要时还有所有内部能量点
+    let mut signals: Vec<String> = Vec::new();
+    if let Some(vcd) = vcd {
+        let internal = simulation
+            .powerpoints
+            .iter()
+            .filter(|_| vcd.internal)
+            .map(|pp| pp.name.clone());
+        for name in circuit
+            .inputs
+            .iter()
+            .chain(circuit.outputs.iter())
+            .map(|p| p.name.clone())
+            .chain(input.watch.iter().cloned())
+            .chain(internal)
+        {
+            if !signals.contains(&name) {
+                signals.push(name);
+            }
+        }
+    }
+    let (output, records) = TickSimulator::new(simulation).run(&input, &signals);
+    if let Some(vcd) = vcd {
+        let signals = signals
+            .into_iter()
+            .map(|name| {
+                let values = records[&name].clone();
+                (name, values)
+            })
+            .collect::<Vec<(String, Vec<i32>)>>();
+        std::fs::write(&vcd.path, generate_vcd(&circuit.name, &signals, input.ticks))
+            .unwrap_or_else(|e| {
+                error_begin();
+                panic!("failed to write vcd file {}: {}", vcd.path, e);
+            });
+        println!("generated vcd file {}", vcd.path);
+    }
+    output
+}
+///缺省记录电路的输出，电路没有输出时记录所有元件的输出
+fn default_watch(circuit: &Circuit, simulation: &Simulation) -> Vec<String> {
+    if circuit.outputs.is_empty() {
+        simulation
+            .powerpoints
+            .iter()
+            .filter(|pp| {
+                pp.powerpoint_type == PowerPointType::OUTPUT
+                    && simulation.units[pp.calcunit_index].func != SimFuncs::WIRE
+                    && simulation.units[pp.calcunit_index].func != SimFuncs::PORT
+            })
+            .map(|pp| pp.name.clone())
+            .collect()
+    } else {
+        circuit.outputs.iter().map(|p| p.name.clone()).collect()
+    }
+}
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+///高低电平
+enum Level {
+    High,
+    Low,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+///测试向量中一个能量点的值，可以是精确的能量，也可以是高低电平
+enum ExpectedPower {
+    Exact(i32),
+    Level(Level),
+}
+impl ExpectedPower {
+    fn matches(&self, power: i32) -> bool {
+        match self {
+            ExpectedPower::Exact(p) => *p == power,
+            ExpectedPower::Level(Level::High) => power > 0,
+            ExpectedPower::Level(Level::Low) => power == 0,
+        }
+    }
+    ///作为输入时的能量，高电平为15
+    fn power(&self) -> i32 {
+        match self {
+            ExpectedPower::Exact(p) => *p,
+            ExpectedPower::Level(Level::High) => 15,
+            ExpectedPower::Level(Level::Low) => 0,
+        }
+    }
+}
+impl std::fmt::Display for ExpectedPower {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpectedPower::Exact(p) => write!(f, "{}", p),
+            ExpectedPower::Level(Level::High) => write!(f, "high"),
+            ExpectedPower::Level(Level::Low) => write!(f, "low"),
+        }
+    }
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+///一个测试向量：输入和期望的输出
+struct TestVector {
+    #[serde(default)]
+    name: Option<String>,
+    inputs: BTreeMap<String, ExpectedPower>,
+    expect: BTreeMap<String, ExpectedPower>,
+}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+///## TestBench
+/// 测试文件，每个向量单独从全低的初始状态开始仿真到稳定
+struct TestBench {
+    vectors: Vec<TestVector>,
+}
+#[derive(Debug, Clone, Serialize)]
+///一个失败的向量
+struct TestFailure {
+    vector: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    inputs: BTreeMap<String, ExpectedPower>,
+    expected: BTreeMap<String, ExpectedPower>,
+    actual: BTreeMap<String, i32>,
+}
+#[derive(Debug, Clone, Serialize)]
+///测试结果
+pub struct TestBenchReport {
+    pub passed: usize,
+    pub failed: usize,
+    failures: Vec<TestFailure>,
+}
+///运行测试文件，打印失败向量的对比并返回测试结果。
+/// 每个向量都从没有充能的电路开始，锁存器等时序元件的状态不会带到下一个向量
+pub fn run_testbench(
+    circuit: &Circuit,
+    models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
+    testbench: &str,
+) -> TestBenchReport {
+    let testbench: TestBench = serde_json::from_str(testbench).unwrap_or_else(|e| {
+        error_begin();
+        panic!("failed reading testbench json: {}", e);
+    });
+    let simulation = generate_simulation_info(circuit, models, library);
+    let mut report = TestBenchReport {
+        passed: 0,
+        failed: 0,
+        failures: Vec::new(),
+    };
+    for (i, vector) in testbench.vectors.iter().enumerate() {
+        let assignments = vector
+            .inputs
+            .iter()
+            .map(|(name, power)| (name.clone(), Value::from(power.power())))
+            .collect::<serde_json::Map<String, Value>>();
+        let mut sim = TickSimulator::new(simulation.clone());
+        if sim.run_until_stable(&assignments, MAX_STABLE_TICKS).is_none() {
+            println!(
+                "warning: vector {} does not settle within {} ticks, checking the last powers",
+                i, MAX_STABLE_TICKS
+            );
+        }
+        let actual = vector
+            .expect
+            .keys()
+            .map(|name| (name.clone(), sim.power(name)))
+            .collect::<BTreeMap<String, i32>>();
+        if vector.expect.iter().all(|(name, e)| e.matches(actual[name])) {
+            report.passed += 1;
+            continue;
+        }
+        report.failed += 1;
+        //类似diff的输出，-为期望，+为实际
+        println!(
+            "FAIL vector {}{}: {}",
+            i,
+            vector.name.as_ref().map(|n| format!(" ({})", n)).unwrap_or_default(),
+            vector
+                .inputs
+                .iter()
+                .map(|(name, power)| format!("{}={}", name, power))
+                .collect::<Vec<String>>()
+                .join(" ")
+        );
+        for (name, expected) in vector.expect.iter() {
+            if expected.matches(actual[name]) {
+                println!("    {}: {}", name, actual[name]);
+            } else {
+                println!("{}", Red.paint(format!("  - {}: {}", name, expected)));
+                println!("{}", Green.paint(format!("  + {}: {}", name, actual[name])));
+            }
+        }
+        report.failures.push(TestFailure {
+            vector: i,
+            name: vector.name.clone(),
+            inputs: vector.inputs.clone(),
+            expected: vector.expect.clone(),
+            actual,
+        });
+    }
+    println!(
+        "{} of {} vectors passed",
+        report.passed,
+        report.passed + report.failed
+    );
+    report
+}
+///## NetlistInstance
+/// 网表中的一个元件或导线，端口是(端口名, 网络序号)
+pub struct NetlistInstance {
+    pub name: String,
+    ///元件的模型名，导线为None
+    pub model: Option<String>,
+    pub inputs: Vec<(String, usize)>,
+    pub outputs: Vec<(String, usize)>,
+}
+///## CircuitNetlist
+/// 一个电路的门级网表，子电路不展开，是它的模型的实例。
+/// 直接相连的能量点是同一个网络，一个网络可能有多个驱动(多根红石线汇合)
+pub struct CircuitNetlist {
+    ///每个网络的名字，有电路端口时是端口名，否则是驱动它的能量点名
+    pub nets: Vec<String>,
+    ///电路的输入输出端口和所在的网络
+    pub inputs: Vec<(String, usize)>,
+    pub outputs: Vec<(String, usize)>,
+    pub instances: Vec<NetlistInstance>,
+}
+///生成电路的网表
+pub fn circuit_netlist(
+    circuit: &Circuit,
+    models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
+) -> CircuitNetlist {
+    let simulation = generate_simulation_info_in(circuit, models, library, &mut Vec::new(), false);
+    let net_of = simulation.nets();
+    //网络按第一次出现的顺序编号
+    let mut roots: Vec<usize> = Vec::new();
+    for root in net_of.iter() {
+        if !roots.contains(root) {
+            roots.push(*root);
+        }
+    }
+    let net_index = |pp: usize| roots.iter().position(|r| *r == net_of[pp]).unwrap();
+    let nets = roots
+        .iter()
+        .map(|root| {
+            let members: Vec<&PowerPoint> = simulation
+                .powerpoints
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| net_of[*i] == *root)
+                .map(|(_, pp)| pp)
+                .collect();
+            members
+                .iter()
+                .find(|pp| simulation.units[pp.calcunit_index].func == SimFuncs::PORT)
+                .or_else(|| members.iter().find(|pp| pp.powerpoint_type == PowerPointType::OUTPUT))
+                .unwrap_or(&members[0])
+                .name
+                .clone()
+        })
+        .collect();
+    let mut netlist = CircuitNetlist {
+        nets,
+        inputs: Vec::new(),
+        outputs: Vec::new(),
+        instances: Vec::new(),
+    };
+    for (unit_index, unit) in simulation.units.iter().enumerate() {
+        let ports = |powerpoint_type: PowerPointType| {
+            simulation
+                .powerpoints
+                .iter()
+                .enumerate()
+                .filter(|(_, pp)| pp.calcunit_index == unit_index && pp.powerpoint_type == powerpoint_type)
+                .map(|(i, pp)| {
+                    let port = pp.name.strip_prefix(&(unit.name.clone() + ".")).unwrap_or(&pp.name);
+                    (port.to_string(), net_index(i))
+                })
+                .collect::<Vec<(String, usize)>>()
+        };
+        match unit.func {
+            //电路输入向内部输出信号，是OUTPUT类型
+            SimFuncs::PORT => {
+                netlist.inputs.extend(ports(PowerPointType::OUTPUT));
+                netlist.outputs.extend(ports(PowerPointType::INPUT));
+            }
+            _ => netlist.instances.push(NetlistInstance {
+                name: unit.name.clone(),
+                model: circuit
+                    .components
+                    .iter()
+                    .find(|c| c.name == unit.name && unit.func != SimFuncs::WIRE)
+                    .map(|c| c.model.clone()),
+                inputs: ports(PowerPointType::INPUT),
+                outputs: ports(PowerPointType::OUTPUT),
+            }),
+        }
+    }
+    netlist
+}
+///仿真入口。输入json里有"ticks"时按游戏刻仿真，否则仿真到稳定状态
 pub fn do_simulation(
     circuit: &Circuit,
     inputs: &str,
     models: &Vec<Box<dyn ModelObject>>,
+    library: Option<&str>,
+    vcd: Option<&VcdOption>,
 ) -> String {
-    let assignments =
+    let value: Value =
         serde_json::from_str(inputs).expect("failed reading input json: format incorrect");
-    let out = simulate_circuit(circuit, assignments, models);
+    if value.get("ticks").is_some() {
+        let input: TickSimulationInput = serde_json::from_value(value)
+            .expect("failed reading tick simulation input json: format incorrect");
+        let out = simulate_circuit_ticks(circuit, input, models, library, vcd);
+        return serde_json::to_string_pretty(&out)
+            .expect("fatal: TickSimulationOutput failed to_string");
+    }
+    if vcd.is_some() {
+        error_begin();
+        panic!("a vcd waveform needs a tick simulation, add \"ticks\" to the simulate input");
+    }
+    let assignments =
+        serde_json::from_value(value).expect("failed reading input json: format incorrect");
+    let out = simulate_circuit(circuit, assignments, models, library);
     serde_json::to_string_pretty(&out).expect("fatal: SimulationPowerAssign failed to_string")
 }
 ///计算导线的有效长度，考虑中继器
 fn calc_wire_effective_length(wire: &Wire, project: &Circuit) -> u64 {
-    //TODO 计算导线的有效长度
-    //目前先返回长度，不考虑中继器
-    wire.end.distance(wire.start)
+    wire.effective_length(&project.blocks) as u64
+}
+///计算信号从start到第一个中继器经过的格数，没有中继器时为None
+fn calc_wire_first_segment_length(wire: &Wire, project: &Circuit) -> Option<u64> {
+    wire.path()
+        .iter()
+        .position(|pos| {
+            project
+                .blocks
+                .iter()
+                .any(|b| b.id.contains("repeater") && on_wire_cell(b.position, *pos))
+        })
+        .map(|i| i as u64)
+}
+
+///导线第cell格的衰减，以及这一格是否在中继器之后
+fn calc_wire_tap(wire: &Wire, project: &Circuit, cell: usize) -> (u64, bool) {
+    let last_repeater = wire.path()[..=cell].iter().rposition(|pos| {
+        project
+            .blocks
+            .iter()
+            .any(|b| b.id.contains("repeater") && on_wire_cell(b.position, *pos))
+    });
+    match last_repeater {
+        Some(i) => ((cell - i) as u64, true),
+        None => (cell as u64, false),
+    }
 }
 
 ///根据连接集合生成连接图
@@ -827,3 +1859,338 @@ fn generate_connention_map(
     }
     conmap
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{gate, load_circuit, nested_library, single_gate, test_library};
+
+    ///逐刻仿真，返回每个输出在每一游戏刻的能量
+    fn run_ticks(circuit: &Circuit, library: &str, input: &str) -> BTreeMap<String, Vec<i32>> {
+        let models = load_imports(&circuit.imports, Some(library));
+        let input: TickSimulationInput = serde_json::from_str(input).unwrap();
+        simulate_circuit_ticks(circuit, input, &models, Some(library), None).outputs
+    }
+
+    ///从a到y的一条导线，导线中间有一个中继器
+    fn repeater_wire(delay: i32) -> Circuit {
+        serde_json::from_str(&format!(
+            r#"{{"name":"test","size":{{"x":5,"y":2,"z":1}},"imports":[],"components":[],
+            "wires":[{{"name":"w","start":{{"x":0,"y":0,"z":0}},"end":{{"x":4,"y":0,"z":0}},"baseMaterial":"stone"}}],
+            "blocks":[{{"position":{{"x":2,"y":1,"z":0}},"id":"repeater","properties":{{"facing":"west","delay":{}}}}}],
+            "inputs":[{{"name":"a","position":{{"x":0,"y":0,"z":0}}}}],
+            "outputs":[{{"name":"y","position":{{"x":4,"y":0,"z":0}}}}]}}"#,
+            delay
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn wire_delay_is_two_game_ticks_per_repeater_tick() {
+        let library = test_library("sim", "repeater_wire", &[]);
+        let input = r#"{"ticks":10,"events":[{"tick":1,"assignments":{"a":15}}]}"#;
+        let y = |delay: i32| run_ticks(&repeater_wire(delay), &library, input)["y"].clone();
+        //1红石刻的中继器延迟2游戏刻，红石线从中继器前一格开始衰减
+        assert_eq!(y(1), [0, 0, 0, 14, 14, 14, 14, 14, 14, 14]);
+        assert_eq!(y(3), [0, 0, 0, 0, 0, 0, 0, 14, 14, 14]);
+    }
+
+    #[test]
+    fn not_and_and_use_their_default_delays() {
+        let library = test_library(
+            "sim",
+            "default_delays",
+            &[gate("not", 1, 1), gate("and", 2, 1)],
+        );
+        let not = run_ticks(
+            &single_gate("not", &["a"], &["y"]),
+            &library,
+            r#"{"ticks":8,"events":[{"tick":3,"assignments":{"a":15}}]}"#,
+        );
+        //非门1红石刻，输入全低时输出在第2刻变高
+        assert_eq!(not["y"], [0, 0, 15, 15, 15, 0, 0, 0]);
+        let and = run_ticks(
+            &single_gate("and", &["a", "b"], &["y"]),
+            &library,
+            r#"{"ticks":8,"events":[{"tick":1,"assignments":{"a":15,"b":15}}]}"#,
+        );
+        //与门2红石刻
+        assert_eq!(and["y"], [0, 0, 0, 0, 0, 15, 15, 15]);
+    }
+
+    #[test]
+    fn run_records_every_tick_and_applies_events_at_their_tick() {
+        let library = test_library("sim", "events", &[gate("not", 1, 1)]);
+        let circuit = single_gate("not", &["a"], &["y"]);
+        let models = load_imports(&circuit.imports, Some(&library));
+        let input: TickSimulationInput = serde_json::from_str(
+            r#"{"ticks":8,"watch":["a","y"],"events":[
+                {"tick":2,"assignments":{"a":15}},
+                {"tick":5,"assignments":{"a":0}}]}"#,
+        )
+        .unwrap();
+        let simulation = generate_simulation_info(&circuit, &models, Some(&library));
+        let (output, records) =
+            TickSimulator::new(simulation).run(&input, &["g.input0".to_string()]);
+        assert_eq!(output.ticks, 8);
+        //赋值在所在游戏刻开始时生效
+        assert_eq!(output.outputs["a"], [0, 0, 15, 15, 15, 0, 0, 0]);
+        assert_eq!(output.outputs["y"], [0, 0, 15, 15, 0, 0, 0, 15]);
+        //traced的能量点只在记录里
+        assert!(!output.outputs.contains_key("g.input0"));
+        assert_eq!(records["g.input0"], output.outputs["a"]);
+    }
+
+    #[test]
+    fn testbench_counts_passing_and_failing_vectors() {
+        let library = test_library("sim", "testbench", &[gate("and", 2, 1)]);
+        let circuit = single_gate("and", &["a", "b"], &["out"]);
+        let models = load_imports(&circuit.imports, Some(&library));
+        let report = run_testbench(
+            &circuit,
+            &models,
+            Some(&library),
+            r#"{"vectors":[
+                {"name":"both high","inputs":{"a":15,"b":15},"expect":{"out":15}},
+                {"inputs":{"a":"high","b":"low"},"expect":{"out":"low"}},
+                {"name":"wrong","inputs":{"a":"high","b":"low"},"expect":{"out":"high"}}]}"#,
+        );
+        assert_eq!((report.passed, report.failed), (2, 1));
+        assert_eq!(report.failures[0].vector, 2);
+        assert_eq!(report.failures[0].name.as_deref(), Some("wrong"));
+        assert_eq!(report.failures[0].actual["out"], 0);
+    }
+
+    #[test]
+    fn testbench_vectors_do_not_share_state() {
+        let library = test_library("sim", "testbench_state", &[gate("rs_latch", 2, 2)]);
+        let circuit = single_gate("rs_latch", &["s", "r"], &["q", "nq"]);
+        let models = load_imports(&circuit.imports, Some(&library));
+        //第二个向量的锁存器没有被置位过
+        let report = run_testbench(
+            &circuit,
+            &models,
+            Some(&library),
+            r#"{"vectors":[
+                {"inputs":{"s":15},"expect":{"q":"high"}},
+                {"inputs":{},"expect":{"q":"low","nq":"high"}}]}"#,
+        );
+        assert_eq!((report.passed, report.failed), (2, 0));
+    }
+
+    #[test]
+    fn nested_sub_circuits_use_hierarchical_names() {
+        let library = nested_library("sim", "nested");
+        let top = load_circuit(&format!("{}/top.json", library));
+        let models = load_imports(&top.imports, Some(&library));
+        let simulation = generate_simulation_info(&top, &models, Some(&library));
+        for name in [
+            "outer.inner.and001.input0",
+            "outer.inner.and001.output",
+            "outer.inner.a",
+            "outer.y",
+        ] {
+            assert!(
+                simulation.powerpoints.iter().any(|pp| pp.name == name),
+                "{} missing",
+                name
+            );
+        }
+        let settle = |a: i32, b: i32| {
+            let mut sim = TickSimulator::new(simulation.clone());
+            let assignments = serde_json::json!({ "a": a, "b": b })
+                .as_object()
+                .unwrap()
+                .clone();
+            assert!(sim.run_until_stable(&assignments, 100).is_some());
+            (sim.power("outer.inner.and001.output"), sim.power("y"))
+        };
+        assert_eq!(settle(15, 15), (15, 15));
+        assert_eq!(settle(15, 0), (0, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "import of sub-circuit middle not found for component outer")]
+    fn sub_circuit_without_import_rejected() {
+        let library = nested_library("sim", "missing_import");
+        let mut top = load_circuit(&format!("{}/top.json", library));
+        let models = load_imports(&top.imports, Some(&library));
+        top.imports.clear();
+        generate_simulation_info(&top, &models, Some(&library));
+    }
+
+    ///依次给时序单元输入，每一游戏刻一组，返回每一刻的输出
+    fn sequential_trace(func: SimFuncs, period: u64, inputs: &[&[i32]]) -> Vec<Vec<i32>> {
+        let mut unit = CalculationUnit::new("u", Position { x: 0, y: 0, z: 0 }, func);
+        unit.set_vars(vec![period]);
+        let mut state = UnitState::default();
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(tick, inputs)| evaluate_sequential(&unit, &mut state, inputs, 2, tick as u64))
+            .collect()
+    }
+
+    #[test]
+    fn rs_latch_both_inputs_high() {
+        let trace = sequential_trace(
+            SimFuncs::RSLATCH,
+            0,
+            &[&[15, 0], &[0, 0], &[15, 15], &[0, 0], &[0, 15], &[15, 15]],
+        );
+        //同时为高时两个输出都是低，之后保持原来的状态
+        assert_eq!(trace, [[15, 0], [15, 0], [0, 0], [15, 0], [0, 15], [0, 0]]);
+    }
+
+    #[test]
+    fn t_flip_flop_toggles_on_rising_edges() {
+        let trace = sequential_trace(
+            SimFuncs::TFLIPFLOP,
+            0,
+            &[&[0], &[15], &[15], &[0], &[15], &[0]],
+        );
+        let q: Vec<i32> = trace.iter().map(|o| o[0]).collect();
+        assert_eq!(q, [0, 15, 15, 15, 0, 0]);
+        assert!(trace.iter().all(|o| o[0] + o[1] == 15));
+    }
+
+    #[test]
+    fn clock_duty_cycle() {
+        let q = |period: u64, off: &[i32]| -> Vec<i32> {
+            let inputs: Vec<&[i32]> = off.iter().map(std::slice::from_ref).collect();
+            sequential_trace(SimFuncs::CLOCK, period, &inputs)
+                .iter()
+                .map(|o| o[0])
+                .collect()
+        };
+        assert_eq!(q(4, &[0; 8]), [15, 15, 0, 0, 15, 15, 0, 0]);
+        //奇数周期时高电平多一刻
+        assert_eq!(q(5, &[0; 10]), [15, 15, 15, 0, 0, 15, 15, 15, 0, 0]);
+        assert_eq!(q(4, &[0, 15, 15, 0]), [15, 0, 0, 0]);
+    }
+
+    #[test]
+    fn pulse_extender_holds_after_input_falls() {
+        let trace = sequential_trace(
+            SimFuncs::PULSEEXTENDER,
+            4,
+            &[&[15], &[0], &[0], &[0], &[0], &[0]],
+        );
+        let q: Vec<i32> = trace.iter().map(|o| o[0]).collect();
+        assert_eq!(q, [15, 15, 15, 15, 0, 0]);
+    }
+
+    ///只有一个元件g的电路的逐刻仿真器
+    fn single_gate_simulator(library: &str, circuit: &Circuit) -> TickSimulator {
+        let models = load_imports(&circuit.imports, Some(library));
+        TickSimulator::new(generate_simulation_info(circuit, &models, Some(library)))
+    }
+
+    #[test]
+    fn clock_runs_until_disabled() {
+        let library = test_library("sim", "clock_run", &[gate("clock", 1, 1)]);
+        let circuit = single_gate("clock", &["off"], &["q"]);
+        let q = run_ticks(
+            &circuit,
+            &library,
+            r#"{"ticks":30,"events":[{"tick":25,"assignments":{"off":15}}]}"#,
+        );
+        //默认周期10红石刻，高低各10游戏刻，off为高时停止
+        let expected: Vec<i32> = (0..30)
+            .map(|t| if t < 25 && t % 20 < 10 { 15 } else { 0 })
+            .collect();
+        assert_eq!(q["q"], expected);
+        let mut running = single_gate_simulator(&library, &circuit);
+        assert_eq!(running.run_until_stable(&serde_json::Map::new(), 100), None);
+        let mut disabled = single_gate_simulator(&library, &circuit);
+        let off = serde_json::json!({"off":15}).as_object().unwrap().clone();
+        assert_eq!(disabled.run_until_stable(&off, 100), Some(0));
+    }
+
+    #[test]
+    fn rs_latch_holds_between_pulses() {
+        let library = test_library("sim", "rs_latch_run", &[gate("rs_latch", 2, 2)]);
+        let out = run_ticks(
+            &single_gate("rs_latch", &["s", "r"], &["q", "nq"]),
+            &library,
+            r#"{"ticks":12,"events":[
+                {"tick":1,"assignments":{"s":15}},{"tick":3,"assignments":{"s":0}},
+                {"tick":6,"assignments":{"r":15}},{"tick":8,"assignments":{"r":0}}]}"#,
+        );
+        //延迟1红石刻，置位和复位之间保持
+        assert_eq!(out["q"], [0, 0, 0, 15, 15, 15, 15, 15, 0, 0, 0, 0]);
+        assert_eq!(out["nq"], [0, 0, 15, 0, 0, 0, 0, 0, 15, 15, 15, 15]);
+    }
+
+    #[test]
+    fn pulse_extender_is_stable_after_it_falls() {
+        let library = test_library(
+            "sim",
+            "pulse_extender_stable",
+            &[gate("pulse_extender", 1, 1)],
+        );
+        let circuit = single_gate("pulse_extender", &["a"], &["y"]);
+        let mut sim = single_gate_simulator(&library, &circuit);
+        let a = |power: i32| {
+            serde_json::json!({ "a": power })
+                .as_object()
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(sim.run_until_stable(&a(15), 100), Some(2));
+        assert_eq!(sim.power("y"), 15);
+        //输入变低后保持4红石刻，再经过1红石刻的延迟变低
+        assert_eq!(sim.run_until_stable(&a(0), 100), Some(12));
+        assert_eq!(sim.power("y"), 0);
+    }
+
+    #[test]
+    fn comparator_delays_one_redstone_tick() {
+        let library = test_library(
+            "sim",
+            "comparator_delay",
+            &[gate("comparator", 2, 1), gate("comparator_subtract", 2, 1)],
+        );
+        let input = r#"{"ticks":10,"events":[
+            {"tick":0,"assignments":{"rear":12,"side":5}},
+            {"tick":4,"assignments":{"side":13}}]}"#;
+        let compare = run_ticks(
+            &single_gate("comparator", &["rear", "side"], &["out"]),
+            &library,
+            input,
+        );
+        assert_eq!(compare["out"], [0, 0, 12, 12, 12, 12, 0, 0, 0, 0]);
+        let subtract = run_ticks(
+            &single_gate("comparator_subtract", &["rear", "side"], &["out"]),
+            &library,
+            input,
+        );
+        assert_eq!(subtract["out"], [0, 0, 7, 7, 7, 7, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn assigned_power_of_numbers_and_containers() {
+        let power = |json: &str| assigned_power("a", &serde_json::from_str(json).unwrap());
+        assert_eq!(power("7"), 7);
+        assert_eq!(power("20"), 15);
+        assert_eq!(power("-3"), 0);
+        //1 + 14 * items / (stack * slots)，向下取整
+        assert_eq!(power(r#"{"container":"chest","items":0}"#), 0);
+        assert_eq!(power(r#"{"container":"chest","items":1}"#), 1);
+        assert_eq!(power(r#"{"container":"chest","items":864}"#), 8);
+        assert_eq!(power(r#"{"container":"minecraft:chest","items":1728}"#), 15);
+        assert_eq!(power(r#"{"container":"double_chest","items":1728}"#), 8);
+        assert_eq!(power(r#"{"container":"hopper","items":64}"#), 3);
+        assert_eq!(power(r#"{"container":"hopper","items":320}"#), 15);
+        assert_eq!(power(r#"{"container":"red_shulker_box","items":1728}"#), 15);
+        assert_eq!(power(r#"{"container":"furnace","items":16,"stack":16}"#), 5);
+        //超过容量时不超过15
+        assert_eq!(power(r#"{"container":"dropper","items":10000}"#), 15);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown container")]
+    fn unknown_container_rejected() {
+        assigned_power("a", &serde_json::json!({"container":"bucket","items":1}));
+    }
+}
@@ -0,0 +1,1263 @@
+use crate::{sim::{evaluate_component_logic, load_component_model}, *};
+
+/*
+从门级网表(BLIF或结构化Verilog)生成项目json。
+
+网表中的每个门按名字和端口数对应到library中的元件:
+    and g1(y, a, b)           -> 名为and、and2、and_2或and_2_1，有2个输入1个输出的元件
+    .subckt and input0=a ...  -> 名为and的元件，端口按名字连接
+    .names a b y / 11 1       -> 逻辑和真值表相同的元件，没有时把积之和拆成not、and和or
+多于两个输入的and/or/xor(以及nand/nor/xnor)在没有对应元件时拆成两输入门的链。
+buf和assign只是把两个信号连在一起。网表中定义而library中没有的模块会被展开。
+
+放置和布线都很简单:
+    每个门按它到输入的最长路径分层，信号沿-z方向从一层流向下一层，电路的输入在最上方(z最大)，输出在z=0
+    每个门和每个输入独占一列(x)，导线从驱动端沿-z走到两层之间的通道，在通道里沿x走到目标的列，再沿-z到达目标
+    同一层的导线在通道中各占一条轨道，但竖直走线和其他轨道交叉时会连在一起，需要手动调整
+    过长的导线自动放置中继器
+元件的输入端口要在+z一侧，输出端口在-z一侧，和library中的元件一致。
+*/
+
+///Verilog的门级原语，第一个端口是输出
+const PRIMITIVES: [&str; 8] = ["and", "nand", "or", "nor", "xor", "xnor", "not", "buf"];
+
+///## Pins
+/// 门的端口连接
+enum Pins {
+    ///按位置连接
+    Positional(Vec<String>),
+    ///端口名=信号
+    Named(Vec<(String, String)>),
+    ///BLIF的.names：输入信号、输出信号和真值表的行(输入组合, 输出)
+    Table {
+        inputs: Vec<String>,
+        output: String,
+        rows: Vec<(String, char)>,
+    },
+}
+
+///## NetlistGate
+/// 网表中的一个门或模块实例
+struct NetlistGate {
+    ///门的类型或模块名
+    kind: String,
+    ///实例名，BLIF没有实例名
+    name: Option<String>,
+    pins: Pins,
+}
+
+///## NetlistModule
+/// 网表中的一个模块
+#[derive(Default)]
+struct NetlistModule {
+    name: String,
+    ///端口按声明顺序，Verilog按位置连接时使用
+    ports: Vec<String>,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    gates: Vec<NetlistGate>,
+    ///连在一起的信号(目标, 源)，来自assign和缓冲器
+    aliases: Vec<(String, String)>,
+    ///不能展开的原因，例如BLIF的.blackbox和Verilog的行为描述。
+    /// 这样的模块只能是library中的元件
+    unsupported: Option<String>,
+}
+
+fn synth_error(path: &str, message: String) -> ! {
+    error_begin();
+    panic!("{}: {}", path, message);
+}
+
+///Verilog的词法分析，转义标识符去掉反斜杠，注释和`开头的编译指令跳过
+fn verilog_tokens(source: &str) -> Vec<String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') || c == '`' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '\\' {
+            let start = i + 1;
+            while i < chars.len() && !chars[i].is_whitespace() {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else if c.is_alphanumeric() || c == '_' || c == '$' || c == '\'' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || "_$'".contains(chars[i])) {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else {
+            tokens.push(c.to_string());
+            i += 1;
+        }
+    }
+    tokens
+}
+
+///## VerilogParser
+/// 只接受结构化的Verilog：端口和信号声明、门级原语、模块实例和assign a = b
+struct VerilogParser<'a> {
+    path: &'a str,
+    tokens: Vec<String>,
+    pos: usize,
+}
+impl VerilogParser<'_> {
+    fn peek(&self) -> &str {
+        self.tokens.get(self.pos).map(|s| s.as_str()).unwrap_or("")
+    }
+    fn next(&mut self) -> String {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            synth_error(self.path, "unexpected end of file".to_string());
+        };
+        self.pos += 1;
+        token
+    }
+    fn expect(&mut self, token: &str) {
+        let found = self.next();
+        if found != token {
+            synth_error(self.path, format!("expected {} but found {}", token, found));
+        }
+    }
+    ///[msb:lsb]，返回所有位的下标
+    fn range(&mut self) -> Option<Vec<i32>> {
+        if self.peek() != "[" {
+            return None;
+        }
+        self.next();
+        let number = |s: String, path: &str| {
+            s.parse::<i32>()
+                .unwrap_or_else(|_| synth_error(path, format!("bad bit index {}", s)))
+        };
+        let msb = number(self.next(), self.path);
+        self.expect(":");
+        let lsb = number(self.next(), self.path);
+        self.expect("]");
+        Some(if msb >= lsb {
+            (lsb..=msb).rev().collect()
+        } else {
+            (msb..=lsb).collect()
+        })
+    }
+    ///一位信号，a或a[3]
+    fn signal(&mut self) -> String {
+        let name = self.next();
+        if name.contains('\'') {
+            synth_error(self.path, format!("constant {} is not supported, drive it from an input", name));
+        }
+        if self.peek() == "[" {
+            self.next();
+            let index = self.next();
+            if self.peek() == ":" {
+                synth_error(self.path, format!("part select of {} is not supported, connect single bits", name));
+            }
+            self.expect("]");
+            return format!("{}[{}]", name, index);
+        }
+        name
+    }
+    ///声明的名字，有范围时展开成每一位
+    fn declared(name: &str, range: &Option<Vec<i32>>) -> Vec<String> {
+        match range {
+            Some(bits) => bits.iter().map(|i| format!("{}[{}]", name, i)).collect(),
+            None => vec![name.to_string()],
+        }
+    }
+    ///assign右边是否只是一个信号(不是常量或表达式)
+    fn plain_source(&self) -> bool {
+        let token = |k: usize| self.tokens.get(self.pos + k).map(|s| s.as_str()).unwrap_or("");
+        let first = token(0);
+        let plain = first.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$')
+            && !first.contains('\'');
+        let after = if token(1) == "[" { token(4) } else { token(1) };
+        plain && matches!(after, ";" | ",")
+    }
+    ///跳过模块剩下的部分，模块只能作为library中的元件使用
+    fn skip_module(&mut self, module: &mut NetlistModule, reason: String) {
+        while self.peek() != "endmodule" {
+            self.next();
+        }
+        module.unsupported.get_or_insert(reason);
+    }
+    fn module(&mut self) -> NetlistModule {
+        self.expect("module");
+        let mut module = NetlistModule {
+            name: self.next(),
+            ..Default::default()
+        };
+        if self.peek() == "#" {
+            synth_error(self.path, format!("parameters of module {} are not supported", module.name));
+        }
+        //端口名 -> 方向和所有位
+        let mut header: Vec<String> = Vec::new();
+        let mut directions: HashMap<String, (bool, Vec<String>)> = HashMap::new();
+        if self.peek() == "(" {
+            self.next();
+            let mut direction = None;
+            let mut range = None;
+            while self.peek() != ")" {
+                match self.next().as_str() {
+                    "input" => {
+                        direction = Some(true);
+                        range = self.range();
+                    }
+                    "output" => {
+                        direction = Some(false);
+                        range = self.range();
+                    }
+                    "wire" | "reg" => range = self.range().or(range),
+                    "inout" => synth_error(self.path, "inout ports are not supported".to_string()),
+                    "," => {}
+                    name => {
+                        header.push(name.to_string());
+                        if let Some(input) = direction {
+                            directions.insert(name.to_string(), (input, Self::declared(name, &range)));
+                        }
+                    }
+                }
+            }
+            self.next();
+        }
+        self.expect(";");
+        loop {
+            match self.next().as_str() {
+                "endmodule" => break,
+                keyword @ ("input" | "output" | "wire" | "reg") => {
+                    let direction = match keyword {
+                        "input" => Some(true),
+                        "output" => Some(false),
+                        _ => None,
+                    };
+                    if matches!(self.peek(), "wire" | "reg") {
+                        self.next();
+                    }
+                    let range = self.range();
+                    loop {
+                        let name = self.next();
+                        if let Some(input) = direction {
+                            directions.insert(name.clone(), (input, Self::declared(&name, &range)));
+                        }
+                        match self.next().as_str() {
+                            ";" => break,
+                            "," => {}
+                            _ => {
+                                self.skip_module(&mut module, format!("{} has an initial value", name));
+                                break;
+                            }
+                        }
+                    }
+                }
+                "inout" => synth_error(self.path, "inout ports are not supported".to_string()),
+                "assign" => loop {
+                    let target = self.signal();
+                    self.expect("=");
+                    if !self.plain_source() {
+                        self.skip_module(&mut module, format!("the assignment to {} is not a plain signal", target));
+                        break;
+                    }
+                    let source = self.signal();
+                    module.aliases.push((target, source));
+                    if self.next() == ";" {
+                        break;
+                    }
+                },
+                keyword @ ("always" | "initial" | "function" | "task") => {
+                    let reason = format!("it has an {} block", keyword);
+                    self.skip_module(&mut module, reason);
+                }
+                kind => {
+                    let kind = kind.to_string();
+                    //门的延迟 #1 或 #(1)，忽略
+                    if self.peek() == "#" {
+                        self.next();
+                        if self.next() == "(" {
+                            while self.next() != ")" {}
+                        }
+                    }
+                    loop {
+                        let name = if self.peek() != "(" {
+                            Some(self.next())
+                        } else {
+                            None
+                        };
+                        self.expect("(");
+                        let pins = if self.peek() == "." {
+                            let mut named = Vec::new();
+                            while self.peek() != ")" {
+                                if self.next() == "," {
+                                    continue;
+                                }
+                                let port = self.next();
+                                self.expect("(");
+                                if self.peek() != ")" {
+                                    named.push((port, self.signal()));
+                                }
+                                self.expect(")");
+                            }
+                            Pins::Named(named)
+                        } else {
+                            let mut positional = Vec::new();
+                            while self.peek() != ")" {
+                                if self.peek() == "," {
+                                    self.next();
+                                    continue;
+                                }
+                                positional.push(self.signal());
+                            }
+                            Pins::Positional(positional)
+                        };
+                        self.expect(")");
+                        module.gates.push(NetlistGate {
+                            kind: kind.clone(),
+                            name,
+                            pins,
+                        });
+                        if self.next() == ";" {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        for name in header.iter() {
+            let Some((input, bits)) = directions.get(name) else {
+                synth_error(self.path, format!("port {} of module {} has no direction", name, module.name));
+            };
+            module.ports.extend(bits.iter().cloned());
+            if *input {
+                module.inputs.extend(bits.iter().cloned());
+            } else {
+                module.outputs.extend(bits.iter().cloned());
+            }
+        }
+        module
+    }
+}
+
+///读取结构化Verilog的所有模块
+fn parse_verilog(path: &str, source: &str) -> Vec<NetlistModule> {
+    let mut parser = VerilogParser {
+        path,
+        tokens: verilog_tokens(source),
+        pos: 0,
+    };
+    let mut modules = Vec::new();
+    while parser.pos < parser.tokens.len() {
+        modules.push(parser.module());
+    }
+    modules
+}
+
+///读取BLIF的所有模型
+fn parse_blif(path: &str, source: &str) -> Vec<NetlistModule> {
+    //去掉注释，合并以\结尾的续行
+    let mut lines: Vec<String> = Vec::new();
+    let mut pending = String::new();
+    for line in source.lines() {
+        let line = line.split('#').next().unwrap();
+        if let Some(head) = line.trim_end().strip_suffix('\\') {
+            pending += head;
+            pending += " ";
+            continue;
+        }
+        pending += line;
+        if !pending.trim().is_empty() {
+            lines.push(pending.trim().to_string());
+        }
+        pending.clear();
+    }
+    let mut modules: Vec<NetlistModule> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let words: Vec<String> = lines[i].split_whitespace().map(|s| s.to_string()).collect();
+        i += 1;
+        if words[0] != ".model" && modules.is_empty() {
+            modules.push(NetlistModule::default());
+        }
+        match words[0].as_str() {
+            ".model" => modules.push(NetlistModule {
+                name: words.get(1).cloned().unwrap_or_default(),
+                ..Default::default()
+            }),
+            ".inputs" => {
+                let module = modules.last_mut().unwrap();
+                module.inputs.extend(words[1..].iter().cloned());
+                module.ports.extend(words[1..].iter().cloned());
+            }
+            ".outputs" => {
+                let module = modules.last_mut().unwrap();
+                module.outputs.extend(words[1..].iter().cloned());
+                module.ports.extend(words[1..].iter().cloned());
+            }
+            ".names" => {
+                let Some((output, inputs)) = words[1..].split_last() else {
+                    synth_error(path, ".names without signals".to_string());
+                };
+                let mut rows = Vec::new();
+                while i < lines.len() && !lines[i].starts_with('.') {
+                    let row: Vec<&str> = lines[i].split_whitespace().collect();
+                    let (cube, value) = match row.as_slice() {
+                        [value] if inputs.is_empty() => ("", *value),
+                        [cube, value] => (*cube, *value),
+                        _ => synth_error(path, format!("bad .names row {}", lines[i])),
+                    };
+                    rows.push((cube.to_string(), value.chars().next().unwrap()));
+                    i += 1;
+                }
+                let module = modules.last_mut().unwrap();
+                //.names a b / 1 1 是缓冲器
+                if inputs.len() == 1 && rows.len() == 1 && rows[0] == ("1".to_string(), '1') {
+                    module.aliases.push((output.clone(), inputs[0].clone()));
+                } else {
+                    module.gates.push(NetlistGate {
+                        kind: ".names".to_string(),
+                        name: None,
+                        pins: Pins::Table {
+                            inputs: inputs.to_vec(),
+                            output: output.clone(),
+                            rows,
+                        },
+                    });
+                }
+            }
+            ".gate" | ".subckt" => {
+                let Some(kind) = words.get(1) else {
+                    synth_error(path, format!("{} without a model name", words[0]));
+                };
+                let named = words[2..]
+                    .iter()
+                    .map(|pin| match pin.split_once('=') {
+                        Some((port, signal)) => (port.to_string(), signal.to_string()),
+                        None => synth_error(path, format!("bad pin {} of {} {}", pin, words[0], kind)),
+                    })
+                    .collect();
+                modules.last_mut().unwrap().gates.push(NetlistGate {
+                    kind: kind.clone(),
+                    name: None,
+                    pins: Pins::Named(named),
+                });
+            }
+            ".blackbox" => modules.last_mut().unwrap().unsupported = Some("it is a .blackbox".to_string()),
+            ".end" => {}
+            other => synth_error(path, format!("{} is not supported", other)),
+        }
+    }
+    modules
+}
+
+///## ComponentLibrary
+/// library目录下的所有元件
+struct ComponentLibrary {
+    components: Vec<ComponentModelObject>,
+    ///每个元件json相对于library目录的路径
+    paths: Vec<String>,
+}
+impl ComponentLibrary {
+    fn load(library: Option<&str>) -> Self {
+        let mut lib = ComponentLibrary {
+            components: Vec::new(),
+            paths: Vec::new(),
+        };
+        for path in library_component_files(library) {
+            lib.components.push(load_component_model(&path));
+            let file_name = std::path::Path::new(&path).file_name().unwrap();
+            lib.paths.push(file_name.to_string_lossy().to_string());
+        }
+        lib
+    }
+    ///按名字和端口数找元件，名字可以是kind、kind2、kind_2或kind_2_1
+    fn find(&self, kind: &str, inputs: usize, outputs: usize) -> Option<usize> {
+        let names = [
+            kind.to_string(),
+            format!("{}{}", kind, inputs),
+            format!("{}_{}", kind, inputs),
+            format!("{}_{}_{}", kind, inputs, outputs),
+        ];
+        names.iter().find_map(|name| {
+            self.components.iter().position(|c| {
+                &c.name == name && c.inputs.len() == inputs && c.outputs.len() == outputs
+            })
+        })
+    }
+    ///找逻辑和真值表相同的单输出元件，on_set的下标是输入组合，第一个输入是最高位
+    fn find_function(&self, on_set: &[bool]) -> Option<usize> {
+        let n = on_set.len().trailing_zeros() as usize;
+        self.components.iter().position(|c| {
+            c.inputs.len() == n
+                && c.outputs.len() == 1
+                && (0..on_set.len()).all(|index| {
+                    let values: Vec<i32> = (0..n)
+                        .map(|j| if index >> (n - 1 - j) & 1 == 1 { 15 } else { 0 })
+                        .collect();
+                    (evaluate_component_logic(c, &values)[0] > 0) == on_set[index]
+                })
+        })
+    }
+}
+
+///## Instance
+/// 展开后的一个元件，端口连接的信号可以为空
+struct Instance {
+    name: String,
+    component: usize,
+    inputs: Vec<Option<String>>,
+    outputs: Vec<Option<String>>,
+}
+
+///## Flattener
+/// 把顶层模块展开成元件实例，子模块的信号和实例名加上实例名前缀
+struct Flattener<'a> {
+    path: &'a str,
+    modules: &'a [NetlistModule],
+    library: &'a ComponentLibrary,
+    instances: Vec<Instance>,
+    ///信号的并查集
+    parent: HashMap<String, String>,
+    ///每种元件已经生成的个数，用于给没有实例名的门命名
+    counts: HashMap<usize, usize>,
+}
+impl Flattener<'_> {
+    fn find(&mut self, net: &str) -> String {
+        let mut root = net.to_string();
+        while let Some(parent) = self.parent.get(&root) {
+            root = parent.clone();
+        }
+        //路径压缩
+        let mut current = net.to_string();
+        while let Some(parent) = self.parent.get(&current).cloned() {
+            self.parent.insert(current, root.clone());
+            current = parent;
+        }
+        root
+    }
+    fn union(&mut self, a: &str, b: &str) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent.insert(a, b);
+        }
+    }
+    fn add_instance(&mut self, name: Option<String>, component: usize, inputs: Vec<Option<String>>, outputs: Vec<Option<String>>) {
+        let count = self.counts.entry(component).or_insert(0);
+        *count += 1;
+        let name = name.unwrap_or_else(|| format!("{}{:03}", self.library.components[component].name, count));
+        self.instances.push(Instance {
+            name,
+            component,
+            inputs,
+            outputs,
+        });
+    }
+    ///按位置连接的门级原语
+    fn primitive(&mut self, kind: &str, name: Option<String>, pins: &[String], prefix: &str) {
+        let net = |s: &String| format!("{}{}", prefix, s);
+        if pins.len() < 2 {
+            synth_error(self.path, format!("{} gate needs an output and at least one input", kind));
+        }
+        if matches!(kind, "buf" | "not") {
+            //多个输出，最后一个是输入
+            let (input, outputs) = pins.split_last().unwrap();
+            for (k, output) in outputs.iter().enumerate() {
+                if kind == "buf" {
+                    self.union(&net(output), &net(input));
+                    continue;
+                }
+                let Some(component) = self.library.find("not", 1, 1) else {
+                    synth_error(self.path, "no component named not with 1 input in the library".to_string());
+                };
+                let name = name.clone().map(|n| if k == 0 { n } else { format!("{}_{}", n, k) });
+                self.add_instance(name, component, vec![Some(net(input))], vec![Some(net(output))]);
+            }
+            return;
+        }
+        let output = net(&pins[0]);
+        let inputs: Vec<String> = pins[1..].iter().map(net).collect();
+        if inputs.len() == 1 && matches!(kind, "and" | "or" | "xor") {
+            self.union(&output, &inputs[0]);
+            return;
+        }
+        if let Some(component) = self.library.find(kind, inputs.len(), 1) {
+            self.add_instance(name, component, inputs.into_iter().map(Some).collect(), vec![Some(output)]);
+            return;
+        }
+        //拆成两输入门的链，只有最后一个门取反
+        let chain_kind = match kind {
+            "and" | "nand" => "and",
+            "or" | "nor" => "or",
+            _ => "xor",
+        };
+        let (Some(chain), Some(last)) = (self.library.find(chain_kind, 2, 1), self.library.find(kind, 2, 1)) else {
+            synth_error(
+                self.path,
+                format!("no component named {} with {} inputs in the library", kind, inputs.len()),
+            );
+        };
+        let base = name.clone().unwrap_or_else(|| output.clone());
+        let mut carry = inputs[0].clone();
+        for (k, input) in inputs[1..].iter().enumerate() {
+            let is_last = k + 2 == inputs.len();
+            let target = if is_last { output.clone() } else { format!("{}_chain{}", base, k) };
+            let name = name.clone().map(|n| if is_last { n } else { format!("{}_{}", n, k) });
+            self.add_instance(
+                name,
+                if is_last { last } else { chain },
+                vec![Some(carry.clone()), Some(input.clone())],
+                vec![Some(target.clone())],
+            );
+            carry = target;
+        }
+    }
+    ///BLIF的.names：有逻辑完全相同的元件时直接使用，否则把积之和拆成not、and和or
+    fn names(&mut self, inputs: &[String], output: &String, rows: &[(String, char)], prefix: &str) {
+        let net = |s: &String| format!("{}{}", prefix, s);
+        let n = inputs.len();
+        let signals = format!("{} {}", inputs.join(" "), output);
+        if n > MAX_DERIVE_INPUTS {
+            synth_error(
+                self.path,
+                format!(".names {} has {} inputs, at most {} are supported", signals, n, MAX_DERIVE_INPUTS),
+            );
+        }
+        if let Some((cube, _)) = rows.iter().find(|(cube, _)| cube.len() != n || cube.chars().any(|c| !"01-".contains(c))) {
+            synth_error(self.path, format!("bad row {} of .names {}", cube, signals));
+        }
+        //没有1输出行时.names描述的是0的集合
+        let on = rows.iter().any(|(_, v)| *v == '1') || rows.is_empty();
+        let mut on_set = vec![!on; 1usize << n];
+        for (cube, value) in rows.iter() {
+            for (index, entry) in on_set.iter_mut().enumerate() {
+                let matches = cube.chars().enumerate().all(|(j, c)| {
+                    let bit = index >> (n - 1 - j) & 1;
+                    c == '-' || (c == '1') == (bit == 1)
+                });
+                if matches {
+                    *entry = *value == '1';
+                }
+            }
+        }
+        if let Some(component) = self.library.find_function(&on_set) {
+            self.add_instance(
+                None,
+                component,
+                inputs.iter().map(|s| Some(net(s))).collect(),
+                vec![Some(net(output))],
+            );
+            return;
+        }
+        if on_set.iter().all(|v| *v == on_set[0]) {
+            synth_error(
+                self.path,
+                format!(".names {} is constant and no component in the library computes it", signals),
+            );
+        }
+        //每一行是输入的与(0取反)，各行再取或，描述0的集合时最后取反
+        let cubes: Vec<&String> = rows.iter().filter(|(_, v)| (*v == '1') == on).map(|(cube, _)| cube).collect();
+        let sum = if on { output.clone() } else { format!("{}_off", output) };
+        let mut terms = Vec::new();
+        let mut inverted_inputs = HashSet::new();
+        for (k, cube) in cubes.iter().enumerate() {
+            let mut literals = Vec::new();
+            for (input, c) in inputs.iter().zip(cube.chars()) {
+                match c {
+                    '1' => literals.push(input.clone()),
+                    '0' => {
+                        let inverted = format!("{}_not_{}", output, input);
+                        if inverted_inputs.insert(input.clone()) {
+                            self.primitive("not", None, &[inverted.clone(), input.clone()], prefix);
+                        }
+                        literals.push(inverted);
+                    }
+                    _ => {}
+                }
+            }
+            let term = if cubes.len() == 1 { sum.clone() } else { format!("{}_term{}", output, k) };
+            literals.insert(0, term.clone());
+            self.primitive("and", None, &literals, prefix);
+            terms.push(term);
+        }
+        if cubes.len() > 1 {
+            terms.insert(0, sum.clone());
+            self.primitive("or", None, &terms, prefix);
+        }
+        if !on {
+            self.primitive("not", None, &[output.clone(), sum], prefix);
+        }
+    }
+    ///展开一个模块，prefix是它的实例名前缀
+    fn flatten(&mut self, module: &NetlistModule, prefix: &str, chain: &mut Vec<String>) {
+        let net = |s: &String| format!("{}{}", prefix, s);
+        for (target, source) in module.aliases.iter() {
+            self.union(&net(target), &net(source));
+        }
+        for gate in module.gates.iter() {
+            let name = gate.name.as_ref().map(|n| format!("{}{}", prefix, n));
+            match &gate.pins {
+                Pins::Table { inputs, output, rows } => {
+                    self.names(inputs, output, rows, prefix);
+                }
+                Pins::Positional(pins) if PRIMITIVES.contains(&gate.kind.as_str()) => {
+                    self.primitive(&gate.kind, name, pins, prefix);
+                }
+                pins => {
+                    //library中的元件优先，否则展开网表中定义的模块
+                    let component = self.library.components.iter().position(|c| c.name == gate.kind);
+                    let sub_module = self.modules.iter().find(|m| m.name == gate.kind);
+                    match (component, sub_module) {
+                        (Some(component), _) => {
+                            let model = &self.library.components[component];
+                            let ports: Vec<&Port> = model.inputs.iter().chain(model.outputs.iter()).collect();
+                            let mut nets: Vec<Option<String>> = vec![None; ports.len()];
+                            match pins {
+                                Pins::Positional(pins) => {
+                                    if pins.len() != ports.len() {
+                                        synth_error(
+                                            self.path,
+                                            format!("component {} has {} ports but {} are connected", gate.kind, ports.len(), pins.len()),
+                                        );
+                                    }
+                                    nets = pins.iter().map(|s| Some(net(s))).collect();
+                                }
+                                Pins::Named(pins) => {
+                                    for (port, signal) in pins.iter() {
+                                        let Some(i) = ports.iter().position(|p| &p.name == port) else {
+                                            synth_error(self.path, format!("component {} has no port {}", gate.kind, port));
+                                        };
+                                        nets[i] = Some(net(signal));
+                                    }
+                                }
+                                Pins::Table { .. } => unreachable!(),
+                            }
+                            let outputs = nets.split_off(model.inputs.len());
+                            self.add_instance(name, component, nets, outputs);
+                        }
+                        (None, Some(sub_module)) => {
+                            if let Some(reason) = &sub_module.unsupported {
+                                synth_error(
+                                    self.path,
+                                    format!("module {} can't be expanded because {}, add a component named {} to the library", sub_module.name, reason, sub_module.name),
+                                );
+                            }
+                            if chain.contains(&sub_module.name) {
+                                synth_error(self.path, format!("module {} instantiates itself", sub_module.name));
+                            }
+                            let inner = format!("{}{}_", prefix, gate.name.clone().unwrap_or_else(|| format!("{}{}", gate.kind, self.instances.len())));
+                            let connections: Vec<(String, String)> = match pins {
+                                Pins::Positional(pins) => sub_module.ports.iter().cloned().zip(pins.iter().cloned()).collect(),
+                                Pins::Named(pins) => pins.clone(),
+                                Pins::Table { .. } => unreachable!(),
+                            };
+                            for (port, signal) in connections.iter() {
+                                if !sub_module.ports.contains(port) {
+                                    synth_error(self.path, format!("module {} has no port {}", sub_module.name, port));
+                                }
+                                self.union(&format!("{}{}", inner, port), &net(signal));
+                            }
+                            chain.push(sub_module.name.clone());
+                            self.flatten(sub_module, &inner, chain);
+                            chain.pop();
+                        }
+                        (None, None) => synth_error(
+                            self.path,
+                            format!("no component or module named {} in the library or the netlist", gate.kind),
+                        ),
+                    }
+                }
+            }
+        }
+    }
+}
+
+///信号的驱动
+#[derive(Clone, Copy)]
+enum Driver {
+    ///电路的第几个输入
+    Input(usize),
+    ///(实例, 输出)
+    Output(usize, usize),
+}
+
+///端口相对于元件原点的范围(min_x, max_x)，包括元件本身
+fn x_extent(model: &ComponentModelObject) -> (i32, i32) {
+    let xs = model.inputs.iter().chain(model.outputs.iter()).map(|p| p.position.x);
+    (xs.clone().min().unwrap_or(0).min(0), xs.max().unwrap_or(0).max(model.size[0] - 1))
+}
+
+///沿z方向的走线和电路端口的底座高度
+const RUN_Y: i32 = 1;
+///通道中沿x方向的轨道的底座高度，比沿z的走线高两格，交叉时不会连在一起
+const TRACK_Y: i32 = RUN_Y + 2;
+///相邻轨道的间距，爬升和下降的阶梯不会碰到旁边的轨道
+const TRACK_SPACING: i32 = 3;
+
+///## 布线
+/// 从from沿-z走到RUN_Y，经过hops中的每条轨道时爬到TRACK_Y再降下来，和同一个驱动的其他导线重合。
+///
+/// to为Some((目标, 轨道))时，最后一个hop是自己的轨道，沿x走到目标的列，再沿-z到达目标；
+/// 为None时经过所有hop后走到z=0
+fn route(name: String, from: Position, hops: &[i32], to: Option<Position>) -> Wire {
+    let at = |x: i32, y: i32, z: i32| Position { x, y, z };
+    let x = from.x;
+    let mut points = vec![from, at(x, RUN_Y, from.z - (from.y - RUN_Y).abs())];
+    for (k, hop) in hops.iter().enumerate() {
+        //和上一条轨道相邻时留在TRACK_Y
+        if k == 0 || hops[k - 1] - hop != TRACK_SPACING {
+            points.push(at(x, RUN_Y, hop + 2));
+        }
+        points.push(at(x, TRACK_Y, *hop));
+        let last = k + 1 == hops.len();
+        if !last && hop - hops[k + 1] != TRACK_SPACING || last && to.is_none() {
+            points.push(at(x, RUN_Y, hop - 2));
+        }
+    }
+    let end = match (to, hops.last()) {
+        (Some(to), Some(track)) => {
+            points.push(at(to.x, TRACK_Y, *track));
+            points.push(at(to.x, RUN_Y, track - 2));
+            points.push(at(to.x, RUN_Y, to.z + (to.y - RUN_Y).abs()));
+            points.push(to);
+            to
+        }
+        _ => {
+            let end = at(x, RUN_Y, 0);
+            points.push(end);
+            end
+        }
+    };
+    points.dedup();
+    //去掉水平直线中间的点
+    let mut k = 1;
+    while k + 1 < points.len() {
+        let (a, b, c) = (points[k - 1], points[k], points[k + 1]);
+        let flat = a.y == b.y && b.y == c.y;
+        if flat && (a.x == b.x && b.x == c.x || a.z == b.z && b.z == c.z) {
+            points.remove(k);
+        } else {
+            k += 1;
+        }
+    }
+    Wire {
+        name,
+        start: from,
+        end,
+        waypoints: points[1..points.len() - 1].to_vec(),
+        baseMaterial: "stone".to_string(),
+        verticalStyle: VerticalStyle::default(),
+    }
+}
+
+///## 从网表生成项目
+/// 按扩展名读取BLIF(.blif)或结构化Verilog(.v)，元件从library中按名字和端口数选择
+pub fn synthesize(netlist_path: &str, library: Option<&str>) -> Circuit {
+    let source = std::fs::read_to_string(netlist_path).unwrap_or_else(|e| {
+        error_begin();
+        panic!("failed to read netlist {}: {}", netlist_path, e);
+    });
+    let blif = netlist_path.ends_with(".blif");
+    let modules = if blif {
+        parse_blif(netlist_path, &source)
+    } else {
+        parse_verilog(netlist_path, &source)
+    };
+    //BLIF的第一个模型是顶层，Verilog取最后一个没有被实例化的模块
+    let instantiated = |m: &NetlistModule| modules.iter().any(|other| other.gates.iter().any(|g| g.kind == m.name));
+    let top = if blif {
+        modules.first()
+    } else {
+        modules.iter().rev().find(|m| !instantiated(m))
+    };
+    let Some(top) = top else {
+        synth_error(netlist_path, "no module found".to_string());
+    };
+    let lib = ComponentLibrary::load(library);
+    let mut flattener = Flattener {
+        path: netlist_path,
+        modules: &modules,
+        library: &lib,
+        instances: Vec::new(),
+        parent: HashMap::new(),
+        counts: HashMap::new(),
+    };
+    flattener.flatten(top, "", &mut vec![top.name.clone()]);
+    //合并后的信号用电路的端口名命名，输入优先
+    let ports: Vec<&String> = top.inputs.iter().chain(top.outputs.iter()).collect();
+    for (k, port) in ports.iter().enumerate() {
+        let root = flattener.find(port);
+        if &&root != port && !ports[..k].contains(&&root) {
+            flattener.parent.remove(*port);
+            flattener.parent.insert(root, port.to_string());
+        }
+    }
+    let mut instances = std::mem::take(&mut flattener.instances);
+    for instance in instances.iter_mut() {
+        for net in instance.inputs.iter_mut().chain(instance.outputs.iter_mut()).flatten() {
+            *net = flattener.find(net);
+        }
+    }
+    let input_nets: Vec<String> = top.inputs.iter().map(|p| flattener.find(p)).collect();
+    let output_nets: Vec<String> = top.outputs.iter().map(|p| flattener.find(p)).collect();
+
+    let mut drivers: HashMap<String, Driver> = HashMap::new();
+    let mut add_driver = |net: &String, driver: Driver| {
+        if drivers.insert(net.clone(), driver).is_some() {
+            synth_error(netlist_path, format!("signal {} has more than one driver", net));
+        }
+    };
+    for (i, net) in input_nets.iter().enumerate() {
+        add_driver(net, Driver::Input(i));
+    }
+    for (i, instance) in instances.iter().enumerate() {
+        for (j, net) in instance.outputs.iter().enumerate() {
+            if let Some(net) = net {
+                add_driver(net, Driver::Output(i, j));
+            }
+        }
+    }
+    //按最长路径分层，电路的输入是第0层，环上的门按先访问到的顺序断开
+    let mut levels: Vec<Option<usize>> = vec![None; instances.len()];
+    fn level_of(i: usize, instances: &[Instance], drivers: &HashMap<String, Driver>, levels: &mut [Option<usize>], visiting: &mut [bool]) -> usize {
+        if let Some(level) = levels[i] {
+            return level;
+        }
+        if visiting[i] {
+            return 0;
+        }
+        visiting[i] = true;
+        let mut level = 1;
+        for net in instances[i].inputs.iter().flatten() {
+            if let Some(Driver::Output(j, _)) = drivers.get(net) {
+                level = level.max(level_of(*j, instances, drivers, levels, visiting) + 1);
+            }
+        }
+        visiting[i] = false;
+        levels[i] = Some(level);
+        level
+    }
+    let mut visiting = vec![false; instances.len()];
+    for i in 0..instances.len() {
+        level_of(i, &instances, &drivers, &mut levels, &mut visiting);
+    }
+    let levels: Vec<usize> = levels.into_iter().map(|l| l.unwrap()).collect();
+    let max_level = levels.iter().copied().max().unwrap_or(0);
+
+    //每列的宽度，列之间空一格
+    let pitch = instances
+        .iter()
+        .map(|inst| {
+            let (min_x, max_x) = x_extent(&lib.components[inst.component]);
+            max_x - min_x + 2
+        })
+        .max()
+        .unwrap_or(2);
+    let mut order: Vec<usize> = (0..instances.len()).collect();
+    order.sort_by_key(|i| levels[*i]);
+    let column_x = |column: usize| column as i32 * pitch;
+    //实例的原点，z在分层时确定
+    let mut origins: Vec<Position> = vec![Position { x: 0, y: 0, z: 0 }; instances.len()];
+    for (k, i) in order.iter().enumerate() {
+        let (min_x, _) = x_extent(&lib.components[instances[*i].component]);
+        origins[*i].x = column_x(top.inputs.len() + k) - min_x;
+    }
+    let port_at = |driver: Driver, origins: &[Position], input_z: i32| match driver {
+        Driver::Input(i) => Position { x: column_x(i), y: RUN_Y, z: input_z },
+        Driver::Output(i, j) => {
+            origins[i] + lib.components[instances[i].component].outputs[j].position
+        }
+    };
+    //从下往上排列各层，cursor是已经占用的最大z，输出在z=0
+    let mut cursor = 0;
+    //(信号, 实例, 输入, 轨道z)
+    let mut connections: Vec<(String, usize, usize, i32)> = Vec::new();
+    for level in (1..=max_level).rev() {
+        let members: Vec<usize> = order.iter().copied().filter(|i| levels[*i] == level).collect();
+        let mut top_z = cursor;
+        for i in members.iter() {
+            let model = &lib.components[instances[*i].component];
+            //输出端口降到RUN_Y之后要离下面的轨道至少三格
+            let min_out = model.outputs.iter().map(|p| p.position.z).min().unwrap_or(0).min(0);
+            let out_dy = model.outputs.iter().map(|p| (p.position.y - RUN_Y).abs()).max().unwrap_or(0);
+            origins[*i].z = cursor + 3 + out_dy - min_out;
+            let max_in = model.inputs.iter().map(|p| p.position.z).max().unwrap_or(0);
+            top_z = top_z.max(origins[*i].z + max_in.max(model.size[2] - 1));
+        }
+        //这一层的每条输入连接占一条轨道
+        let mut pending = Vec::new();
+        let mut max_dy = 0;
+        for i in members.iter() {
+            let model = &lib.components[instances[*i].component];
+            for (j, net) in instances[*i].inputs.iter().enumerate() {
+                let Some(net) = net else { continue };
+                let Some(driver) = drivers.get(net).copied() else { continue };
+                if let Driver::Output(d, _) = driver
+                    && levels[d] >= level
+                {
+                    println!(
+                        "warning: feedback from {} to {}.{} is not routed",
+                        instances[d].name, instances[*i].name, model.inputs[j].name
+                    );
+                    continue;
+                }
+                max_dy = max_dy.max((model.inputs[j].position.y - RUN_Y).abs());
+                pending.push((net.clone(), *i, j));
+            }
+        }
+        for (t, (net, i, j)) in pending.into_iter().enumerate() {
+            let track = top_z + 3 + max_dy + TRACK_SPACING * t as i32;
+            cursor = track;
+            connections.push((net, i, j, track));
+        }
+        cursor = cursor.max(top_z);
+    }
+    let input_z = cursor + 3;
+    //每个信号经过的轨道，从上往下
+    let mut tracks: HashMap<&String, Vec<i32>> = HashMap::new();
+    for (net, _, _, track) in connections.iter() {
+        tracks.entry(net).or_default().push(*track);
+    }
+    for hops in tracks.values_mut() {
+        hops.sort_unstable_by(|a, b| b.cmp(a));
+    }
+
+    let mut circuit = Circuit {
+        name: top.name.clone(),
+        size: Position { x: 0, y: 0, z: input_z + 1 },
+        imports: Vec::new(),
+        components: Vec::new(),
+        wires: Vec::new(),
+        nets: Vec::new(),
+        blocks: Vec::new(),
+        inputs: Vec::new(),
+        outputs: Vec::new(),
+    };
+    for (i, name) in top.inputs.iter().enumerate() {
+        circuit.inputs.push(Port {
+            name: name.clone(),
+            position: port_at(Driver::Input(i), &origins, input_z),
+        });
+    }
+    for i in order.iter() {
+        let instance = &instances[*i];
+        let model = &lib.components[instance.component];
+        if !circuit.imports.iter().any(|import| import.modelName == model.name) {
+            circuit.imports.push(ImportItem {
+                modelName: model.name.clone(),
+                modelType: "component".to_string(),
+                path: lib.paths[instance.component].clone(),
+            });
+        }
+        circuit.components.push(Component {
+            name: instance.name.clone(),
+            model: model.name.clone(),
+            position: Some(origins[*i]),
+            orientation: Orientation::default(),
+            movable: false,
+        });
+        for (j, net) in instance.inputs.iter().enumerate() {
+            if net.as_ref().is_none_or(|net| !drivers.contains_key(net)) {
+                println!("warning: input {}.{} is not driven", instance.name, model.inputs[j].name);
+            }
+        }
+    }
+    //同一个信号的多条导线编号
+    let mut wire_counts: HashMap<String, usize> = HashMap::new();
+    let mut wire_name = |net: &String| {
+        let count = wire_counts.entry(net.clone()).or_insert(0);
+        *count += 1;
+        format!("{}_{}", net, count)
+    };
+    for (net, i, j, track) in connections.iter() {
+        let from = port_at(drivers[net], &origins, input_z);
+        let to = origins[*i] + lib.components[instances[*i].component].inputs[*j].position;
+        let hops: Vec<i32> = tracks[net].iter().copied().filter(|hop| hop >= track).collect();
+        circuit.wires.push(route(wire_name(net), from, &hops, Some(to)));
+    }
+    for (name, net) in top.outputs.iter().zip(output_nets.iter()) {
+        let Some(driver) = drivers.get(net).copied() else {
+            println!("warning: output {} is not driven", name);
+            continue;
+        };
+        let from = port_at(driver, &origins, input_z);
+        let hops = tracks.get(net).cloned().unwrap_or_default();
+        let wire = route(wire_name(net), from, &hops, None);
+        circuit.outputs.push(Port { name: name.clone(), position: wire.end });
+        circuit.wires.push(wire);
+    }
+    let columns = top.inputs.len() + instances.len();
+    circuit.size.x = column_x(columns.max(1)) - 1;
+    circuit.size.y = instances
+        .iter()
+        .map(|inst| lib.components[inst.component].size[1] + 1)
+        .chain(circuit.wires.iter().flat_map(|w| w.points()).map(|p| p.y + 2))
+        .max()
+        .unwrap_or(2);
+    //列和通道较多时导线很长，和--auto-repeater一样放置中继器
+    let models = load_imports(&circuit.imports, library);
+    insert_repeaters(&mut circuit, &models);
+    circuit
+}
+
+///从网表生成项目并保存到output_path
+pub fn synthesize_to_file(netlist_path: &str, output_path: &str, library: Option<&str>) {
+    let circuit = synthesize(netlist_path, library);
+    let json = serde_json::to_string_pretty(&circuit).expect("fatal: Circuit failed to_string");
+    std::fs::write(output_path, json).unwrap_or_else(|e| {
+        error_begin();
+        panic!("failed to write project json file {}: {}", output_path, e);
+    });
+    println!(
+        "synthesized {} component(s) and {} wire(s) into {}",
+        circuit.components.len(),
+        circuit.wires.len(),
+        output_path
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::generate_truth_table;
+    use crate::test_util::{gate_with_sim, test_library};
+
+    ///临时目录下只有sim表达式的两输入and、or和一输入not，返回目录
+    fn sim_library(test: &str) -> String {
+        test_library(
+            "synth",
+            test,
+            &[
+                gate_with_sim("and", 2, "input0 & input1"),
+                gate_with_sim("or", 2, "input0 | input1"),
+                gate_with_sim("not", 1, "!input0"),
+            ],
+        )
+    }
+    ///写入网表并综合
+    fn synthesize_source(library: &str, file: &str, source: &str) -> Circuit {
+        let path = format!("{}/{}", library, file);
+        std::fs::write(&path, source).unwrap();
+        synthesize(&path, Some(library))
+    }
+    ///真值表的每一行，高电平为1，低电平为0，输入和输出之间用|分开
+    fn levels(circuit: &Circuit, library: &str) -> Vec<String> {
+        let models = load_imports(&circuit.imports, Some(library));
+        let table = generate_truth_table(circuit, &models, Some(library), 1 << 10);
+        table
+            .to_csv()
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let bits: Vec<&str> = line
+                    .split(',')
+                    .map(|v| if v == "0" { "0" } else { "1" })
+                    .collect();
+                let (inputs, outputs) = bits.split_at(circuit.inputs.len());
+                inputs.concat() + "|" + &outputs.concat()
+            })
+            .collect()
+    }
+    ///按函数列出期望的真值表，第一个输入是最高位
+    fn expected(n: usize, f: impl Fn(&[bool]) -> Vec<bool>) -> Vec<String> {
+        (0..1 << n)
+            .map(|index: usize| {
+                let inputs: Vec<bool> = (0..n).map(|j| index >> (n - 1 - j) & 1 == 1).collect();
+                let bit = |b: &bool| if *b { "1" } else { "0" };
+                inputs.iter().map(bit).collect::<String>()
+                    + "|"
+                    + &f(&inputs).iter().map(bit).collect::<String>()
+            })
+            .collect()
+    }
+    ///导出网表后重新综合
+    fn round_trip(circuit: &Circuit, library: &str, format: OutputFormat, file: &str) -> Circuit {
+        let models = load_imports(&circuit.imports, Some(library));
+        let netlist = export_netlist(circuit, &models, Some(library), format);
+        synthesize_source(library, file, &netlist)
+    }
+
+    const SOP_BLIF: &str = "\
+.model sop
+.inputs a b c d
+.outputs y z
+# 3输入的积之和
+.names a b c y
+11- 1
+--1 1
+# 0的集合
+.names a b d z
+0-1 0
+10- 0
+.end
+";
+
+    #[test]
+    fn blif_sum_of_products() {
+        let library = sim_library("blif_sop");
+        let circuit = synthesize_source(&library, "sop.blif", SOP_BLIF);
+        assert_eq!(
+            levels(&circuit, &library),
+            expected(4, |v| {
+                let (a, b, c, d) = (v[0], v[1], v[2], v[3]);
+                let off = !a && d || a && !b;
+                vec![a && b || c, !off]
+            })
+        );
+        //a和b的反相器各只有一个
+        let nots = circuit.components.iter().filter(|c| c.model == "not").count();
+        assert_eq!(nots, 3);
+    }
+
+    #[test]
+    fn blif_round_trip() {
+        let library = sim_library("blif_round_trip");
+        let circuit = synthesize_source(&library, "sop.blif", SOP_BLIF);
+        let again = round_trip(&circuit, &library, OutputFormat::Blif, "sop_out.blif");
+        assert_eq!(again.inputs.len(), 4);
+        assert_eq!(again.components.len(), circuit.components.len());
+        assert_eq!(levels(&again, &library), levels(&circuit, &library));
+    }
+
+    #[test]
+    fn verilog_round_trip() {
+        let library = sim_library("verilog_round_trip");
+        let source = "
+// 3输入与门拆成两输入门的链
+module top(a, b, c, d, y, n);
+  input a, b, c, d;
+  output y, n;
+  wire t;
+  and g1(t, a, b, c);
+  or (y, t, \\d );
+  not g2(n, a);
+endmodule
+";
+        let circuit = synthesize_source(&library, "top.v", source);
+        let names: Vec<&str> = circuit.components.iter().map(|c| c.name.as_str()).collect();
+        assert!(names.contains(&"g1") && names.contains(&"g2"), "{:?}", names);
+        let expect = expected(4, |v| vec![v[0] && v[1] && v[2] || v[3], !v[0]]);
+        assert_eq!(levels(&circuit, &library), expect);
+        let again = round_trip(&circuit, &library, OutputFormat::Verilog, "top_out.v");
+        assert_eq!(levels(&again, &library), expect);
+    }
+
+    #[test]
+    #[should_panic(expected = "has 13 inputs, at most 12 are supported")]
+    fn wide_names_rejected() {
+        let library = sim_library("wide_names");
+        let inputs: Vec<String> = (0..13).map(|i| format!("i{}", i)).collect();
+        let source = format!(
+            ".model wide\n.inputs {0}\n.outputs y\n.names {0} y\n{1} 1\n.end\n",
+            inputs.join(" "),
+            "1".repeat(13)
+        );
+        synthesize_source(&library, "wide.blif", &source);
+    }
+}
@@ -0,0 +1,132 @@
+use std::cmp::max;
+use std::collections::BTreeMap;
+
+use crate::{Circuit, ComponentModelObject, sim::load_component_model};
+
+/*
+测试共用的元件和电路。
+
+元件json写在临时目录mc_circuit_script_<模块>_<测试>下，nbt不存在，只用于逻辑仿真和导出网表。
+需要方块的测试直接用仓库里的lib。
+*/
+
+pub const LIBRARY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/lib");
+
+///临时目录下写入元件json，返回目录
+pub fn test_library(module: &str, test: &str, models: &[String]) -> String {
+    let dir = std::env::temp_dir().join(format!("mc_circuit_script_{}_{}", module, test));
+    std::fs::create_dir_all(&dir).unwrap();
+    for model in models {
+        let name = serde_json::from_str::<ComponentModelObject>(model)
+            .unwrap()
+            .name;
+        std::fs::write(dir.join(format!("{}.json", name)), model).unwrap();
+    }
+    dir.to_string_lossy().to_string()
+}
+
+///一行排开的元件，第i个输入在[i,0,1]，第i个输出在[i,0,-1]。
+/// 只有一个输出时叫output，否则叫output0、output1……
+pub fn gate(name: &str, inputs: usize, outputs: usize) -> String {
+    let ports = |names: Vec<String>, z: i32| {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| format!(r#"{{"name":"{}","position":[{},0,{}]}}"#, n, i, z))
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    let output_names = if outputs == 1 {
+        vec!["output".to_string()]
+    } else {
+        (0..outputs).map(|i| format!("output{}", i)).collect()
+    };
+    format!(
+        r#"{{"nbt":"nbt/{0}.nbt","name":"{0}","modelType":"component","size":[{1},1,1],
+        "inputs":[{2}],"outputs":[{3}]}}"#,
+        name,
+        max(inputs, outputs),
+        ports((0..inputs).map(|i| format!("input{}", i)).collect(), 1),
+        ports(output_names, -1),
+    )
+}
+
+///和gate一样，唯一的输出由sim表达式计算
+pub fn gate_with_sim(name: &str, inputs: usize, sim: &str) -> String {
+    let mut model: ComponentModelObject = serde_json::from_str(&gate(name, inputs, 1)).unwrap();
+    model.sim = Some(BTreeMap::from([("output".to_string(), sim.to_string())]));
+    serde_json::to_string(&model).unwrap()
+}
+
+///lib/and.json换成另一个sim，写到临时目录，返回json路径。nbt仍然从lib读取
+pub fn library_and_with_sim(module: &str, test: &str, sim: &str) -> String {
+    let mut model = load_component_model(&format!("{}/and.json", LIBRARY));
+    model.sim = Some(BTreeMap::from([("output".to_string(), sim.to_string())]));
+    let library = test_library(module, test, &[serde_json::to_string(&model).unwrap()]);
+    format!("{}/and.json", library)
+}
+
+///只有一个元件g的电路，电路的输入输出和元件的端口依次相连
+pub fn single_gate(model: &str, inputs: &[&str], outputs: &[&str]) -> Circuit {
+    let ports = |names: &[&str], z: i32| {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| {
+                format!(
+                    r#"{{"name":"{}","position":{{"x":{},"y":0,"z":{}}}}}"#,
+                    n, i, z
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    serde_json::from_str(&format!(
+        r#"{{"name":"test","size":{{"x":{0},"y":1,"z":3}},
+        "imports":[{{"modelName":"{1}","modelType":"component","path":"{1}.json"}}],
+        "components":[{{"name":"g","model":"{1}","position":{{"x":0,"y":0,"z":1}}}}],
+        "wires":[],"blocks":[],"inputs":[{2}],"outputs":[{3}]}}"#,
+        max(inputs.len(), outputs.len()),
+        model,
+        ports(inputs, 2),
+        ports(outputs, 0)
+    ))
+    .unwrap()
+}
+
+///三层嵌套的电路，元件都在原点：top.json的元件outer是middle.json，middle.json的元件inner是half.json，
+/// half.json里是lib的与门and001。每层的输入a、b和输出y都在与门端口的位置。
+/// 与门的json和nbt复制到临时目录，返回目录
+pub fn nested_library(module: &str, test: &str) -> String {
+    let library = test_library(module, test, &[]);
+    std::fs::create_dir_all(format!("{}/nbt", library)).unwrap();
+    for file in ["and.json", "nbt/and.nbt"] {
+        std::fs::copy(
+            format!("{}/{}", LIBRARY, file),
+            format!("{}/{}", library, file),
+        )
+        .unwrap();
+    }
+    for (name, component, model, path, model_type) in [
+        ("half", "and001", "and", "and.json", "component"),
+        ("middle", "inner", "half", "half.json", "circuit"),
+        ("top", "outer", "middle", "middle.json", "circuit"),
+    ] {
+        let circuit = format!(
+            r#"{{"name":"{0}","size":{{"x":4,"y":4,"z":5}},
+            "imports":[{{"modelName":"{2}","modelType":"{4}","path":"{3}"}}],
+            "components":[{{"name":"{1}","model":"{2}","position":{{"x":0,"y":0,"z":0}}}}],
+            "wires":[],"blocks":[],
+            "inputs":[{{"name":"a","position":{{"x":0,"y":2,"z":4}}}},{{"name":"b","position":{{"x":2,"y":2,"z":4}}}}],
+            "outputs":[{{"name":"y","position":{{"x":3,"y":1,"z":0}}}}]}}"#,
+            name, component, model, path, model_type
+        );
+        std::fs::write(format!("{}/{}.json", library, name), circuit).unwrap();
+    }
+    library
+}
+
+///读取电路json
+pub fn load_circuit(path: &str) -> Circuit {
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
@@ -0,0 +1,137 @@
+///## VcdOption
+/// 逐刻仿真时输出VCD波形文件的选项
+pub struct VcdOption {
+    pub path: String,
+    ///是否包含所有内部能量点(component.port、wire.start等)，否则只有电路的输入输出
+    pub internal: bool,
+}
+
+///一个游戏刻的时长(毫秒)，VCD的时间单位是1ms
+const TICK_MS: u64 = 50;
+
+///VCD中信号的短标识符，由可打印字符 ! 到 ~ 组成
+fn identifier(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            break;
+        }
+        index -= 1;
+    }
+    id
+}
+
+///VCD中的名字不能有空白
+fn vcd_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<&str>>().join("_")
+}
+
+///生成Value Change Dump文件内容。
+///
+/// 每个信号是4位的红石能量，signals中的值是每一游戏刻结束时的能量。
+/// 名字里有"."的信号(component.port、wire.start)放在以元件/导线名命名的子scope里，
+/// 子电路展开后的名字按"."逐层嵌套。
+pub fn generate_vcd(module: &str, signals: &[(String, Vec<i32>)], ticks: u64) -> String {
+    let mut vcd = String::new();
+    vcd += "$version mc_circuit_script $end\n";
+    vcd += &format!("$comment 1 game tick = {} ms $end\n", TICK_MS);
+    vcd += "$timescale 1 ms $end\n";
+    vcd += &format!("$scope module {} $end\n", vcd_name(module));
+    //先声明顶层的信号，再按元件/导线分组，子电路里的名字(adder1.ha0.and001.output)逐层嵌套
+    let mut vars: Vec<(Vec<&str>, usize, &str)> = signals
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| {
+            let mut path: Vec<&str> = name.split('.').collect();
+            let port = path.pop().unwrap();
+            (path, i, port)
+        })
+        .collect();
+    vars.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+    let mut open: Vec<&str> = Vec::new();
+    for (path, i, port) in vars.iter() {
+        let common = open.iter().zip(path.iter()).take_while(|(a, b)| a == b).count();
+        for _ in common..open.len() {
+            vcd += "$upscope $end\n";
+        }
+        open.truncate(common);
+        for scope in path[common..].iter() {
+            vcd += &format!("$scope module {} $end\n", vcd_name(scope));
+            open.push(scope);
+        }
+        vcd += &format!("$var wire 4 {} {} $end\n", identifier(*i), vcd_name(port));
+    }
+    for _ in 0..open.len() {
+        vcd += "$upscope $end\n";
+    }
+    vcd += "$upscope $end\n$enddefinitions $end\n";
+    //只记录变化
+    for tick in 0..ticks as usize {
+        let changes = signals
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, values))| tick == 0 || values[tick] != values[tick - 1])
+            .map(|(i, (_, values))| format!("b{:04b} {}\n", values[tick].clamp(0, 15), identifier(i)))
+            .collect::<String>();
+        if tick == 0 {
+            vcd += &format!("#0\n$dumpvars\n{}$end\n", changes);
+        } else if !changes.is_empty() {
+            vcd += &format!("#{}\n{}", tick as u64 * TICK_MS, changes);
+        }
+    }
+    vcd += &format!("#{}\n", ticks * TICK_MS);
+    vcd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers_are_printable() {
+        assert_eq!(identifier(0), "!");
+        assert_eq!(identifier(93), "~");
+        assert_eq!(identifier(94), "!!");
+        assert_eq!(identifier(95), "\"!");
+    }
+
+    #[test]
+    fn scopes_and_value_changes() {
+        let signals = vec![
+            ("a".to_string(), vec![0, 15, 15]),
+            ("g.output".to_string(), vec![15, 15, 0]),
+            ("sub.g.output".to_string(), vec![0, 0, 0]),
+        ];
+        let expected = "\
+$version mc_circuit_script $end
+$comment 1 game tick = 50 ms $end
+$timescale 1 ms $end
+$scope module full_adder $end
+$var wire 4 ! a $end
+$scope module g $end
+$var wire 4 \" output $end
+$upscope $end
+$scope module sub $end
+$scope module g $end
+$var wire 4 # output $end
+$upscope $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+b0000 !
+b1111 \"
+b0000 #
+$end
+#50
+b1111 !
+#100
+b0000 \"
+#150
+";
+        assert_eq!(generate_vcd("full adder", &signals, 3), expected);
+    }
+}
@@ -1,4 +1,402 @@
-use crate::Circuit;
+use crate::*;
 
 ///使用
 pub fn wiring(circuit: Circuit) {}
+
+///## VerticalStyle
+/// 导线纯竖直段(只有Y变化)的走线方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VerticalStyle {
+    ///不允许纯竖直段，升降必须伴随水平移动，生成底座+红石线的阶梯
+    #[default]
+    Staircase,
+    ///玻璃和上半砖交替的塔，只能向上传递信号
+    Tower,
+}
+
+///## WireCell
+/// 导线上的一格：底座方块的位置和材质，红石线放在底座上方
+#[derive(Debug, Clone)]
+pub struct WireCell {
+    pub position: Position,
+    pub base: String,
+}
+
+///方块是否在导线的这一格上。
+///
+/// 中继器应该放在红石线的位置(底座上方)，也兼容放在底座位置的写法。
+pub fn on_wire_cell(block_position: Position, cell: Position) -> bool {
+    block_position == cell || block_position == cell + Position { x: 0, y: 1, z: 0 }
+}
+
+///塔的底座材质
+const TOWER_BASES: [&str; 2] = ["glass", "smooth_stone_slab[type=top]"];
+
+impl Wire {
+    ///导线的所有拐点，包括首尾
+    pub fn points(&self) -> Vec<Position> {
+        let mut points = vec![self.start];
+        points.extend(self.waypoints.iter().copied());
+        points.push(self.end);
+        points
+    }
+    ///展开导线的每一格，相邻两格的底座在水平方向相邻，或者是阶梯的一级
+    pub fn cells(&self) -> Vec<WireCell> {
+        let points = self.points();
+        let mut cells = vec![WireCell {
+            position: self.start,
+            base: self.baseMaterial.clone(),
+        }];
+        for i in 1..points.len() {
+            let (from, to) = (points[i - 1], points[i]);
+            let (dx, dy, dz) = (to.x - from.x, to.y - from.y, to.z - from.z);
+            if dx != 0 && dz != 0 {
+                error_begin();
+                panic!(
+                    "segment {} -> {} of wire {} is diagonal, add a waypoint to turn the corner",
+                    from, to, self.name
+                );
+            }
+            if dx == 0 && dz == 0 && dy != 0 {
+                //纯竖直段
+                let prev = if i >= 2 { Some(points[i - 2]) } else { None };
+                let next = points.get(i + 1).copied();
+                cells.extend(self.tower_cells(from, to, prev, next));
+                continue;
+            }
+            //水平段或者阶梯，每一步水平前进一格，Y方向同时升降直到到达终点高度
+            let steps = dx.abs().max(dz.abs());
+            if dy.abs() > steps {
+                error_begin();
+                panic!(
+                    "segment {} -> {} of wire {} climbs {} blocks over {} blocks, a staircase needs at least as many horizontal steps",
+                    from,
+                    to,
+                    self.name,
+                    dy.abs(),
+                    steps
+                );
+            }
+            let mut pos = from;
+            for _ in 0..steps {
+                pos = pos
+                    + Position {
+                        x: dx.signum(),
+                        y: if pos.y != to.y { dy.signum() } else { 0 },
+                        z: dz.signum(),
+                    };
+                cells.push(WireCell {
+                    position: pos,
+                    base: self.baseMaterial.clone(),
+                });
+            }
+        }
+        cells
+    }
+    ///导线经过的所有底座位置
+    pub fn path(&self) -> Vec<Position> {
+        self.cells().iter().map(|c| c.position).collect()
+    }
+    ///导线长度，即红石信号经过的格数
+    pub fn length(&self) -> i32 {
+        self.cells().len() as i32 - 1
+    }
+    ///导线上每一格的位置，以及信号从start向end前进时在这一格的水平方向
+    pub fn headings(&self) -> Vec<(Position, GlobalDirection)> {
+        let path = self.path();
+        path.iter()
+            .enumerate()
+            .map(|(i, pos)| {
+                //首格用后一格判断方向，其余用前一格
+                let (from, to) = if i == 0 {
+                    (path[0], path.get(1).copied().unwrap_or(path[0]))
+                } else {
+                    (path[i - 1], path[i])
+                };
+                let direction = if from.x < to.x {
+                    GlobalDirection::East
+                } else if from.x > to.x {
+                    GlobalDirection::West
+                } else if from.z < to.z {
+                    GlobalDirection::South
+                } else {
+                    GlobalDirection::North
+                };
+                (*pos, direction)
+            })
+            .collect()
+    }
+    ///考虑中继器后的有效长度：信号从最后一个中继器(没有中继器时从start)到end经过的格数
+    pub fn effective_length(&self, blocks: &[BlockInfo]) -> i32 {
+        let path = self.path();
+        let last_repeater = path.iter().rposition(|pos| {
+            blocks
+                .iter()
+                .any(|b| b.id.contains("repeater") && on_wire_cell(b.position, *pos))
+        });
+        match last_repeater {
+            Some(i) => (path.len() - 1 - i) as i32,
+            None => self.length(),
+        }
+    }
+    ///导线上所有中继器的延迟之和(红石刻)，中继器缺省delay为1
+    pub fn repeater_delay(&self, blocks: &[BlockInfo]) -> i32 {
+        self.path()
+            .iter()
+            .filter_map(|pos| {
+                blocks
+                    .iter()
+                    .find(|b| b.id.contains("repeater") && on_wire_cell(b.position, *pos))
+            })
+            .map(|b| {
+                //delay可以写在id里，也可以写在properties里
+                let mut block = Block::from_id(&b.id).unwrap_or_else(|_| Block::air());
+                if let Some(properties) = &b.properties {
+                    properties.apply_to(&mut block);
+                }
+                block
+                    .attributes
+                    .get("delay")
+                    .and_then(|d| d.parse::<i32>().ok())
+                    .unwrap_or(1)
+            })
+            .sum()
+    }
+    ///纯竖直段的塔，在本列和相邻的一列之间交替上升
+    ///
+    /// 相邻列不能在上一段和下一段的方向上，否则会覆盖它们的红石线
+    fn tower_cells(
+        &self,
+        from: Position,
+        to: Position,
+        prev: Option<Position>,
+        next: Option<Position>,
+    ) -> Vec<WireCell> {
+        let dy = to.y - from.y;
+        if self.verticalStyle != VerticalStyle::Tower {
+            error_begin();
+            panic!(
+                "segment {} -> {} of wire {} is vertical, set \"verticalStyle\":\"tower\" or add waypoints for a staircase",
+                from, to, self.name
+            );
+        }
+        if dy < 0 {
+            error_begin();
+            panic!(
+                "segment {} -> {} of wire {} goes down, but towers only carry signals upward",
+                from, to, self.name
+            );
+        }
+        if dy % 2 != 0 {
+            error_begin();
+            panic!(
+                "tower segment {} -> {} of wire {} must climb an even number of blocks",
+                from, to, self.name
+            );
+        }
+        //水平方向上从p指向q的单位向量
+        let heading = |p: Position, q: Position| Position {
+            x: (q.x - p.x).signum(),
+            y: 0,
+            z: if q.x != p.x { 0 } else { (q.z - p.z).signum() },
+        };
+        let blocked: Vec<Position> = [prev.map(|p| heading(from, p)), next.map(|n| heading(to, n))]
+            .into_iter()
+            .flatten()
+            .collect();
+        //优先伸向下一段的反方向
+        let mut candidates = vec![];
+        if let Some(next) = next {
+            let n = heading(to, next);
+            candidates.push(Position { x: -n.x, y: 0, z: -n.z });
+        }
+        candidates.extend([
+            Position { x: 1, y: 0, z: 0 },
+            Position { x: 0, y: 0, z: 1 },
+            Position { x: -1, y: 0, z: 0 },
+            Position { x: 0, y: 0, z: -1 },
+        ]);
+        let side = candidates
+            .into_iter()
+            .find(|c| !blocked.contains(c))
+            .unwrap();
+        (1..=dy)
+            .map(|k| WireCell {
+                position: from
+                    + Position {
+                        x: if k % 2 == 1 { side.x } else { 0 },
+                        y: k,
+                        z: if k % 2 == 1 { side.z } else { 0 },
+                    },
+                base: TOWER_BASES[(k % 2) as usize].to_string(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(x: i32, y: i32, z: i32) -> Position {
+        Position { x, y, z }
+    }
+    fn wire(points: &[Position], style: &str) -> Wire {
+        let json = |p: &Position| format!(r#"{{"x":{},"y":{},"z":{}}}"#, p.x, p.y, p.z);
+        let waypoints = points[1..points.len() - 1]
+            .iter()
+            .map(json)
+            .collect::<Vec<String>>()
+            .join(",");
+        serde_json::from_str(&format!(
+            r#"{{"name":"w","start":{},"end":{},"waypoints":[{}],"baseMaterial":"stone","verticalStyle":"{}"}}"#,
+            json(&points[0]),
+            json(points.last().unwrap()),
+            waypoints,
+            style
+        ))
+        .unwrap()
+    }
+    fn blocks(json: &str) -> Vec<BlockInfo> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn straight_wire() {
+        let w = wire(&[pos(0, 0, 0), pos(3, 0, 0)], "staircase");
+        assert_eq!(
+            w.path(),
+            [pos(0, 0, 0), pos(1, 0, 0), pos(2, 0, 0), pos(3, 0, 0)]
+        );
+        assert_eq!(w.length(), 3);
+        assert!(w.cells().iter().all(|c| c.base == "stone"));
+    }
+
+    #[test]
+    fn waypoints_turn_corners() {
+        use GlobalDirection::*;
+        let w = wire(&[pos(0, 0, 0), pos(2, 0, 0), pos(2, 0, 2)], "staircase");
+        assert_eq!(
+            w.path(),
+            [
+                pos(0, 0, 0),
+                pos(1, 0, 0),
+                pos(2, 0, 0),
+                pos(2, 0, 1),
+                pos(2, 0, 2)
+            ]
+        );
+        let headings: Vec<GlobalDirection> = w.headings().iter().map(|(_, d)| *d).collect();
+        assert_eq!(headings, [East, East, East, South, South]);
+    }
+
+    #[test]
+    fn reversed_wire_runs_backwards() {
+        let forward = wire(&[pos(0, 0, 0), pos(0, 0, 2), pos(2, 0, 2)], "staircase");
+        let backward = wire(&[pos(2, 0, 2), pos(0, 0, 2), pos(0, 0, 0)], "staircase");
+        let mut path = forward.path();
+        path.reverse();
+        assert_eq!(backward.path(), path);
+        assert!(
+            backward
+                .headings()
+                .iter()
+                .skip(1)
+                .all(|(_, d)| matches!(d, GlobalDirection::West | GlobalDirection::North))
+        );
+    }
+
+    #[test]
+    fn staircase_climbs_one_block_per_step() {
+        let up = wire(&[pos(0, 0, 0), pos(3, 2, 0)], "staircase");
+        assert_eq!(
+            up.path(),
+            [pos(0, 0, 0), pos(1, 1, 0), pos(2, 2, 0), pos(3, 2, 0)]
+        );
+        let down = wire(&[pos(0, 2, 0), pos(0, 0, -3)], "staircase");
+        assert_eq!(
+            down.path(),
+            [pos(0, 2, 0), pos(0, 1, -1), pos(0, 0, -2), pos(0, 0, -3)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "climbs 3 blocks over 2 blocks")]
+    fn steep_staircase_rejected() {
+        wire(&[pos(0, 0, 0), pos(2, 3, 0)], "staircase").cells();
+    }
+
+    #[test]
+    #[should_panic(expected = "is diagonal")]
+    fn diagonal_segment_rejected() {
+        wire(&[pos(0, 0, 0), pos(2, 0, 2)], "staircase").cells();
+    }
+
+    #[test]
+    #[should_panic(expected = "is vertical")]
+    fn vertical_segment_needs_a_tower() {
+        wire(&[pos(0, 0, 0), pos(0, 2, 0)], "staircase").cells();
+    }
+
+    #[test]
+    fn tower_alternates_glass_and_slabs_beside_the_wire() {
+        let w = wire(
+            &[pos(0, 0, 0), pos(2, 0, 0), pos(2, 4, 0), pos(4, 4, 0)],
+            "tower",
+        );
+        let cells = w.cells();
+        let cells: Vec<(Position, &str)> = cells
+            .iter()
+            .map(|c| (c.position, c.base.as_str()))
+            .collect();
+        //前后两段在东西方向，塔伸向南边
+        let slab = "smooth_stone_slab[type=top]";
+        assert_eq!(
+            cells,
+            [
+                (pos(0, 0, 0), "stone"),
+                (pos(1, 0, 0), "stone"),
+                (pos(2, 0, 0), "stone"),
+                (pos(2, 1, 1), slab),
+                (pos(2, 2, 0), "glass"),
+                (pos(2, 3, 1), slab),
+                (pos(2, 4, 0), "glass"),
+                (pos(3, 4, 0), "stone"),
+                (pos(4, 4, 0), "stone"),
+            ]
+        );
+        //只有一段时伸向东边
+        let alone = wire(&[pos(0, 0, 0), pos(0, 2, 0)], "tower");
+        assert_eq!(alone.path(), [pos(0, 0, 0), pos(1, 1, 0), pos(0, 2, 0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "towers only carry signals upward")]
+    fn tower_going_down_rejected() {
+        wire(&[pos(0, 2, 0), pos(0, 0, 0)], "tower").cells();
+    }
+
+    #[test]
+    #[should_panic(expected = "must climb an even number of blocks")]
+    fn odd_tower_rejected() {
+        wire(&[pos(0, 0, 0), pos(0, 3, 0)], "tower").cells();
+    }
+
+    #[test]
+    fn repeater_delay_sums_repeaters_on_the_wire() {
+        let w = wire(&[pos(0, 0, 0), pos(6, 0, 0)], "staircase");
+        assert_eq!(w.repeater_delay(&[]), 0);
+        let on_wire = blocks(
+            r#"[{"position":{"x":1,"y":1,"z":0},"id":"repeater","properties":{"facing":"west","delay":3}},
+            {"position":{"x":3,"y":0,"z":0},"id":"repeater[delay=2]"},
+            {"position":{"x":4,"y":1,"z":0},"id":"repeater"},
+            {"position":{"x":5,"y":1,"z":1},"id":"repeater","properties":{"delay":4}},
+            {"position":{"x":2,"y":1,"z":0},"id":"stone"}]"#,
+        );
+        //旁边的中继器不算，缺省delay为1
+        assert_eq!(w.repeater_delay(&on_wire), 3 + 2 + 1);
+        //最后一个中继器在x=4
+        assert_eq!(w.effective_length(&on_wire), 2);
+    }
+}